@@ -0,0 +1,45 @@
+// where posts get the time from: the system clock normally, a clock that only moves when told to wherever the timing has to be exact, like when checking that a scheduled post is published when it's due
+
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Where a post gets the time from.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The time of the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that stands still until it's set or advanced.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<SystemTime>,
+}
+
+impl ManualClock {
+    pub fn new(now: SystemTime) -> ManualClock {
+        ManualClock { now: Mutex::new(now) }
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().expect("The clock is in a poisoned state.") = now;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().expect("The clock is in a poisoned state.") += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().expect("The clock is in a poisoned state.")
+    }
+}
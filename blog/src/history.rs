@@ -0,0 +1,75 @@
+// the audit trail of a post: every change of its state, who made it, when and why
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::storage::{self, Record};
+
+/// A single change of the state of a post, or of the time it's scheduled at, then `from` and `to` are the same. The history of a post is only ever appended to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    pub from: String,
+    pub to: String,
+    pub actor: String,
+    pub at: SystemTime,
+    /// Why the transition happened, if anyone said, e.g. the reason a post has been rejected for.
+    pub comment: Option<String>,
+}
+
+impl Transition {
+    pub(crate) fn new(from: &str, to: &str, actor: &str, at: SystemTime, comment: Option<&str>) -> Transition {
+        Transition {
+            from: from.to_string(),
+            to: to.to_string(),
+            actor: actor.to_string(),
+            at,
+            comment: comment.map(String::from),
+        }
+    }
+}
+
+// every transition is a `transition` field of its own, e.g. `transition: pending-review; draft; bob; 1760860000.123456789; Too short`; the time is in seconds since the Unix epoch, to the nanosecond, so that it's restored exactly
+pub(crate) fn save_history(record: &mut Record, history: &[Transition]) {
+    for transition in history {
+        let at = format_time(transition.at);
+
+        let mut parts = vec![transition.from.as_str(), &transition.to, &transition.actor, &at];
+        parts.extend(transition.comment.as_deref());
+
+        record.add("transition", storage::join(&parts));
+    }
+}
+
+pub(crate) fn load_history(record: &Record) -> Result<Vec<Transition>, String> {
+    record
+        .get_all("transition")
+        .map(|value| {
+            let invalid = || format!("Invalid transition '{}': expected 'FROM; TO; ACTOR; SECONDS.NANOSECONDS' and an optional '; COMMENT'.", value);
+
+            match storage::split(value).as_slice() {
+                [from, to, actor, at, comment @ ..] if comment.len() <= 1 => {
+                    let at = parse_time(at).ok_or_else(invalid)?;
+
+                    Ok(Transition::new(from, to, actor, at, comment.first().map(String::as_str)))
+                }
+                _ => Err(invalid()),
+            }
+        })
+        .collect()
+}
+
+// the time in the storage, wherever it appears
+pub(crate) fn format_time(at: SystemTime) -> String {
+    let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    format!("{}.{:09}", since_epoch.as_secs(), since_epoch.subsec_nanos())
+}
+
+pub(crate) fn parse_time(value: &str) -> Option<SystemTime> {
+    let (seconds, nanoseconds) = value.split_once('.')?;
+
+    if nanoseconds.len() != 9 {
+        return None;
+    }
+
+    UNIX_EPOCH.checked_add(Duration::new(seconds.parse().ok()?, nanoseconds.parse().ok().filter(|&nanoseconds| nanoseconds < 1_000_000_000)?))
+}
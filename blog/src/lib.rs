@@ -1,140 +1,384 @@
+pub mod clock;
+pub mod history;
+pub mod review;
+pub mod storage;
+pub mod workflow;
+
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use clock::{Clock, SystemClock};
+use history::Transition;
+use review::Review;
+use storage::{Persist, Record};
+use workflow::{Who, Workflow};
+
+// unlike the book's version, the states of `Post` and the rules of moving between them are not `State` trait objects but data, a `Workflow`, so that they can be changed without changing the code; `Workflow::standard` is the Draft -> Pending review -> Published pipeline the book's version hard-codes
 pub struct Post {
-    state: Option<Box<dyn State>>,
+    workflow: Arc<Workflow>,
+    state: String,
+    approvers: Vec<String>, // who has performed the transition from `state` that takes several approvals so far, see `Workflow::guarded`
+    scheduled_at: Option<SystemTime>,
+    clock: Arc<dyn Clock>,
     content: String,
+    trail: Trail,
+}
+
+// what both implementations remember about a post besides its content and its state
+#[derive(Debug)]
+struct Trail {
+    author: String,
+    reviews: Vec<Review>, // every review the post has had, in all the rounds of review
+    history: Vec<Transition>,
 }
 
 impl Post {
-    pub fn new() -> Post {
+    /// Creates a post that follows the standard workflow.
+    pub fn new(author: &str) -> Post {
+        Post::with_workflow(author, Workflow::standard())
+    }
+
+    /// Creates a post that follows `workflow`, e.g. one loaded with `Workflow::load`.
+    pub fn with_workflow(author: &str, workflow: Arc<Workflow>) -> Post {
         Post {
-            state: Some(Box::new(Draft {})),
+            state: workflow.initial().to_string(),
+            workflow,
+            approvers: Vec::new(),
+            scheduled_at: None,
+            clock: Arc::new(SystemClock),
             content: String::new(),
+            trail: Trail::new(author),
         }
     }
 
+    /// Makes the post take the time from `clock` rather than from the system: when it's due to be published and when its transitions happen.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Post {
+        self.clock = clock;
+
+        self
+    }
+
+    /// Sets the time the post is to be published at on behalf of `actor`, `None` meaning as soon as it's approved. A post that is waiting for the time already is published at the new one. The history gets the change as a transition from the current state to itself, with the new time as the comment.
+    ///
+    /// # Errors
+    ///
+    /// Fails, changing nothing, if `actor` is not the author.
+    pub fn schedule(&mut self, actor: &str, at: Option<SystemTime>) -> Result<(), String> {
+        self.tick();
+        Who::Author.check(&self.trail.author, actor, "schedule")?;
+        self.scheduled_at = at;
+
+        let comment = at.map_or_else(|| String::from("Unscheduled"), |at| format!("Scheduled at {}", history::format_time(at)));
+        self.trail.history.push(Transition::new(&self.state, &self.state, actor, self.clock.now(), Some(&comment)));
+
+        Ok(())
+    }
+
+    pub fn scheduled_at(&self) -> Option<SystemTime> {
+        self.scheduled_at
+    }
+
+    /// Adds text on behalf of the author; nothing happens if the workflow doesn't let them edit the post in its current state, see `edit`.
     pub fn add_text(&mut self, text: &str) {
-        self.state.as_ref().unwrap().add_text(&mut self.content, text);
+        let author = self.trail.author.clone();
+
+        let _ = self.edit(&author, text);
     }
 
-    pub fn content(&self) -> &str {
-        self.state.as_ref().unwrap().content(&self.content) // the goal is to keep all the rules defining what content return inside the structs that implement `State`, so we call `content` method on the value in `state` and pass `content` member of `Post`; we call `as_ref` method on the `Option<T>` because we want a reference to the value inside rather than ownership of the value; because `state` is an `Option<Box<dyn State>>`, when we call `as_ref`, an `Option<&Box<dyn State>>` is returned (if we didn’t call `as_ref`, we would get an error because we can’t move `state` out of the borrowed `&self` of the function parameter
+    /// Adds text on behalf of `editor`.
+    ///
+    /// # Errors
+    ///
+    /// Fails, changing nothing, if the workflow doesn't let `editor` edit the post in its current state.
+    pub fn edit(&mut self, editor: &str, text: &str) -> Result<(), String> {
+        self.tick();
+        self.rule().edit.check(&self.trail.author, editor, "edit")?;
+        self.content.push_str(text);
+
+        Ok(())
     }
 
-    pub fn request_review(&mut self) {
-        // To consume the old state we call `take` method to take the `Some` value out of `state` field and leave a `None` in its place, because Rust doesn’t let us have unpopulated fields in structs. This lets us move the state value out of `Post` (rather than borrowing it) into `State::request_review`.
-        if let Some(s) = self.state.take() {
-            self.state = Some(s.request_review())
+    /// Returns the content if the current state makes it visible, an empty string otherwise.
+    pub fn content(&self) -> &str {
+        if self.rule().visible {
+            &self.content
+        } else {
+            ""
         }
     }
 
-    pub fn approve(&mut self) {
-        if let Some(s) = self.state.take() {
-            self.state = Some(s.approve())
-        }
+    /// Returns the name of the current state, as the workflow calls it. A post that is due to move on by the clock is in the state it moves to already, even if `tick` hasn't been called since.
+    pub fn state(&self) -> &str {
+        self.due().map_or(&self.state, |transition| &transition.to)
     }
 
-    pub fn reject(&mut self) {
-        if let Some(s) = self.state.take() {
-            self.state = Some(s.reject())
+    /// Performs the transition the clock performs, if the post is due for it, e.g. publishes a post scheduled for a time that has passed. The history gets the time the post was due at, not the one of the call. Every method that changes the post calls this first, so it's only needed to have the history up to date.
+    pub fn tick(&mut self) {
+        if let Some(transition) = self.due() {
+            let to = transition.to.clone();
+            let at = self.scheduled_at.take().unwrap_or_else(|| self.clock.now());
+            let from = std::mem::replace(&mut self.state, to);
+            self.approvers.clear();
+
+            self.trail.record(&from, &self.state, "clock", at, None);
         }
     }
-}
 
-trait State {
-    fn add_text(&self, _content: &mut String, _text: &str) {}
+    pub fn workflow(&self) -> &Workflow {
+        &self.workflow
+    }
 
-    // default implementations of these methods would need to return `self` breaking the trait object safety requirements, thus there's no other choice than duplicating them in the implementation blocks of the particular states
-    fn request_review(self: Box<Self>) -> Box<dyn State>; // note that rather than having `self`, `&self`, or `&mut self` as the first parameter of the method, we have `self: Box<Self>`; this syntax means the method is only valid when called on a `Box` holding the type; this syntax takes ownership of `Box<Self>`, invalidating the old state so the state value of the `Post` can transform into a new state
-    fn approve(self: Box<Self>) -> Box<dyn State>;
-    fn reject(self: Box<Self>) -> Box<dyn State>;
+    pub fn author(&self) -> &str {
+        &self.trail.author
+    }
 
-    fn content<'a>(&self, _content: &'a String) -> &'a str {
-        ""
+    pub fn reviews(&self) -> &[Review] {
+        &self.trail.reviews
     }
-}
 
-struct Draft {}
+    /// Returns every change of the state of the post, and of the time it's scheduled at, oldest first.
+    pub fn history(&self) -> &[Transition] {
+        &self.trail.history
+    }
 
-impl State for Draft {
-    fn add_text(&self, content: &mut String, text: &str) {
-        content.push_str(text);
+    /// Sends the post for review, i.e. performs `request-review` on behalf of the author; nothing happens if the workflow doesn't allow it.
+    pub fn request_review(&mut self) {
+        let author = self.trail.author.clone();
+
+        let _ = self.act("request-review", &author, None);
     }
 
-    fn request_review(self: Box<Self>) -> Box<dyn State> {
-        Box::new(PendingReview { approvals: 0 })
+    /// Records the approval of `reviewer`, i.e. performs `approve` on their behalf; in the standard workflow the post is published once two different reviewers have approved it.
+    ///
+    /// # Errors
+    ///
+    /// Fails, changing nothing, if the workflow doesn't allow `reviewer` to approve the post, e.g. if it isn't pending review, if `reviewer` is its author or if `reviewer` has approved it already.
+    pub fn approve(&mut self, reviewer: &str) -> Result<(), String> {
+        self.act("approve", reviewer, None)
     }
 
-    fn approve(self: Box<Self>) -> Box<dyn State> {
-        self
+    /// Records the rejection of `reviewer`, i.e. performs `reject` on their behalf, which in the standard workflow sends the post back to the draft, dropping the approvals it has got so far. `reason` goes to the history as well.
+    ///
+    /// # Errors
+    ///
+    /// Fails, changing nothing, if the workflow doesn't allow `reviewer` to reject the post.
+    pub fn reject(&mut self, reviewer: &str, reason: &str) -> Result<(), String> {
+        self.act("reject", reviewer, Some(reason))
     }
 
-    fn reject(self: Box<Self>) -> Box<dyn State> {
-        self
+    /// Performs `action` on behalf of `actor`, moving the post to the state the workflow says, unless the transition takes more approvals than it has got with this one. `comment` goes to the history. An `approve` or a `reject` goes to the reviews as well, `comment` being the reason of the rejection, so it makes no difference whether they are performed by this or by `approve` and `reject`.
+    ///
+    /// # Errors
+    ///
+    /// Fails, changing nothing, if the workflow has no such transition from the current state, if it doesn't let `actor` perform it or if it takes several approvals and `actor` has given theirs already.
+    pub fn act(&mut self, action: &str, actor: &str, comment: Option<&str>) -> Result<(), String> {
+        self.tick();
+
+        let transition = self.workflow.transition(&self.state, action).ok_or_else(|| format!("Cannot {} a post in the state '{}'.", action, self.state))?;
+        transition.by.check(&self.trail.author, actor, action)?;
+
+        if transition.approvals > 1 && self.approvers.iter().any(|approver| approver == actor) {
+            return Err(format!("{} has {} the post already.", actor, past_tense(action)));
+        }
+
+        // an approval counts as a review even if it isn't the last one the transition takes
+        match action {
+            "approve" => self.trail.reviews.push(Review::approved(actor)),
+            "reject" => self.trail.reviews.push(Review::rejected(actor, comment.unwrap_or_default())),
+            _ => (),
+        }
+
+        if transition.approvals > 1 && self.approvers.len() + 1 < transition.approvals {
+            self.approvers.push(actor.to_string());
+            return Ok(());
+        }
+
+        let now = self.clock.now();
+        let waiting = transition.wait.is_some() && self.scheduled_at.is_some_and(|at| at > now);
+        let to = match &transition.wait {
+            Some(wait) if waiting => wait.clone(),
+            _ => transition.to.clone(),
+        };
+
+        // the time is used up once the post has gone past the transition it could have waited at, either straight to where it leads because the time has passed already, or out of the state it was waiting in
+        if !waiting && (transition.wait.is_some() || self.workflow.timed(&self.state).is_some()) {
+            self.scheduled_at = None;
+        }
+
+        let from = std::mem::replace(&mut self.state, to);
+        self.approvers.clear(); // approvals only count towards leaving the state they were given in
+
+        self.trail.record(&from, &self.state, actor, now, comment);
+
+        Ok(())
     }
-}
 
-struct PendingReview {
-    approvals: u32,
+    /// Restores a post that follows `workflow` from what `Persist::to_record` has made of it; `Persist::from_record` is this with the standard workflow.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the record is not a valid post in `workflow`, e.g. if its state is not one of the workflow's.
+    pub fn from_record_with(record: Record, workflow: Arc<Workflow>) -> Result<Post, String> {
+        let trail = Trail::load(&record)?;
+        let state = record.get("state").ok_or("Missing the state.")?.to_string();
+
+        if workflow.state(&state).is_none() {
+            return Err(format!("Unknown state '{}'.", state));
+        }
+
+        // the approvers have to be ones the workflow would have let approve, and too few to have moved the post on
+        let mut approvers: Vec<String> = Vec::new();
+        let guarded = workflow.guarded(&state);
+
+        for approver in record.get_all("approved-by") {
+            let transition = guarded.ok_or_else(|| format!("The state '{}' takes no approvals.", state))?;
+            transition.by.check(&trail.author, approver, &transition.action)?;
+
+            if approvers.iter().any(|other| other == approver) {
+                return Err(format!("{} has {} the post already.", approver, past_tense(&transition.action)));
+            }
+
+            approvers.push(approver.to_string());
+
+            if approvers.len() >= transition.approvals {
+                return Err(format!("A post in the state '{}' with {} approvals: it should have been moved to '{}'.", state, approvers.len(), transition.to));
+            }
+        }
+
+        let scheduled_at = match record.get("scheduled-at") {
+            Some(at) => Some(history::parse_time(at).ok_or_else(|| format!("Invalid scheduled time '{}': expected 'SECONDS.NANOSECONDS'.", at))?),
+            None => None,
+        };
+
+        Ok(Post {
+            workflow,
+            state,
+            approvers,
+            scheduled_at,
+            clock: Arc::new(SystemClock),
+            trail,
+            content: record.into_content(),
+        })
+    }
+
+    fn rule(&self) -> &workflow::StateRule {
+        self.workflow.state(self.state()).expect("The state of a post is one of its workflow's.")
+    }
+
+    // the transition the clock would perform if `tick` was called now; a post waiting with no time to wait for is due right away
+    fn due(&self) -> Option<&workflow::TransitionRule> {
+        self.workflow.timed(&self.state).filter(|_| self.scheduled_at.is_none_or(|at| at <= self.clock.now()))
+    }
 }
 
-impl State for PendingReview {
-    fn request_review(self: Box<Self>) -> Box<dyn State> {
-        self // requesting a review for a draft that's already pending review has no effect thus returning the current state without changes
+// "approve" -> "approved", for the error messages; good enough for the actions the workflows are likely to have
+fn past_tense(action: &str) -> String {
+    match action.strip_suffix('e') {
+        Some(stem) => format!("{}ed", stem),
+        None => format!("{}ed", action),
     }
+}
 
-    fn approve(mut self: Box<Self>) -> Box<dyn State> {
-        self.approvals += 1;
-        if self.approvals == 2 {
-            Box::new(Published {})
-        } else {
-            self
+impl Persist for Post {
+    fn to_record(&self) -> Record {
+        let mut record = Record::new(&self.content);
+        record.set("state", &self.state);
+
+        for approver in &self.approvers {
+            record.add("approved-by", approver);
+        }
+
+        if let Some(at) = self.scheduled_at {
+            record.set("scheduled-at", history::format_time(at));
         }
+
+        self.trail.save(&mut record);
+
+        record
     }
 
-    fn reject(self: Box<Self>) -> Box<dyn State> {
-        Box::new(Draft {})
+    fn from_record(record: Record) -> Result<Post, String> {
+        Post::from_record_with(record, Workflow::standard())
     }
 }
 
-struct Published {}
-
-impl State for Published {
-    fn request_review(self: Box<Self>) -> Box<dyn State> {
-        self
+impl Trail {
+    fn new(author: &str) -> Trail {
+        Trail {
+            author: author.to_string(),
+            reviews: Vec::new(),
+            history: Vec::new(),
+        }
     }
 
-    fn approve(self: Box<Self>) -> Box<dyn State> {
-        self
+    // a "transition" to the same state, like the first of the two approvals, is not one
+    fn record(&mut self, from: &str, to: &str, actor: &str, at: SystemTime, comment: Option<&str>) {
+        if from != to {
+            self.history.push(Transition::new(from, to, actor, at, comment));
+        }
     }
 
-    fn reject(self: Box<Self>) -> Box<dyn State> {
-        self
+    fn save(&self, record: &mut Record) {
+        record.set("author", &self.author);
+        review::save_reviews(record, &self.reviews);
+        history::save_history(record, &self.history);
     }
 
-    fn content<'a>(&self, content: &'a String) -> &'a str {
-        &content
+    fn load(record: &Record) -> Result<Trail, String> {
+        Ok(Trail {
+            author: record.get("author").ok_or("Missing the author.")?.to_string(),
+            reviews: review::load_reviews(record)?,
+            history: history::load_history(record)?,
+        })
     }
 }
 
 // `RustifiedPost` is Rust-like approach to implement transition and transformations between states without using total encapsulation.
 // Instead states in this approach are encoded as different types.
+// This also means that its rules cannot come from a `Workflow`: they are the ones of the standard workflow, fixed at compile time.
+#[derive(Debug)]
 pub struct RustifiedPost {
     content: String,
+    trail: Trail,
 }
 
 impl RustifiedPost {
-    pub fn new() -> DraftPost {
+    #[allow(clippy::new_ret_no_self)] // a new post is a draft, and it's a different type in this approach
+    pub fn new(author: &str) -> DraftPost {
         DraftPost {
             content: String::new(),
+            trail: Trail::new(author),
         }
     }
 
     pub fn content(&self) -> &str {
         &self.content
     }
+
+    pub fn author(&self) -> &str {
+        &self.trail.author
+    }
+
+    pub fn reviews(&self) -> &[Review] {
+        &self.trail.reviews
+    }
+
+    pub fn history(&self) -> &[Transition] {
+        &self.trail.history
+    }
+}
+
+/// A transition the rules don't allow, along with the post it has been refused for, unchanged, since the transition has consumed it.
+#[derive(Debug)]
+pub struct Refused<P> {
+    pub post: P,
+    pub reason: String,
 }
 
+#[derive(Debug)]
 pub struct DraftPost {
     content: String,
+    trail: Trail,
 }
 
 impl DraftPost {
@@ -143,45 +387,181 @@ impl DraftPost {
     }
 
     // methods that transform the post take `self` and consume the instance in order to ensure that the old state cannot be used after the transformation
-    pub fn request_review(self) -> PendingReviewPost {
+    pub fn request_review(mut self) -> PendingReviewPost {
+        let author = self.trail.author.clone();
+        self.trail.record("draft", "pending-review", &author, SystemTime::now(), None);
+
         PendingReviewPost {
             content: self.content,
+            trail: self.trail,
         }
     }
 }
 
+#[derive(Debug)]
 pub struct PendingReviewPost {
     content: String,
+    trail: Trail,
 }
 
 impl PendingReviewPost {
-    pub fn approve(self) -> OneApprovalPost {
-        OneApprovalPost {
-            content: self.content,
+    // the rules that depend on who the reviewer is can only be checked at run time, so unlike the stage the result of the transition is not known at compile time
+    pub fn approve(mut self, reviewer: &str) -> Result<OneApprovalPost, Refused<PendingReviewPost>> {
+        if let Err(reason) = review::check_approval(&self.trail.author, &[], reviewer) {
+            return Err(Refused { post: self, reason });
         }
-    }
 
-    pub fn reject(self) -> DraftPost {
-        DraftPost {
+        self.trail.reviews.push(Review::approved(reviewer));
+
+        Ok(OneApprovalPost {
             content: self.content,
+            trail: self.trail,
+            approver: reviewer.to_string(),
+        })
+    }
+
+    pub fn reject(mut self, reviewer: &str, reason: &str) -> Result<DraftPost, Refused<PendingReviewPost>> {
+        if let Err(reason) = review::check_rejection(&self.trail.author, reviewer) {
+            return Err(Refused { post: self, reason });
         }
+
+        self.trail.reviews.push(Review::rejected(reviewer, reason));
+        self.trail.record("pending-review", "draft", reviewer, SystemTime::now(), Some(reason));
+
+        Ok(DraftPost {
+            content: self.content,
+            trail: self.trail,
+        })
     }
 }
 
+#[derive(Debug)]
 pub struct OneApprovalPost {
     content: String,
+    trail: Trail,
+    approver: String,
 }
 
 impl OneApprovalPost {
-    pub fn approve(self) -> RustifiedPost {
-        RustifiedPost {
-            content: self.content,
+    #[allow(clippy::result_large_err)] // the post is handed back by value, boxing it would just move it to the heap and back for the rare refusal
+    pub fn approve(mut self, reviewer: &str) -> Result<RustifiedPost, Refused<OneApprovalPost>> {
+        if let Err(reason) = review::check_approval(&self.trail.author, &[self.approver.clone()], reviewer) {
+            return Err(Refused { post: self, reason });
         }
+
+        self.trail.reviews.push(Review::approved(reviewer));
+        self.trail.record("pending-review", "published", reviewer, SystemTime::now(), None);
+
+        Ok(RustifiedPost {
+            content: self.content,
+            trail: self.trail,
+        })
     }
 
-    pub fn reject(self) -> DraftPost {
-        DraftPost {
+    #[allow(clippy::result_large_err)] // see `approve`
+    pub fn reject(mut self, reviewer: &str, reason: &str) -> Result<DraftPost, Refused<OneApprovalPost>> {
+        if let Err(reason) = review::check_rejection(&self.trail.author, reviewer) {
+            return Err(Refused { post: self, reason });
+        }
+
+        self.trail.reviews.push(Review::rejected(reviewer, reason));
+        self.trail.record("pending-review", "draft", reviewer, SystemTime::now(), Some(reason));
+
+        Ok(DraftPost {
             content: self.content,
+            trail: self.trail,
+        })
+    }
+}
+
+/// A `RustifiedPost` at any stage. Since every stage is a type of its own, this is what a post whose stage is only known at run time, like one loaded from a `storage::Store`, comes as.
+#[derive(Debug)]
+pub enum AnyPost {
+    Draft(DraftPost),
+    PendingReview(PendingReviewPost),
+    OneApproval(OneApprovalPost),
+    Published(RustifiedPost),
+}
+
+impl AnyPost {
+    // what the post is, for the error messages
+    fn describe(&self) -> &'static str {
+        match self {
+            AnyPost::Draft(_) => "a draft",
+            AnyPost::PendingReview(_) => "a post pending review",
+            AnyPost::OneApproval(_) => "a post with one approval",
+            AnyPost::Published(_) => "a published post",
+        }
+    }
+}
+
+// the stages are stored as the states of `Post` they correspond to, so that either implementation can load what the other has saved
+impl Persist for AnyPost {
+    fn to_record(&self) -> Record {
+        match self {
+            AnyPost::Draft(post) => post.to_record(),
+            AnyPost::PendingReview(post) => post.to_record(),
+            AnyPost::OneApproval(post) => post.to_record(),
+            AnyPost::Published(post) => post.to_record(),
+        }
+    }
+
+    fn from_record(record: Record) -> Result<AnyPost, String> {
+        let trail = Trail::load(&record)?;
+        let state = record.get("state").map(String::from);
+        let approvers = match state.as_deref() {
+            Some("pending-review") => review::load_approvers(&record, &trail.author)?,
+            _ => Vec::new(),
+        };
+        let content = record.into_content();
+
+        match (state.as_deref(), approvers.into_iter().next()) {
+            (Some("draft"), _) => Ok(AnyPost::Draft(DraftPost { content, trail })),
+            (Some("pending-review"), None) => Ok(AnyPost::PendingReview(PendingReviewPost { content, trail })),
+            (Some("pending-review"), Some(approver)) => Ok(AnyPost::OneApproval(OneApprovalPost { content, trail, approver })),
+            (Some("published"), _) => Ok(AnyPost::Published(RustifiedPost { content, trail })),
+            (Some(state), _) => Err(format!("Unknown state '{}'.", state)),
+            (None, _) => Err(String::from("Missing the state.")),
         }
     }
 }
+
+// every stage implements `Persist` by itself as well, loading fails if the stored post is at another stage; `approvers` are the ones of a post pending review
+macro_rules! persist_stage {
+    ($stage:ident, $variant:ident, $description:literal, $state:literal, $approvers:expr) => {
+        impl Persist for $stage {
+            fn to_record(&self) -> Record {
+                let approvers: fn(&$stage) -> Vec<&str> = $approvers;
+
+                let mut record = Record::new(&self.content);
+                record.set("state", $state);
+
+                for approver in approvers(self) {
+                    record.add("approved-by", approver);
+                }
+
+                self.trail.save(&mut record);
+
+                record
+            }
+
+            fn from_record(record: Record) -> Result<$stage, String> {
+                match AnyPost::from_record(record)? {
+                    AnyPost::$variant(post) => Ok(post),
+                    other => Err(format!("Expected {}, found {}.", $description, other.describe())),
+                }
+            }
+        }
+
+        impl From<$stage> for AnyPost {
+            fn from(post: $stage) -> AnyPost {
+                AnyPost::$variant(post)
+            }
+        }
+    };
+}
+
+persist_stage!(DraftPost, Draft, "a draft", "draft", |_| Vec::new());
+persist_stage!(PendingReviewPost, PendingReview, "a post pending review", "pending-review", |_| Vec::new());
+persist_stage!(OneApprovalPost, OneApproval, "a post with one approval", "pending-review", |post| vec![post.approver.as_str()]);
+persist_stage!(RustifiedPost, Published, "a published post", "published", |_| Vec::new());
@@ -1,7 +1,16 @@
-use blog::{Post, RustifiedPost};
+use std::env;
+use std::fs;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use blog::clock::{Clock, ManualClock};
+use blog::review::{Review, Verdict};
+use blog::storage::Store;
+use blog::workflow::Workflow;
+use blog::{AnyPost, OneApprovalPost, Post, RustifiedPost};
 
 fn main() {
-    let mut post = Post::new();
+    let mut post = Post::new("alice");
 
     post.add_text("I ate a salad for lunch today");
     assert_eq!("", post.content());
@@ -9,7 +18,7 @@ fn main() {
     post.request_review();
     assert_eq!("", post.content());
 
-    post.reject();
+    post.reject("bob", "Too short").unwrap();
     assert_eq!("", post.content());
 
     post.add_text(". And for dinner too.");
@@ -18,18 +27,223 @@ fn main() {
     post.request_review();
     assert_eq!("", post.content());
 
-    post.approve();
-    post.approve();
+    // it takes two different reviewers, neither of them the author
+    assert_eq!(post.approve("alice"), Err(String::from("alice cannot approve their own post.")));
+    post.approve("bob").unwrap();
+    assert_eq!(post.approve("bob"), Err(String::from("bob has approved the post already.")));
+    assert_eq!("", post.content());
+
+    post.approve("carol").unwrap();
     assert_eq!("I ate a salad for lunch today. And for dinner too.", post.content());
+    assert!(post.approve("dave").is_err()); // there's nothing left to approve
+
+    // who did what
+    assert_eq!(post.reviews().len(), 3);
+    assert_eq!(post.reviews()[0], Review::rejected("bob", "Too short"));
+    assert!(post.reviews()[1..].iter().all(|review| review.verdict == Verdict::Approved));
+
+    // and how the post got where it is, including why it bounced back to the draft
+    let history: Vec<_> = post.history().iter().map(|t| (t.from.as_str(), t.to.as_str(), t.actor.as_str(), t.comment.as_deref())).collect();
+    assert_eq!(
+        history,
+        [
+            ("draft", "pending-review", "alice", None),
+            ("pending-review", "draft", "bob", Some("Too short")),
+            ("draft", "pending-review", "alice", None),
+            ("pending-review", "published", "carol", None), // the first approval alone didn't change the state
+        ]
+    );
+    assert!(post.history().windows(2).all(|pair| pair[0].at <= pair[1].at));
+
+    // the reviews are recorded however the actions are performed
+    let mut post = Post::new("alice");
+    post.request_review();
+    post.act("reject", "bob", Some("Empty")).unwrap();
+    post.request_review();
+    post.act("approve", "carol", None).unwrap();
+    assert_eq!(post.reviews(), [Review::rejected("bob", "Empty"), Review::approved("carol")]);
 
-    let mut post = RustifiedPost::new();
+    let mut post = RustifiedPost::new("alice");
     post.add_text("I ate a salad for lunch today");
     let post = post.request_review(); // notice that we need to re-declare `post` after each transformation; that's because the methods that transform it consume `self` which in turn is to make sure that the old cannot be used after it has been transformed into a new one
-    let mut post = post.reject();
+
+    // the author can no more reject their own post than approve it
+    let refused = post.reject("alice", "Not good enough").unwrap_err();
+    assert_eq!(refused.reason, "alice cannot reject their own post.");
+    let mut post = refused.post.reject("bob", "Too short").unwrap();
     post.add_text(". And for dinner too.");
     let post = post.request_review();
-    let post = post.approve();
-    let post = post.approve();
+    let post = post.approve("bob").unwrap();
+
+    // a refused transition hands the post back as it was
+    let refused = post.approve("bob").unwrap_err();
+    assert_eq!(refused.reason, "bob has approved the post already.");
+    let post = refused.post.approve("carol").unwrap();
 
     assert_eq!("I ate a salad for lunch today. And for dinner too.", post.content());
+    assert_eq!(post.reviews().len(), 3);
+    assert_eq!(post.history().len(), 4);
+
+    // the rules `Post` follows are a workflow, which can be loaded from a file instead of being the standard one
+    let copy_edit = Arc::new(Workflow::load(concat!(env!("CARGO_MANIFEST_DIR"), "/workflows/copy-edit.toml")).unwrap());
+    assert_eq!(copy_edit.states().collect::<Vec<_>>(), ["draft", "copy-edit", "published"]);
+    assert_eq!(copy_edit.actions("copy-edit").collect::<Vec<_>>(), ["approve", "reject", "withdraw"]);
+
+    let mut post = Post::with_workflow("alice", Arc::clone(&copy_edit));
+    post.add_text("I ate a salad for lunch today");
+    post.request_review();
+    assert_eq!(post.state(), "copy-edit");
+
+    // the copy editors, and only them, may edit the post in this state, and one of them is enough to publish it
+    assert_eq!(post.edit("alice", "!"), Err(String::from("alice cannot edit the post.")));
+    post.edit("carol", ", and for dinner too.").unwrap();
+    assert_eq!(post.approve("bob"), Err(String::from("bob cannot approve the post.")));
+
+    // the author can take the post back to the draft, but not the copy editors
+    assert_eq!(post.act("withdraw", "carol", None), Err(String::from("Only the author can withdraw the post.")));
+    post.act("withdraw", "alice", Some("One more thing")).unwrap();
+    post.add_text(" Yummy!");
+    post.request_review();
+
+    post.approve("dave").unwrap();
+    assert_eq!(post.state(), "published");
+    assert_eq!("I ate a salad for lunch today, and for dinner too. Yummy!", post.content());
+    assert_eq!(post.act("withdraw", "alice", None), Err(String::from("Cannot withdraw a post in the state 'published'.")));
+    assert_eq!(post.history().len(), 4);
+
+    // a workflow is checked as a whole when it's loaded
+    assert_eq!(Workflow::parse("initial = \"draft\"\n[states.draft]\nedit = \"author\"\n[[transitions]]\naction = \"publish\"\nfrom = \"draft\"\nto = \"published\"\n"), Err(String::from("The transition 'publish' refers to the state 'published', which is not defined.")));
+    assert_eq!(Workflow::parse("initial = \"draft\"\n[states.draft]\nvisible = maybe\n"), Err(String::from("3: Invalid value 'maybe' for visible: expected true or false.")));
+
+    // a post can be scheduled for later, then it's the time that publishes it rather than the last approval; a clock that only moves when told to shows exactly when
+    let start = UNIX_EPOCH + Duration::from_secs(1_760_860_800);
+    let clock = Arc::new(ManualClock::new(start));
+    let noon = start + Duration::from_secs(60 * 60);
+
+    let mut post = Post::new("alice").with_clock(clock.clone());
+    post.add_text("Soup of the day");
+    assert_eq!(post.schedule("bob", None), Err(String::from("Only the author can schedule the post.")));
+    post.schedule("alice", Some(noon)).unwrap();
+    let last = post.history().last().unwrap();
+    assert_eq!((last.from.as_str(), last.to.as_str(), last.comment.as_deref()), ("draft", "draft", Some("Scheduled at 1760864400.000000000")));
+    post.request_review();
+    post.approve("bob").unwrap();
+    post.approve("carol").unwrap();
+    assert_eq!(post.state(), "scheduled");
+    assert_eq!("", post.content());
+    assert_eq!(post.act("publish", "bob", None), Err(String::from("Nobody can publish the post: it happens by itself at the time it's scheduled at.")));
+
+    clock.advance(Duration::from_secs(60 * 60 - 1));
+    assert_eq!(post.state(), "scheduled");
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(post.state(), "published");
+    assert_eq!("Soup of the day", post.content());
+
+    // the history has the time the post was due at, no matter when it has noticed
+    clock.advance(Duration::from_secs(60));
+    post.tick();
+    let last = post.history().last().unwrap();
+    assert_eq!((last.from.as_str(), last.to.as_str(), last.actor.as_str(), last.at), ("scheduled", "published", "clock", noon));
+    assert_eq!(post.scheduled_at(), None);
+
+    // and being published is not the end of the road anymore
+    assert_eq!(post.act("archive", "bob", None), Err(String::from("Only the author can archive the post.")));
+    post.act("archive", "alice", Some("Out of season")).unwrap();
+    assert_eq!("", post.content());
+    assert_eq!(post.act("unpublish", "alice", None), Err(String::from("Cannot unpublish a post in the state 'archived'.")));
+
+    let mut post = Post::new("alice").with_clock(clock.clone());
+    post.add_text("Salad of the day");
+    post.request_review();
+    post.approve("bob").unwrap();
+    post.approve("carol").unwrap();
+    assert_eq!(post.state(), "published"); // right away, since it isn't scheduled
+    post.act("unpublish", "alice", Some("Wrong day")).unwrap();
+    assert_eq!(post.state(), "draft");
+    post.add_text("!");
+    assert!(post.history().iter().all(|transition| transition.at == clock.now()));
+
+    // the posts can outlive the program in a store, a directory with a file per post
+    let dir = env::temp_dir().join(format!("blog-{}", std::process::id()));
+    let store = Store::open(&dir).unwrap();
+
+    let mut post = Post::new("alice");
+    post.add_text("Line one\nline two");
+    post.request_review();
+    post.reject("bob", "Needs a title;\nand a picture").unwrap();
+    post.request_review();
+    post.approve("bob").unwrap();
+    store.save("salad", &post).unwrap();
+    let history = post.history().to_vec();
+
+    // the state is restored along with the content, approvers and reviews included, so the second approval has to come from someone else and is enough to publish it
+    let mut post: Post = store.load("salad").unwrap();
+    assert_eq!("", post.content());
+    assert!(post.approve("bob").is_err());
+    post.approve("carol").unwrap();
+    assert_eq!("Line one\nline two", post.content());
+    assert_eq!(post.reviews()[0], Review::rejected("bob", "Needs a title;\nand a picture"));
+    assert_eq!(post.history()[..3], history[..]); // to the nanosecond
+    assert_eq!(post.history()[1].comment.as_deref(), Some("Needs a title;\nand a picture"));
+
+    // the other implementation reads the same files; the stage the post is at is only known once it's loaded
+    match store.load::<AnyPost>("salad").unwrap() {
+        AnyPost::OneApproval(post) => assert_eq!("Line one\nline two", post.approve("carol").unwrap().content()),
+        _ => panic!("The stored post has one approval."),
+    }
+
+    let post = RustifiedPost::new("alice").request_review();
+    store.save("draft", &post.reject("bob", "Empty").unwrap()).unwrap();
+    assert!(store.load::<OneApprovalPost>("draft").is_err()); // a draft is not a post with one approval
+
+    // a post that follows another workflow has to be loaded with it
+    let mut post = Post::with_workflow("alice", Arc::clone(&copy_edit));
+    post.add_text("Soup");
+    post.request_review();
+    store.save("soup", &post).unwrap();
+    assert!(store.load::<Post>("soup").is_err()); // there's no copy editing in the standard workflow
+    let mut post = store.load_with("soup", |record| Post::from_record_with(record, Arc::clone(&copy_edit))).unwrap();
+    post.approve("carol").unwrap();
+    assert_eq!("Soup", post.content());
+
+    // a scheduled post keeps waiting in the store
+    let mut post = Post::new("alice").with_clock(clock.clone());
+    post.schedule("alice", Some(clock.now() + Duration::from_secs(60))).unwrap();
+    post.request_review();
+    post.approve("bob").unwrap();
+    post.approve("carol").unwrap();
+    store.save("stew", &post).unwrap();
+    let post = store.load::<Post>("stew").unwrap().with_clock(clock.clone());
+    assert_eq!(post.state(), "scheduled");
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(post.state(), "published");
+    assert!(store.load::<AnyPost>("stew").is_err()); // the other implementation has no such stage
+
+    // the time is forgotten once it's of no use anymore: when it has passed by the time the post is approved
+    let mut post = Post::new("alice").with_clock(clock.clone());
+    post.schedule("alice", Some(noon)).unwrap();
+    post.request_review();
+    post.approve("bob").unwrap();
+    post.approve("carol").unwrap();
+    assert_eq!(post.state(), "published");
+    assert_eq!(post.scheduled_at(), None);
+    store.save("pie", &post).unwrap();
+    assert!(!fs::read_to_string(dir.join("pie.post")).unwrap().contains("scheduled-at"));
+    assert_eq!(store.load::<Post>("pie").unwrap().scheduled_at(), None);
+
+    // or when the post is taken back from waiting
+    let mut post = Post::new("alice").with_clock(clock.clone());
+    post.schedule("alice", Some(clock.now() + Duration::from_secs(60))).unwrap();
+    post.request_review();
+    post.approve("bob").unwrap();
+    post.approve("carol").unwrap();
+    post.act("unpublish", "alice", None).unwrap();
+    assert_eq!(post.scheduled_at(), None);
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(post.state(), "draft");
+
+    assert_eq!(store.ids().unwrap(), ["draft", "pie", "salad", "soup", "stew"]);
+    assert!(store.load::<Post>("../salad").is_err());
+
+    fs::remove_dir_all(dir).unwrap();
 }
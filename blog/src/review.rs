@@ -0,0 +1,96 @@
+// who reviewed a post and what they decided; both `Post` and `RustifiedPost` keep a log of the reviews, the rules below are the ones `RustifiedPost` follows, `Post` follows its workflow's
+
+use crate::storage::{self, Record};
+
+// the number of distinct reviewers it takes to publish a post, the same as in `workflow::STANDARD`
+pub(crate) const REQUIRED_APPROVALS: usize = 2;
+
+/// A single review of a post.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Review {
+    pub reviewer: String,
+    pub verdict: Verdict,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Approved,
+    Rejected { reason: String },
+}
+
+impl Review {
+    pub fn approved(reviewer: &str) -> Review {
+        Review {
+            reviewer: reviewer.to_string(),
+            verdict: Verdict::Approved,
+        }
+    }
+
+    pub fn rejected(reviewer: &str, reason: &str) -> Review {
+        Review {
+            reviewer: reviewer.to_string(),
+            verdict: Verdict::Rejected { reason: reason.to_string() },
+        }
+    }
+}
+
+// whether `reviewer` may approve a post by `author` that the reviewers in `approvers` have approved already
+pub(crate) fn check_approval(author: &str, approvers: &[String], reviewer: &str) -> Result<(), String> {
+    if reviewer == author {
+        return Err(format!("{} cannot approve their own post.", reviewer));
+    }
+
+    if approvers.iter().any(|approver| approver == reviewer) {
+        return Err(format!("{} has approved the post already.", reviewer));
+    }
+
+    Ok(())
+}
+
+// whether `reviewer` may reject a post by `author`
+pub(crate) fn check_rejection(author: &str, reviewer: &str) -> Result<(), String> {
+    if reviewer == author {
+        return Err(format!("{} cannot reject their own post.", reviewer));
+    }
+
+    Ok(())
+}
+
+// every review is a `review` field of its own, e.g. `review: rejected; bob; Too short`
+pub(crate) fn save_reviews(record: &mut Record, reviews: &[Review]) {
+    for review in reviews {
+        let value = match &review.verdict {
+            Verdict::Approved => storage::join(&["approved", &review.reviewer]),
+            Verdict::Rejected { reason } => storage::join(&["rejected", &review.reviewer, reason]),
+        };
+
+        record.add("review", value);
+    }
+}
+
+pub(crate) fn load_reviews(record: &Record) -> Result<Vec<Review>, String> {
+    record
+        .get_all("review")
+        .map(|value| match storage::split(value).as_slice() {
+            [verdict, reviewer] if verdict == "approved" => Ok(Review::approved(reviewer)),
+            [verdict, reviewer, reason] if verdict == "rejected" => Ok(Review::rejected(reviewer, reason)),
+            _ => Err(format!("Invalid review '{}': expected 'approved; REVIEWER' or 'rejected; REVIEWER; REASON'.", value)),
+        })
+        .collect()
+}
+
+// the reviewers who have approved a post pending review, which may not be enough to publish it yet
+pub(crate) fn load_approvers(record: &Record, author: &str) -> Result<Vec<String>, String> {
+    let mut approvers: Vec<String> = Vec::new();
+
+    for approver in record.get_all("approved-by") {
+        check_approval(author, &approvers, approver)?;
+        approvers.push(approver.to_string());
+    }
+
+    if approvers.len() >= REQUIRED_APPROVALS {
+        return Err(format!("A post pending review with {} approvals: it should have been published.", approvers.len()));
+    }
+
+    Ok(approvers)
+}
@@ -0,0 +1,279 @@
+// file-backed storage for the posts: every post is a file of its own in the directory of a `Store`, named after the id of the post
+// the file is plain text, a few `name: value` lines describing the state of the post, an empty line and then the content as it is, e.g.
+//
+//     state: pending-review
+//     approved-by: bob
+//     author: alice
+//     review: rejected; bob; Too short
+//     review: approved; bob
+//     transition: draft; pending-review; alice; 1760860000.123456789
+//     transition: pending-review; draft; bob; 1760860060.000000000; Too short
+//     transition: draft; pending-review; alice; 1760860120.000000000
+//
+//     I ate a salad for lunch today
+//
+// a `Post` scheduled for later has its time in a `scheduled-at` field, in the same format as the time of a transition
+//
+// both `Post` and the stages of `RustifiedPost` are stored in this format, so either of them can load what the other has saved, as long as the post is in a state `RustifiedPost` has a stage for
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const EXTENSION: &str = "post";
+
+/// The stored form of a post: the fields describing its state and its content.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Record {
+    fields: Vec<(String, String)>, // in the order they were set, which is also the order they are written in
+    content: String,
+}
+
+/// Something that can be stored as a `Record` and restored from it.
+pub trait Persist: Sized {
+    fn to_record(&self) -> Record;
+
+    /// Fails with a description of the problem if the record doesn't describe a valid `Self`.
+    fn from_record(record: Record) -> Result<Self, String>;
+}
+
+/// A directory of posts.
+pub struct Store {
+    dir: PathBuf,
+}
+
+impl Record {
+    pub fn new(content: &str) -> Record {
+        Record {
+            fields: Vec::new(),
+            content: content.to_string(),
+        }
+    }
+
+    /// Sets the field `name`, replacing its value if it's already there. `name` must not contain a colon or a line break; `value` may contain anything.
+    pub fn set(&mut self, name: &str, value: impl ToString) {
+        let value = value.to_string();
+
+        match self.fields.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value,
+            None => self.fields.push((name.to_string(), value)),
+        }
+    }
+
+    /// Adds another value for the field `name`, after the ones it has already. The same rules for `name` and `value` as in `set` apply.
+    pub fn add(&mut self, name: &str, value: impl ToString) {
+        self.fields.push((name.to_string(), value.to_string()));
+    }
+
+    /// Returns the (first) value of the field `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Returns all the values of the field `name`, in the order they were added.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.fields.iter().filter(move |(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn into_content(self) -> String {
+        self.content
+    }
+
+    /// Parses the text `Record` is displayed as.
+    ///
+    /// # Errors
+    ///
+    /// Fails if one of the lines before the empty line separating the content isn't a `name: value` pair.
+    pub fn parse(text: &str) -> Result<Record, String> {
+        let mut record = Record::default();
+        let mut rest = text;
+
+        loop {
+            let (line, tail) = rest.split_once('\n').ok_or("Missing the empty line before the content.")?;
+            rest = tail;
+
+            if line.is_empty() {
+                break;
+            }
+
+            let (name, value) = line.split_once(": ").ok_or_else(|| format!("Invalid field '{}': expected 'name: value'.", line))?;
+            record.fields.push((name.to_string(), unescape(value)));
+        }
+
+        record.content = rest.to_string();
+
+        Ok(record)
+    }
+}
+
+// the fields take a line each, so the line breaks in their values (and the backslashes, to tell an escaped line break from a literal `\n`) are escaped
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, value) in &self.fields {
+            writeln!(f, "{}: {}", name, escape(value))?;
+        }
+
+        writeln!(f)?;
+        write!(f, "{}", self.content)
+    }
+}
+
+impl Store {
+    /// Opens the store in `dir`, creating the directory if it doesn't exist yet.
+    ///
+    /// # Errors
+    ///
+    /// The same as `std::fs::create_dir_all`.
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Store> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        Ok(Store { dir })
+    }
+
+    /// Saves `post` under `id`, replacing the post saved under it before, if any.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `id` isn't made of letters, digits, `-` and `_` only, or if the file cannot be written.
+    pub fn save<P: Persist>(&self, id: &str, post: &P) -> io::Result<()> {
+        // a name of its own for every save, so that two saves of the same post at once don't write into the same temporary file; it doesn't end in `.post`, so `ids` doesn't list it
+        static SAVES: AtomicUsize = AtomicUsize::new(0);
+
+        let path = self.path(id)?;
+        let temporary = path.with_extension(format!("{}-{}.tmp", process::id(), SAVES.fetch_add(1, Ordering::Relaxed)));
+
+        // writing a file of its own and renaming it over the old one means that a crash halfway through leaves the old version intact rather than a truncated new one, as long as the new file is on the disk before the rename and the rename is before anything else relies on it
+        let written = File::create(&temporary).and_then(|mut file| {
+            file.write_all(post.to_record().to_string().as_bytes())?;
+            file.sync_all()
+        });
+
+        if let Err(e) = written.and_then(|()| fs::rename(&temporary, &path)) {
+            let _ = fs::remove_file(&temporary);
+
+            return Err(e);
+        }
+
+        File::open(&self.dir)?.sync_all()
+    }
+
+    /// Loads the post saved under `id`.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `io::ErrorKind::NotFound` if there is no such post, with `io::ErrorKind::InvalidData` if the file doesn't describe a valid `P`.
+    pub fn load<P: Persist>(&self, id: &str) -> io::Result<P> {
+        self.load_with(id, P::from_record)
+    }
+
+    /// Loads the post saved under `id` with `from_record` rather than `Persist::from_record`, for posts that need more than the record to be restored, like a `Post` that follows a workflow of its own.
+    ///
+    /// # Errors
+    ///
+    /// Same as `load`, `from_record` failing is `io::ErrorKind::InvalidData`.
+    pub fn load_with<T>(&self, id: &str, from_record: impl FnOnce(Record) -> Result<T, String>) -> io::Result<T> {
+        let text = fs::read_to_string(self.path(id)?)?;
+
+        Record::parse(&text)
+            .and_then(from_record)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Cannot load the post '{}': {}", id, e)))
+    }
+
+    /// Deletes the post saved under `id`.
+    ///
+    /// # Errors
+    ///
+    /// The same as `std::fs::remove_file`.
+    pub fn remove(&self, id: &str) -> io::Result<()> {
+        fs::remove_file(self.path(id)?)
+    }
+
+    /// Returns the ids of all the posts in the store, sorted.
+    ///
+    /// # Errors
+    ///
+    /// The same as `std::fs::read_dir`.
+    pub fn ids(&self) -> io::Result<Vec<String>> {
+        let mut ids = Vec::new();
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+
+            if path.extension().is_some_and(|extension| extension == EXTENSION) {
+                if let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    ids.push(id.to_string());
+                }
+            }
+        }
+
+        ids.sort();
+
+        Ok(ids)
+    }
+
+    // the ids become file names, so anything that could point outside of the directory (or just confuse the file system) is refused
+    fn path(&self, id: &str) -> io::Result<PathBuf> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid post id '{}': expected letters, digits, '-' and '_'.", id)));
+        }
+
+        Ok(self.dir.join(id).with_extension(EXTENSION))
+    }
+}
+
+/// Joins `parts` into a single value, e.g. to store a structure in one field; `split` takes it apart again, whatever the parts contain.
+pub fn join(parts: &[&str]) -> String {
+    parts.iter().map(|part| part.replace('\\', "\\\\").replace(';', "\\;")).collect::<Vec<_>>().join("; ")
+}
+
+/// The counterpart of `join`.
+pub fn split(value: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = value.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => parts.last_mut().unwrap().extend(chars.next()),
+            ';' => {
+                chars.next_if_eq(&' ');
+                parts.push(String::new());
+            }
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+
+    parts
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+
+    result
+}
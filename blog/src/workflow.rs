@@ -0,0 +1,368 @@
+// the states a `Post` goes through and the rules of moving between them, defined as data rather than code
+//
+// a workflow is written in a small subset of TOML: `key = value` pairs, `[states.NAME]` tables describing the states, `[[transitions]]` tables describing the moves between them, `#` comments and double-quoted strings; see `STANDARD` below for the workflow every post follows unless told otherwise
+//
+// a state has these keys, all of them optional:
+//   edit     who may add text to the post in this state [default: nobody]
+//   visible  whether the content of the post is shown in this state [default: false]
+//
+// a transition has these keys:
+//   action     the name to perform it by; `Post::request_review`, `Post::approve` and `Post::reject` perform `request-review`, `approve` and `reject`, and the last two are recorded as reviews however they are performed
+//   from, to   the states it moves the post between
+//   by         who may perform it [default: anyone]
+//   approvals  how many different people have to perform it before the post moves, the ones before are only counted [default: 1]
+//   wait       the state the post goes to instead of `to` if it's scheduled for later, see `Post::schedule`; the post has to be able to leave it by the clock
+//
+// "who" is one of `author`, `reviewers` (anyone but the author), `anyone`, `nobody` or a comma-separated list of names; a transition can be performed by the `clock` as well, which means the post goes through it by itself once the time it's scheduled at has passed
+
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+/// The Draft -> Pending review -> Published pipeline, with two different reviewers to approve a post. An approved post that is scheduled for later waits until it's due, and the author can take a published post back to the draft or archive it.
+pub const STANDARD: &str = r#"
+initial = "draft"
+
+[states.draft]
+edit = "author"
+
+[states.pending-review]
+
+[states.scheduled]
+
+[states.published]
+visible = true
+
+[states.archived]
+
+[[transitions]]
+action = "request-review"
+from = "draft"
+to = "pending-review"
+by = "author"
+
+[[transitions]]
+action = "approve"
+from = "pending-review"
+to = "published"
+by = "reviewers"
+approvals = 2
+wait = "scheduled"
+
+[[transitions]]
+action = "reject"
+from = "pending-review"
+to = "draft"
+by = "reviewers"
+
+[[transitions]]
+action = "publish"
+from = "scheduled"
+to = "published"
+by = "clock"
+
+[[transitions]]
+action = "unpublish"
+from = "scheduled"
+to = "draft"
+by = "author"
+
+[[transitions]]
+action = "unpublish"
+from = "published"
+to = "draft"
+by = "author"
+
+[[transitions]]
+action = "archive"
+from = "published"
+to = "archived"
+by = "author"
+"#;
+
+/// The states of a post and the transitions between them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workflow {
+    initial: String,
+    states: Vec<StateRule>,
+    transitions: Vec<TransitionRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StateRule {
+    pub(crate) name: String,
+    pub(crate) edit: Who,
+    pub(crate) visible: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TransitionRule {
+    pub(crate) action: String,
+    pub(crate) from: String,
+    pub(crate) to: String,
+    pub(crate) by: Who,
+    pub(crate) approvals: usize,
+    pub(crate) wait: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Who {
+    Author,
+    Reviewers,
+    Anyone,
+    Nobody,
+    Clock,
+    Only(Vec<String>),
+}
+
+// which table the keys being parsed belong to
+enum Table {
+    Top,
+    State(usize),
+    Transition(usize),
+}
+
+impl Workflow {
+    /// Returns the workflow defined by `STANDARD`, shared by all the posts that follow it.
+    pub fn standard() -> Arc<Workflow> {
+        static STANDARD_WORKFLOW: OnceLock<Arc<Workflow>> = OnceLock::new();
+
+        Arc::clone(STANDARD_WORKFLOW.get_or_init(|| Arc::new(Workflow::parse(STANDARD).expect("The standard workflow is valid."))))
+    }
+
+    /// Reads the workflow from the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file cannot be read or if it isn't a valid workflow, see `parse`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Workflow, String> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+
+        Workflow::parse(&text).map_err(|e| format!("{}:{}", path.display(), e))
+    }
+
+    /// Parses a workflow.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the text isn't valid (the message starts with the number of the offending line then), or if the workflow it describes isn't: if a transition refers to a state that doesn't exist, if the same action is defined twice for the same state, if more than one transition from a state requires several approvals or is performed by the clock, or if a post could wait in a state it cannot leave by the clock.
+    pub fn parse(text: &str) -> Result<Workflow, String> {
+        let mut initial = None;
+        let mut states: Vec<StateRule> = Vec::new();
+        let mut transitions: Vec<TransitionRule> = Vec::new();
+        let mut table = Table::Top;
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| format!("{}: {}", index + 1, message);
+
+            let line = strip_comment(line).trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if line == "[[transitions]]" {
+                transitions.push(TransitionRule {
+                    action: String::new(),
+                    from: String::new(),
+                    to: String::new(),
+                    by: Who::Anyone,
+                    approvals: 1,
+                    wait: None,
+                });
+                table = Table::Transition(transitions.len() - 1);
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let name = header.strip_suffix(']').and_then(|header| header.trim().strip_prefix("states.")).ok_or_else(|| error(format!("Invalid table '{}': expected [states.NAME] or [[transitions]].", line)))?;
+
+                if states.iter().any(|state| state.name == name) {
+                    return Err(error(format!("The state '{}' is defined twice.", name)));
+                }
+
+                states.push(StateRule {
+                    name: name.to_string(),
+                    edit: Who::Nobody,
+                    visible: false,
+                });
+                table = Table::State(states.len() - 1);
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(|| error(String::from("Expected 'key = value'.")))?;
+            let key = key.trim();
+            let value = value.trim();
+            let value = match value.strip_prefix('"') {
+                Some(quoted) => quoted.strip_suffix('"').ok_or_else(|| error(String::from("Unterminated string.")))?,
+                None => value,
+            };
+
+            match (&table, key) {
+                (Table::Top, "initial") => initial = Some(value.to_string()),
+                (Table::State(state), "edit") => states[*state].edit = Who::parse(value).map_err(error)?,
+                (Table::State(state), "visible") => states[*state].visible = value.parse().map_err(|_| error(format!("Invalid value '{}' for visible: expected true or false.", value)))?,
+                (Table::Transition(transition), "action") => transitions[*transition].action = value.to_string(),
+                (Table::Transition(transition), "from") => transitions[*transition].from = value.to_string(),
+                (Table::Transition(transition), "to") => transitions[*transition].to = value.to_string(),
+                (Table::Transition(transition), "by") => transitions[*transition].by = Who::parse(value).map_err(error)?,
+                (Table::Transition(transition), "approvals") => {
+                    transitions[*transition].approvals = value.parse().ok().filter(|&approvals| approvals > 0).ok_or_else(|| error(format!("Invalid value '{}' for approvals: expected a positive integer.", value)))?;
+                }
+                (Table::Transition(transition), "wait") => transitions[*transition].wait = Some(value.to_string()),
+                _ => return Err(error(format!("Unknown key '{}'.", key))),
+            }
+        }
+
+        let workflow = Workflow {
+            initial: initial.ok_or("Missing the initial state.")?,
+            states,
+            transitions,
+        };
+
+        workflow.validate()?;
+
+        Ok(workflow)
+    }
+
+    /// The state a new post starts in.
+    pub fn initial(&self) -> &str {
+        &self.initial
+    }
+
+    /// Returns the names of the states, in the order they are defined.
+    pub fn states(&self) -> impl Iterator<Item = &str> {
+        self.states.iter().map(|state| state.name.as_str())
+    }
+
+    /// Returns the actions that can be performed on a post in the state `from`.
+    pub fn actions<'a>(&'a self, from: &'a str) -> impl Iterator<Item = &'a str> {
+        self.transitions.iter().filter(move |transition| transition.from == from).map(|transition| transition.action.as_str())
+    }
+
+    pub(crate) fn state(&self, name: &str) -> Option<&StateRule> {
+        self.states.iter().find(|state| state.name == name)
+    }
+
+    pub(crate) fn transition(&self, from: &str, action: &str) -> Option<&TransitionRule> {
+        self.transitions.iter().find(|transition| transition.from == from && transition.action == action)
+    }
+
+    // the transition from `from` that takes several approvals, if any; `validate` makes sure there is at most one
+    pub(crate) fn guarded(&self, from: &str) -> Option<&TransitionRule> {
+        self.transitions.iter().find(|transition| transition.from == from && transition.approvals > 1)
+    }
+
+    // the transition from `from` the clock performs, if any; `validate` makes sure there is at most one
+    pub(crate) fn timed(&self, from: &str) -> Option<&TransitionRule> {
+        self.transitions.iter().find(|transition| transition.from == from && transition.by == Who::Clock)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.state(&self.initial).is_none() {
+            return Err(format!("The initial state '{}' is not defined.", self.initial));
+        }
+
+        if let Some(state) = self.states.iter().find(|state| state.edit == Who::Clock) {
+            return Err(format!("The state '{}' is edited by the clock: only transitions can be performed by it.", state.name));
+        }
+
+        for (index, transition) in self.transitions.iter().enumerate() {
+            if transition.action.is_empty() {
+                return Err(format!("The transition #{} has no action.", index + 1));
+            }
+
+            for state in [&transition.from, &transition.to] {
+                if self.state(state).is_none() {
+                    return Err(format!("The transition '{}' refers to the state '{}', which is not defined.", transition.action, state));
+                }
+            }
+
+            if self.transitions[..index].iter().any(|other| other.from == transition.from && other.action == transition.action) {
+                return Err(format!("The transition '{}' from '{}' is defined twice.", transition.action, transition.from));
+            }
+
+            if transition.approvals > 1 && self.guarded(&transition.from).is_some_and(|guarded| !std::ptr::eq(guarded, transition)) {
+                return Err(format!("More than one transition from '{}' requires several approvals.", transition.from));
+            }
+
+            if transition.by == Who::Clock {
+                if self.timed(&transition.from).is_some_and(|timed| !std::ptr::eq(timed, transition)) {
+                    return Err(format!("More than one transition from '{}' is performed by the clock.", transition.from));
+                }
+
+                if transition.approvals > 1 {
+                    return Err(format!("The transition '{}' is performed by the clock, it cannot require several approvals.", transition.action));
+                }
+            }
+
+            if let Some(wait) = &transition.wait {
+                if self.state(wait).is_none() {
+                    return Err(format!("The transition '{}' refers to the state '{}', which is not defined.", transition.action, wait));
+                }
+
+                if self.timed(wait).is_none() {
+                    return Err(format!("The transition '{}' waits in the state '{}', which no transition leaves by the clock.", transition.action, wait));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Who {
+    fn parse(value: &str) -> Result<Who, String> {
+        match value {
+            "author" => Ok(Who::Author),
+            "reviewers" => Ok(Who::Reviewers),
+            "anyone" => Ok(Who::Anyone),
+            "nobody" => Ok(Who::Nobody),
+            "clock" => Ok(Who::Clock),
+            names => {
+                let names: Vec<String> = names.split(',').map(|name| name.trim().to_string()).filter(|name| !name.is_empty()).collect();
+
+                if names.is_empty() {
+                    return Err(format!("Invalid value '{}': expected author, reviewers, anyone, nobody, clock or a list of names.", value));
+                }
+
+                Ok(Who::Only(names))
+            }
+        }
+    }
+
+    // whether `actor` is one of these people on a post by `author`; the reason why not otherwise
+    pub(crate) fn check(&self, author: &str, actor: &str, action: &str) -> Result<(), String> {
+        let allowed = match self {
+            Who::Author => actor == author,
+            Who::Reviewers => actor != author,
+            Who::Anyone => true,
+            Who::Nobody | Who::Clock => false,
+            Who::Only(names) => names.iter().any(|name| name == actor),
+        };
+
+        match (allowed, self) {
+            (true, _) => Ok(()),
+            (false, Who::Author) => Err(format!("Only the author can {} the post.", action)),
+            (false, Who::Reviewers) => Err(format!("{} cannot {} their own post.", actor, action)),
+            (false, Who::Clock) => Err(format!("Nobody can {} the post: it happens by itself at the time it's scheduled at.", action)),
+            (false, _) => Err(format!("{} cannot {} the post.", actor, action)),
+        }
+    }
+}
+
+// `#` starts a comment unless it is inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => (),
+        }
+    }
+
+    line
+}
@@ -0,0 +1,219 @@
+// a small criterion-style harness: every benchmark is warmed up first, then timed over a number of samples, and the distribution of the samples is reported
+// run with `cargo bench`; `cargo bench -- flood` only runs the benchmarks whose names contain `flood`
+
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use hello::ThreadPool;
+
+const WORKERS: usize = 4;
+const JOBS: usize = 100_000;
+const WARM_UP_TIME: Duration = Duration::from_secs(1);
+const SAMPLES: usize = 20;
+
+// the common interface of the two pool designs for the benchmarks to be generic over
+trait Pool: Send + Sync + 'static {
+    fn new(size: usize) -> Self;
+    fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static;
+}
+
+impl Pool for ThreadPool {
+    fn new(size: usize) -> ThreadPool {
+        ThreadPool::new(size)
+    }
+
+    fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        ThreadPool::execute(self, f)
+    }
+}
+
+// the previous design of `ThreadPool` kept around as the reference point: all the workers take turns on a single `Mutex<mpsc::Receiver>`
+mod baseline {
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+
+    type Job = Box<dyn FnOnce() + Send + 'static>;
+
+    enum Message {
+        NewJob(Job),
+        Terminate,
+    }
+
+    pub struct ThreadPool {
+        workers: Vec<thread::JoinHandle<()>>,
+        sender: mpsc::Sender<Message>,
+    }
+
+    impl super::Pool for ThreadPool {
+        fn new(size: usize) -> ThreadPool {
+            let (sender, receiver) = mpsc::channel();
+            let receiver = Arc::new(Mutex::new(receiver));
+
+            let workers = (0..size)
+                .map(|_| {
+                    let receiver = Arc::clone(&receiver);
+
+                    thread::spawn(move || loop {
+                        let message = receiver.lock().unwrap().recv().unwrap();
+
+                        match message {
+                            Message::NewJob(job) => job(),
+                            Message::Terminate => break,
+                        }
+                    })
+                })
+                .collect();
+
+            ThreadPool { workers, sender }
+        }
+
+        fn execute<F>(&self, f: F)
+        where
+            F: FnOnce() + Send + 'static,
+        {
+            self.sender.send(Message::NewJob(Box::new(f))).unwrap();
+        }
+    }
+
+    impl Drop for ThreadPool {
+        fn drop(&mut self) {
+            for _ in &self.workers {
+                self.sender.send(Message::Terminate).unwrap();
+            }
+
+            for worker in self.workers.drain(..) {
+                worker.join().unwrap();
+            }
+        }
+    }
+}
+
+// counts the finished jobs down and lets the benchmark wait for all of them
+struct Latch {
+    remaining: Mutex<usize>,
+    done: Condvar,
+}
+
+impl Latch {
+    fn new(count: usize) -> Latch {
+        Latch {
+            remaining: Mutex::new(count),
+            done: Condvar::new(),
+        }
+    }
+
+    fn count_down(&self) {
+        let mut remaining = self.remaining.lock().unwrap();
+
+        *remaining -= 1;
+
+        if *remaining == 0 {
+            self.done.notify_all();
+        }
+    }
+
+    fn wait(&self) {
+        let mut remaining = self.remaining.lock().unwrap();
+
+        while *remaining > 0 {
+            remaining = self.done.wait(remaining).unwrap();
+        }
+    }
+}
+
+// `JOBS` tiny jobs submitted from a single thread outside of the pool
+fn flood<P: Pool>(pool: &Arc<P>) {
+    let latch = Arc::new(Latch::new(JOBS));
+    let counter = Arc::new(AtomicUsize::new(0));
+
+    for _ in 0..JOBS {
+        let latch = Arc::clone(&latch);
+        let counter = Arc::clone(&counter);
+
+        pool.execute(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+            latch.count_down();
+        });
+    }
+
+    latch.wait();
+}
+
+// a few jobs that each submit a lot of tiny jobs from inside the pool
+fn fan_out<P: Pool>(pool: &Arc<P>) {
+    const PARENTS: usize = 100;
+
+    let latch = Arc::new(Latch::new(JOBS + PARENTS));
+    let counter = Arc::new(AtomicUsize::new(0));
+
+    for _ in 0..PARENTS {
+        let inner_pool = Arc::clone(pool);
+        let latch = Arc::clone(&latch);
+        let counter = Arc::clone(&counter);
+
+        pool.execute(move || {
+            for _ in 0..JOBS / PARENTS {
+                let latch = Arc::clone(&latch);
+                let counter = Arc::clone(&counter);
+
+                inner_pool.execute(move || {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    latch.count_down();
+                });
+            }
+
+            drop(inner_pool); // before counting down, so that the benchmark holds the last reference to the pool by the time it is done
+            latch.count_down();
+        });
+    }
+
+    latch.wait();
+}
+
+fn bench<P: Pool>(name: &str, filter: Option<&str>, routine: fn(&Arc<P>)) {
+    if filter.is_some_and(|filter| !name.contains(filter)) {
+        return;
+    }
+
+    let pool = Arc::new(P::new(WORKERS));
+
+    let warm_up_start = Instant::now();
+    while warm_up_start.elapsed() < WARM_UP_TIME {
+        routine(&pool);
+    }
+
+    let mut samples: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let start = Instant::now();
+            routine(&pool);
+            start.elapsed()
+        })
+        .collect();
+    samples.sort();
+
+    let mean = samples.iter().sum::<Duration>() / SAMPLES as u32;
+    let median = samples[SAMPLES / 2];
+    let throughput = JOBS as f64 / median.as_secs_f64();
+
+    println!("{:<32} time:   [{:>10.3?} {:>10.3?} {:>10.3?}]", name, samples[0], median, samples[SAMPLES - 1]);
+    println!("{:<32} mean:   {:>10.3?}", "", mean);
+    println!("{:<32} thrpt:  {:>10.0} jobs/s", "", throughput);
+}
+
+fn main() {
+    // `cargo bench` passes `--bench` to the binary, anything else is treated as a filter
+    let filter = env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let filter = filter.as_deref();
+
+    bench::<baseline::ThreadPool>("flood/mutex_receiver", filter, flood);
+    bench::<ThreadPool>("flood/work_stealing", filter, flood);
+    bench::<baseline::ThreadPool>("fan_out/mutex_receiver", filter, fan_out);
+    bench::<ThreadPool>("fan_out/work_stealing", filter, fan_out);
+}
@@ -0,0 +1,96 @@
+//! The standard base64 alphabet (RFC 4648) with padding; just enough for HTTP's basic authentication and the WebSocket handshake.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes `data` as padded base64.
+pub fn encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let mut bytes = [0; 4];
+        bytes[1..=chunk.len()].copy_from_slice(chunk);
+        let bits = u32::from_be_bytes(bytes);
+
+        // every 3 bytes become 4 characters of 6 bits each; a shorter last chunk is padded with `=`
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Decodes padded base64, returning `None` if `encoded` is not valid base64.
+pub fn decode(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.as_bytes();
+
+    if !encoded.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+
+    for (index, chunk) in encoded.chunks(4).enumerate() {
+        let last = index == encoded.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+
+        // padding may only end the very last chunk, and there are at most two `=`
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+
+        let mut bits = 0;
+
+        for &c in &chunk[..4 - padding] {
+            bits = bits << 6 | ALPHABET.iter().position(|&a| a == c)? as u32;
+        }
+
+        bits <<= 6 * padding;
+
+        decoded.extend_from_slice(&bits.to_be_bytes()[1..4 - padding]);
+    }
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the test vectors from RFC 4648, section 10
+    const VECTORS: [(&str, &str); 7] = [
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ];
+
+    #[test]
+    fn encodes() {
+        for (plain, encoded) in VECTORS {
+            assert_eq!(encode(plain.as_bytes()), encoded);
+        }
+    }
+
+    #[test]
+    fn decodes() {
+        for (plain, encoded) in VECTORS {
+            assert_eq!(decode(encoded).unwrap(), plain.as_bytes());
+        }
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert_eq!(decode("Zm9"), None);
+        assert_eq!(decode("Zm9v!A=="), None);
+        assert_eq!(decode("Zg==Zm9v"), None);
+        assert_eq!(decode("Z==="), None);
+    }
+}
@@ -0,0 +1,306 @@
+// a load generator for the server: keeps N connections busy sending requests for a while, then reports the throughput and the latency distribution
+// every request goes over a connection of its own, since the server closes the connection after each response; connecting is part of the measured latency
+
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, prelude::*};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "\
+Usage: hello-bench [OPTIONS] ADDRESS
+
+Sends requests to the server at ADDRESS (HOST:PORT) over concurrent connections and reports the throughput and the latency percentiles.
+
+Options:
+  -c, --connections N        the number of concurrent connections [default: 16]
+  -d, --duration DURATION    for how long to send the requests, e.g. 30s or 1m [default: 10s]
+  -m, --method METHOD        the method of the requests [default: GET]
+  -p, --path PATH            the path of the requests [default: /]
+  -t, --timeout DURATION     how long a request may take before it counts as failed [default: 5s]
+  -h, --help                 print this help";
+
+const PERCENTILES: [f64; 4] = [50.0, 90.0, 99.0, 99.9];
+
+#[derive(Debug, Clone, PartialEq)]
+struct Options {
+    address: SocketAddr,
+    host: String, // as given, for the `Host` header
+    connections: usize,
+    duration: Duration,
+    method: String,
+    path: String,
+    timeout: Duration,
+}
+
+// what a single connection has seen; merged into the totals at the end
+#[derive(Debug, Default)]
+struct Stats {
+    latencies: Vec<Duration>, // of the requests that got a response
+    statuses: BTreeMap<u16, usize>,
+    bytes: usize,
+    connect_errors: usize,
+    timeouts: usize,
+    other_errors: usize,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+
+        return;
+    }
+
+    let options = parse_args(&args).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+
+        process::exit(2);
+    });
+
+    println!("Running {:?} test @ http://{}{}", options.duration, options.host, options.path);
+    println!("  {} connections, {} requests", options.connections, options.method);
+
+    let started = Instant::now();
+    let deadline = started + options.duration;
+
+    let stats = thread::scope(|s| {
+        let handles: Vec<_> = (0..options.connections).map(|_| s.spawn(|| run_connection(&options, deadline))).collect();
+
+        handles.into_iter().map(|handle| handle.join().expect("A connection thread has panicked.")).fold(Stats::default(), Stats::merge)
+    });
+
+    report(&stats, started.elapsed());
+
+    // a run where nothing got through is most likely a typo in the address, which a script should notice
+    if stats.latencies.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut address = None;
+    let mut connections = 16;
+    let mut duration = Duration::from_secs(10);
+    let mut method = String::from("GET");
+    let mut path = String::from("/");
+    let mut timeout = Duration::from_secs(5);
+
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        // both `--option value` and `--option=value`
+        let (option, inline) = match arg.split_once('=') {
+            Some((option, value)) if option.starts_with("--") => (option, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+
+        if !option.starts_with('-') {
+            match address {
+                None => address = Some(arg.clone()),
+                Some(_) => return Err(format!("Unexpected argument {}. See --help.", arg)),
+            }
+
+            continue;
+        }
+
+        let mut value = || inline.clone().or_else(|| args.next().cloned()).ok_or_else(|| format!("Option {} needs a value.", option));
+
+        match option {
+            "-c" | "--connections" => {
+                connections = value()?.parse().ok().filter(|&connections| connections > 0).ok_or_else(|| format!("Invalid value for {}: expected a positive integer.", option))?;
+            }
+            "-d" | "--duration" => duration = parse_duration(&value()?).ok_or_else(|| format!("Invalid value for {}: expected a duration like 500ms, 5s or 1m.", option))?,
+            "-t" | "--timeout" => timeout = parse_duration(&value()?).ok_or_else(|| format!("Invalid value for {}: expected a duration like 500ms, 5s or 1m.", option))?,
+            "-m" | "--method" => method = value()?,
+            "-p" | "--path" => path = value()?,
+            _ => return Err(format!("Unknown option {}. See --help.", option)),
+        }
+    }
+
+    let host = address.ok_or("Missing the address of the server. See --help.")?;
+
+    let address = host
+        .to_socket_addrs()
+        .map_err(|e| format!("Invalid address {}: {}", host, e))?
+        .next()
+        .ok_or_else(|| format!("Invalid address {}: it resolves to nothing", host))?;
+
+    Ok(Options {
+        address,
+        host,
+        connections,
+        duration,
+        method,
+        path,
+        timeout,
+    })
+}
+
+// the same notation as the timeouts of the server: a number with an optional unit, `ms`, `s` (the default) or `m`
+fn parse_duration(value: &str) -> Option<Duration> {
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok()?;
+
+    let duration = match unit.trim() {
+        "ms" => Duration::from_millis(number),
+        "" | "s" => Duration::from_secs(number),
+        "m" => Duration::from_secs(number.checked_mul(60)?),
+        _ => return None,
+    };
+
+    Some(duration).filter(|duration| !duration.is_zero())
+}
+
+fn run_connection(options: &Options, deadline: Instant) -> Stats {
+    let request = format!("{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: hello-bench\r\nConnection: close\r\n\r\n", options.method, options.path, options.host);
+    let mut stats = Stats::default();
+
+    while Instant::now() < deadline {
+        let started = Instant::now();
+
+        match send(options, &request) {
+            Ok((status, bytes)) => {
+                stats.latencies.push(started.elapsed());
+                *stats.statuses.entry(status).or_insert(0) += 1;
+                stats.bytes += bytes;
+            }
+            Err(Failure::Connect) => {
+                stats.connect_errors += 1;
+                thread::sleep(Duration::from_millis(10)); // don't spin on a server that is down
+            }
+            Err(Failure::Exchange(e)) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => stats.timeouts += 1, // depending on the platform a socket timeout is reported as either of the two
+            Err(Failure::Exchange(_)) => stats.other_errors += 1,
+        }
+    }
+
+    stats
+}
+
+enum Failure {
+    Connect,
+    Exchange(io::Error),
+}
+
+// sends the request over a new connection and reads the response until the server closes it; returns the status and the size of the response
+fn send(options: &Options, request: &str) -> Result<(u16, usize), Failure> {
+    let mut stream = TcpStream::connect_timeout(&options.address, options.timeout).map_err(|_| Failure::Connect)?;
+
+    let exchange = |stream: &mut TcpStream| -> io::Result<(u16, usize)> {
+        stream.set_read_timeout(Some(options.timeout))?;
+        stream.set_write_timeout(Some(options.timeout))?;
+        stream.write_all(request.as_bytes())?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+
+        // `HTTP/1.1 200 OK`
+        let status = response
+            .split(|&b| b == b' ')
+            .nth(1)
+            .and_then(|status| std::str::from_utf8(status).ok())
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not an HTTP response"))?;
+
+        Ok((status, response.len()))
+    };
+
+    exchange(&mut stream).map_err(Failure::Exchange)
+}
+
+impl Stats {
+    fn merge(mut self, other: Stats) -> Stats {
+        self.latencies.extend(other.latencies);
+
+        for (status, count) in other.statuses {
+            *self.statuses.entry(status).or_insert(0) += count;
+        }
+
+        self.bytes += other.bytes;
+        self.connect_errors += other.connect_errors;
+        self.timeouts += other.timeouts;
+        self.other_errors += other.other_errors;
+
+        self
+    }
+}
+
+// the nearest-rank percentile of `sorted`, which must not be empty; in whole per mille, `99.9` times `1000` in floating point is a hair above `999`
+fn percentile(sorted: &[Duration], percentile: f64) -> Duration {
+    let per_mille = (percentile * 10.0).round() as usize;
+    let rank = (per_mille * sorted.len()).div_ceil(1000);
+
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn report(stats: &Stats, elapsed: Duration) {
+    let requests = stats.latencies.len();
+    let errors = stats.connect_errors + stats.timeouts + stats.other_errors;
+
+    println!();
+    println!("Requests:     {} in {:.2?} ({:.1}/s)", requests, elapsed, requests as f64 / elapsed.as_secs_f64());
+    println!("Transferred:  {:.2} MiB", stats.bytes as f64 / (1024.0 * 1024.0));
+    println!("Errors:       {} (connect {}, timeout {}, other {})", errors, stats.connect_errors, stats.timeouts, stats.other_errors);
+
+    let statuses: Vec<String> = stats.statuses.iter().map(|(status, count)| format!("{}: {}", status, count)).collect();
+    println!("Statuses:     {}", if statuses.is_empty() { String::from("none") } else { statuses.join(", ") });
+
+    if requests == 0 {
+        return;
+    }
+
+    let mut latencies = stats.latencies.clone();
+    latencies.sort();
+
+    let mean = latencies.iter().sum::<Duration>() / requests as u32;
+
+    println!("Latency:      min {:.2?}, mean {:.2?}, max {:.2?}", latencies[0], mean, latencies[requests - 1]);
+
+    for p in PERCENTILES {
+        println!("  p{:<6}     {:.2?}", p, percentile(&latencies, p));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn options() {
+        let options = parse_args(&args(&["-c", "4", "--duration=2s", "--path", "/sleep", "127.0.0.1:7878"])).unwrap();
+
+        assert_eq!(options.connections, 4);
+        assert_eq!(options.duration, Duration::from_secs(2));
+        assert_eq!(options.path, "/sleep");
+        assert_eq!(options.method, "GET");
+        assert_eq!(options.timeout, Duration::from_secs(5));
+        assert_eq!(options.address, "127.0.0.1:7878".parse().unwrap());
+
+        assert_eq!(parse_args(&args(&[])).unwrap_err(), "Missing the address of the server. See --help.");
+        assert_eq!(parse_args(&args(&["-c", "0", "localhost:80"])).unwrap_err(), "Invalid value for -c: expected a positive integer.");
+        assert_eq!(parse_args(&args(&["127.0.0.1:80", "-d"])).unwrap_err(), "Option -d needs a value.");
+        assert_eq!(parse_args(&args(&["--verbose", "127.0.0.1:80"])).unwrap_err(), "Unknown option --verbose. See --help.");
+        assert!(parse_args(&args(&["--timeout", "5h", "127.0.0.1:80"])).is_err());
+        assert!(parse_args(&args(&["--duration", "999999999999999999m", "127.0.0.1:80"])).is_err());
+    }
+
+    #[test]
+    fn percentiles() {
+        let sorted: Vec<Duration> = (1..=1000).map(Duration::from_millis).collect();
+
+        assert_eq!(percentile(&sorted, 50.0), Duration::from_millis(500));
+        assert_eq!(percentile(&sorted, 99.0), Duration::from_millis(990));
+        assert_eq!(percentile(&sorted, 99.9), Duration::from_millis(999));
+        assert_eq!(percentile(&sorted, 100.0), Duration::from_millis(1000));
+        assert_eq!(percentile(&sorted[..1], 99.9), Duration::from_millis(1));
+        assert_eq!(percentile(&sorted, 0.0), Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,663 @@
+//! Request bodies: reading them off the connection within the size limits and parsing them according to their `Content-Type`.
+//!
+//! - `application/x-www-form-urlencoded` and `multipart/form-data` become a `Form`; the files of a multipart form are streamed to the upload directory rather than kept in memory,
+//! - `application/json` (and the `+json` types) becomes a `Json` value,
+//! - anything else is kept as it is.
+//!
+//! The body has to come with a `Content-Length`; a chunked one is refused with `411 Length Required`.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::json::Json;
+use crate::request::Request;
+
+// the headers of a single part of a multipart form are small, unlike its contents
+const MAX_PART_HEAD_SIZE: usize = 8 * 1024;
+
+// how much of a multipart body is read at a time
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// The parsed body of a request.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Body {
+    /// The request has no body (or an empty one).
+    #[default]
+    None,
+    Form(Form),
+    Json(Json),
+    /// A body of any other type, or any body forwarded to a proxy, which passes it on untouched.
+    Raw(Vec<u8>),
+}
+
+/// The fields of a submitted form, and the files uploaded with it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Form {
+    fields: Vec<(String, String)>,
+    files: Vec<Arc<Upload>>, // shared, since the file on disk goes away with the last reference to it
+}
+
+/// A file uploaded with a multipart form. It is stored in the upload directory and deleted once the request is gone, unless it's `persist`ed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Upload {
+    name: String,
+    filename: String,
+    content_type: Option<String>,
+    path: PathBuf,
+    size: u64,
+}
+
+/// Where the bodies are read to, and how large they may be.
+#[derive(Debug, Clone)]
+pub(crate) struct Limits {
+    /// The limit on the bodies kept in memory, the fields of a multipart form included.
+    pub max_size: usize,
+    /// The limit on a whole multipart body, the files included.
+    pub max_upload_size: usize,
+    pub upload_dir: PathBuf,
+}
+
+/// Why a body couldn't be read.
+#[derive(Debug)]
+pub(crate) enum Error {
+    LengthRequired,
+    TooLarge,
+    Malformed(String),
+    /// Reading from the client has failed (or timed out).
+    Io(io::Error),
+    /// Storing an uploaded file has failed.
+    Storage(io::Error),
+}
+
+impl Body {
+    /// Returns the raw bytes, if the body is neither a form nor JSON.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Raw(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Parses the form field `name`, or the member `name` of a JSON object, as a `T`.
+    ///
+    /// # Errors
+    /// Fails if there is no such field, or if it isn't a valid `T`.
+    pub fn field<T>(&self, name: &str) -> Result<T, String>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        match self {
+            Body::Form(form) => form.parse(name),
+            Body::Json(json) => {
+                let value = match json.get(name) {
+                    Some(Json::String(value)) => value.clone(),
+                    Some(value @ (Json::Number(_) | Json::Bool(_))) => value.to_string(),
+                    Some(_) => return Err(format!("Field '{}' is not a string, a number or a boolean.", name)),
+                    None => return Err(format!("Missing field '{}'.", name)),
+                };
+
+                parse_field(&value, name)
+            }
+            Body::None | Body::Raw(_) => Err(format!("Missing field '{}'.", name)),
+        }
+    }
+}
+
+impl Form {
+    /// Returns the first value of the field `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Returns all the values of the field `name`, e.g. of a multiple choice, in the order they were sent.
+    pub fn get_all<'f>(&'f self, name: &'f str) -> impl Iterator<Item = &'f str> {
+        self.fields.iter().filter(move |(n, _)| n == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Parses the first value of the field `name` as a `T`.
+    ///
+    /// # Errors
+    /// Fails if there is no such field, or if it isn't a valid `T`.
+    ///
+    /// # Example
+    /// ```
+    /// use hello::body::Form;
+    ///
+    /// let form = Form::parse_urlencoded("name=Ferris&legs=10&tags=crab&tags=mascot").unwrap();
+    ///
+    /// assert_eq!(form.parse::<u32>("legs"), Ok(10));
+    /// assert_eq!(form.get_all("tags").collect::<Vec<_>>(), ["crab", "mascot"]);
+    /// assert!(form.parse::<u32>("name").is_err());
+    /// ```
+    pub fn parse<T>(&self, name: &str) -> Result<T, String>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let value = self.get(name).ok_or_else(|| format!("Missing field '{}'.", name))?;
+
+        parse_field(value, name)
+    }
+
+    /// Returns all the fields in the order they were sent; the files are not among them.
+    pub fn fields(&self) -> &[(String, String)] {
+        &self.fields
+    }
+
+    /// Returns the first file uploaded as the field `name`.
+    pub fn file(&self, name: &str) -> Option<&Upload> {
+        self.files.iter().find(|file| file.name == name).map(|file| file.as_ref())
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &Upload> {
+        self.files.iter().map(|file| file.as_ref())
+    }
+
+    /// Parses an `application/x-www-form-urlencoded` body (or query string): `name=value` pairs separated by `&`, with `+` for a space and `%XX` escapes.
+    ///
+    /// # Errors
+    /// Fails on an invalid escape or if the decoded text isn't UTF-8.
+    pub fn parse_urlencoded(text: &str) -> Result<Form, String> {
+        let fields = text
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                Ok((percent_decode(name)?, percent_decode(value)?))
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Form { fields, files: Vec::new() })
+    }
+}
+
+impl Upload {
+    /// The name of the form field the file has been uploaded as.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The name of the file on the client's side. It comes straight from the client, so it must not be used as a path as it is.
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Where the file is stored for the time being.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The size of the file in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Moves the file to `to`, where it stays once the request is gone.
+    ///
+    /// # Errors
+    /// The same as `std::fs::copy`: moving to another file system means copying, and that's what may fail.
+    pub fn persist(&self, to: impl AsRef<Path>) -> io::Result<()> {
+        match fs::rename(&self.path, to.as_ref()) {
+            Ok(()) => Ok(()),
+            Err(_) => fs::copy(&self.path, to).map(|_| ()), // the copy in the upload directory is deleted on drop
+        }
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path); // already gone if it's been persisted
+    }
+}
+
+impl Error {
+    /// The status to answer the request with.
+    pub fn status(&self) -> u16 {
+        match self {
+            Error::LengthRequired => 411,
+            Error::TooLarge => 413,
+            Error::Malformed(_) => 400,
+            // depending on the platform a socket timeout is reported as either of the two
+            Error::Io(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => 408,
+            Error::Io(_) => 400,
+            Error::Storage(_) => 500,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::LengthRequired => write!(f, "the body has no Content-Length"),
+            Error::TooLarge => write!(f, "the body is too large"),
+            Error::Malformed(reason) => write!(f, "{}", reason),
+            Error::Io(e) => write!(f, "cannot read the body: {}", e),
+            Error::Storage(e) => write!(f, "cannot store an uploaded file: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// Reads the body of `request` from `reader` (which continues right after the head) and parses it, unless it's `raw`.
+///
+/// Nothing is read past the end of the body, but a body refused for its size isn't read at all.
+pub(crate) fn read<R: Read>(reader: R, request: &Request, raw: bool, limits: &Limits) -> Result<Body, Error> {
+    if request.header("Transfer-Encoding").is_some() {
+        return Err(Error::LengthRequired);
+    }
+
+    let length = match request.header("Content-Length") {
+        Some(length) => length.parse::<usize>().map_err(|_| Error::Malformed(format!("invalid Content-Length '{}'", length)))?,
+        None => 0,
+    };
+
+    if length == 0 {
+        return Ok(Body::None);
+    }
+
+    let (media_type, parameters) = media_type(request.header("Content-Type").unwrap_or_default());
+    let multipart = !raw && media_type == "multipart/form-data";
+
+    if length > if multipart { limits.max_upload_size } else { limits.max_size } {
+        return Err(Error::TooLarge);
+    }
+
+    let mut reader = reader.take(length as u64);
+
+    if multipart {
+        let boundary = parameters
+            .iter()
+            .find(|(name, _)| name == "boundary")
+            .map(|(_, boundary)| boundary.as_str())
+            .filter(|boundary| !boundary.is_empty())
+            .ok_or_else(|| Error::Malformed(String::from("the multipart body has no boundary")))?;
+
+        let form = Multipart::new(&mut reader, boundary).read(limits)?;
+
+        // the epilogue after the last part means nothing, but it has to be read off the connection
+        io::copy(&mut reader, &mut io::sink())?;
+
+        if reader.limit() > 0 {
+            return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        return Ok(Body::Form(form));
+    }
+
+    let mut bytes = Vec::with_capacity(length);
+    reader.read_to_end(&mut bytes)?;
+
+    if bytes.len() < length {
+        return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+    }
+
+    if raw {
+        return Ok(Body::Raw(bytes));
+    }
+
+    let text = || String::from_utf8(bytes.clone()).map_err(|_| Error::Malformed(String::from("the body is not UTF-8")));
+
+    match media_type.as_str() {
+        "application/x-www-form-urlencoded" => Ok(Body::Form(Form::parse_urlencoded(&text()?).map_err(Error::Malformed)?)),
+        media_type if media_type == "application/json" || media_type.ends_with("+json") => {
+            Json::parse(&text()?).map(Body::Json).map_err(|e| Error::Malformed(format!("invalid JSON at {}", e)))
+        }
+        _ => Ok(Body::Raw(bytes)),
+    }
+}
+
+fn parse_field<T>(value: &str, name: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value.parse().map_err(|e| format!("Invalid value '{}' for field '{}': {}.", value, name, e))
+}
+
+fn percent_decode(text: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let escape = rest.get(..2).and_then(|hex| std::str::from_utf8(hex).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                bytes.push(escape.ok_or_else(|| format!("invalid escape in '{}'", text))?);
+                rest = &rest[2..];
+            }
+            byte => bytes.push(byte),
+        }
+    }
+
+    String::from_utf8(bytes).map_err(|_| format!("'{}' is not UTF-8 once decoded", text))
+}
+
+// splits a header like `multipart/form-data; boundary="abc"` into the lowercase type and the parameters, unquoted
+fn media_type(value: &str) -> (String, Vec<(String, String)>) {
+    let (media_type, parameters) = value.split_once(';').unwrap_or((value, ""));
+
+    (media_type.trim().to_ascii_lowercase(), parse_parameters(parameters))
+}
+
+// `name=value` pairs separated by `;`, where a value may be a quoted string with `\` escapes (and with `;` in it)
+fn parse_parameters(text: &str) -> Vec<(String, String)> {
+    let mut parameters = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    loop {
+        while chars.next_if(|&c| c == ';' || c.is_whitespace()).is_some() {}
+
+        let name: String = chars.by_ref().take_while(|&c| c != '=').collect();
+        if name.trim().is_empty() {
+            return parameters;
+        }
+
+        let mut value = String::new();
+
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+        }
+
+        // what follows a quoted value up to the `;` is garbage, an unquoted value is all of it
+        let unquoted: String = chars.by_ref().take_while(|&c| c != ';').collect();
+        if value.is_empty() {
+            value = unquoted.trim().to_string();
+        }
+
+        parameters.push((name.trim().to_ascii_lowercase(), value));
+    }
+}
+
+// a streaming parser of a `multipart/form-data` body (RFC 7578): the parts are separated by `--boundary` lines
+struct Multipart<'r, R> {
+    reader: &'r mut R,
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+}
+
+impl<'r, R: Read> Multipart<'r, R> {
+    fn new(reader: &'r mut R, boundary: &str) -> Multipart<'r, R> {
+        Multipart {
+            reader,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            buffer: b"\r\n".to_vec(), // the first delimiter starts the body, without a line break before it
+        }
+    }
+
+    fn read(mut self, limits: &Limits) -> Result<Form, Error> {
+        let mut form = Form::default();
+        let mut fields_size = 0;
+
+        self.copy_until_delimiter(&mut |_| Ok(()))?; // the preamble
+
+        loop {
+            // a delimiter is followed by `--` after the last part, by a line break otherwise
+            while self.buffer.len() < 2 {
+                self.fill()?;
+            }
+
+            if self.buffer.starts_with(b"--") {
+                self.buffer.clear(); // the epilogue is read off by the caller
+                return Ok(form);
+            }
+
+            let head = self.read_part_head()?;
+            let disposition = head.iter().find(|(name, _)| name.eq_ignore_ascii_case("Content-Disposition")).map(|(_, value)| media_type(value));
+
+            let Some((_, parameters)) = disposition.filter(|(kind, _)| kind == "form-data") else {
+                return Err(Error::Malformed(String::from("a part has no form-data Content-Disposition")));
+            };
+
+            let parameter = |name: &str| parameters.iter().find(|(n, _)| n == name).map(|(_, value)| value.clone());
+            let name = parameter("name").ok_or_else(|| Error::Malformed(String::from("a part has no name")))?;
+
+            match parameter("filename") {
+                Some(filename) => {
+                    let content_type = head.iter().find(|(name, _)| name.eq_ignore_ascii_case("Content-Type")).map(|(_, value)| value.clone());
+                    let (mut file, mut upload) = create_upload(&limits.upload_dir, name, filename, content_type).map_err(Error::Storage)?;
+
+                    self.copy_until_delimiter(&mut |chunk| {
+                        upload.size += chunk.len() as u64;
+                        file.write_all(chunk).map_err(Error::Storage)
+                    })?;
+
+                    form.files.push(Arc::new(upload));
+                }
+                None => {
+                    let mut value = Vec::new();
+
+                    self.copy_until_delimiter(&mut |chunk| {
+                        fields_size += chunk.len();
+
+                        if fields_size > limits.max_size {
+                            return Err(Error::TooLarge);
+                        }
+
+                        value.extend_from_slice(chunk);
+                        Ok(())
+                    })?;
+
+                    let value = String::from_utf8(value).map_err(|_| Error::Malformed(format!("the field '{}' is not UTF-8", name)))?;
+                    form.fields.push((name, value));
+                }
+            }
+        }
+    }
+
+    // the headers of a part, from the line break after the delimiter to the empty line
+    fn read_part_head(&mut self) -> Result<Vec<(String, String)>, Error> {
+        loop {
+            if let Some(end) = find(&self.buffer, b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&self.buffer[2..end.max(2)]).into_owned();
+                self.buffer.drain(..end + 4);
+
+                return Ok(head.lines().filter_map(|line| line.split_once(':')).map(|(name, value)| (name.trim().to_string(), value.trim().to_string())).collect());
+            }
+
+            if self.buffer.len() > MAX_PART_HEAD_SIZE {
+                return Err(Error::Malformed(String::from("the headers of a part are too large")));
+            }
+
+            self.fill()?;
+        }
+    }
+
+    // passes everything up to the next delimiter on to `sink`, and skips the delimiter
+    fn copy_until_delimiter(&mut self, sink: &mut dyn FnMut(&[u8]) -> Result<(), Error>) -> Result<(), Error> {
+        loop {
+            if let Some(position) = find(&self.buffer, &self.delimiter) {
+                sink(&self.buffer[..position])?;
+                self.buffer.drain(..position + self.delimiter.len());
+
+                return Ok(());
+            }
+
+            // whatever could be the beginning of a delimiter has to wait for the next chunk
+            let safe = self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+            sink(&self.buffer[..safe])?;
+            self.buffer.drain(..safe);
+
+            self.fill()?;
+        }
+    }
+
+    fn fill(&mut self) -> Result<(), Error> {
+        let mut chunk = [0; CHUNK_SIZE];
+
+        loop {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => return Err(Error::Malformed(String::from("the multipart body ends early"))),
+                Ok(bytes_read) => {
+                    self.buffer.extend_from_slice(&chunk[..bytes_read]);
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+// creates an empty file with a name of its own in `dir`, the client's name for it is not to be trusted
+fn create_upload(dir: &Path, name: String, filename: String, content_type: Option<String>) -> io::Result<(File, Upload)> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+
+    fs::create_dir_all(dir)?;
+
+    let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let path = dir.join(format!("upload-{}-{}-{}", process::id(), started, NEXT.fetch_add(1, Ordering::Relaxed)));
+
+    let file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+
+    Ok((
+        file,
+        Upload {
+            name,
+            filename,
+            content_type,
+            path,
+            size: 0,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn limits(max_size: usize, max_upload_size: usize) -> Limits {
+        Limits {
+            max_size,
+            max_upload_size,
+            upload_dir: env::temp_dir().join(format!("hello-body-test-{}", process::id())),
+        }
+    }
+
+    fn read_body(content_type: &str, body: &[u8], limits: &Limits) -> Result<Body, Error> {
+        let request = Request::parse(&format!("POST /form HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n", content_type, body.len()));
+
+        read(body, &request, false, limits)
+    }
+
+    #[test]
+    fn urlencoded() {
+        let form = Form::parse_urlencoded("name=J%C3%BCrgen+M.&empty=&flag&a%26b=c%3Dd").unwrap();
+
+        assert_eq!(form.get("name"), Some("Jürgen M."));
+        assert_eq!(form.get("empty"), Some(""));
+        assert_eq!(form.get("flag"), Some(""));
+        assert_eq!(form.get("a&b"), Some("c=d"));
+        assert_eq!(form.get("missing"), None);
+
+        assert!(Form::parse_urlencoded("broken=%4").is_err());
+        assert!(Form::parse_urlencoded("broken=%zz").is_err());
+        assert!(Form::parse_urlencoded("broken=%ff").is_err());
+    }
+
+    #[test]
+    fn parameters() {
+        assert_eq!(
+            media_type("Multipart/Form-Data; boundary=\"a;b\\\"c\"; charset=utf-8"),
+            (String::from("multipart/form-data"), vec![(String::from("boundary"), String::from("a;b\"c")), (String::from("charset"), String::from("utf-8"))])
+        );
+        assert_eq!(media_type("text/plain"), (String::from("text/plain"), Vec::new()));
+    }
+
+    #[test]
+    fn typed_fields() {
+        let limits = limits(1024, 1024);
+
+        let body = read_body("application/json", br#"{"age": 42, "name": "Ferris", "admin": true, "tags": []}"#, &limits).unwrap();
+        assert_eq!(body.field::<u8>("age"), Ok(42));
+        assert_eq!(body.field::<String>("name"), Ok(String::from("Ferris")));
+        assert_eq!(body.field::<bool>("admin"), Ok(true));
+        assert_eq!(body.field::<String>("tags"), Err(String::from("Field 'tags' is not a string, a number or a boolean.")));
+        assert_eq!(body.field::<u8>("missing"), Err(String::from("Missing field 'missing'.")));
+
+        let body = read_body("application/x-www-form-urlencoded", b"age=300", &limits).unwrap();
+        assert_eq!(body.field::<u8>("age"), Err(String::from("Invalid value '300' for field 'age': number too large to fit in target type.")));
+
+        let body = read_body("application/octet-stream", b"\x00\x01", &limits).unwrap();
+        assert_eq!(body.bytes(), Some(&b"\x00\x01"[..]));
+    }
+
+    #[test]
+    fn multipart() {
+        let limits = limits(1024, 64 * 1024);
+        let contents: Vec<u8> = (0..=255).cycle().take(20_000).collect(); // longer than a chunk, with line breaks and dashes in it
+
+        let mut body = b"preamble\r\n--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHello\r\nworld\r\n--XyZ\r\n".to_vec();
+        body.extend_from_slice(b"Content-Disposition: form-data; name=\"file\"; filename=\"../../etc/passwd\"\r\nContent-Type: application/octet-stream\r\n\r\n");
+        body.extend_from_slice(&contents);
+        body.extend_from_slice(b"\r\n--XyZ\r\nContent-Disposition: form-data; name=\"empty\"; filename=\"\"\r\n\r\n\r\n--XyZ--\r\nepilogue");
+
+        let Body::Form(form) = read_body("multipart/form-data; boundary=XyZ", &body, &limits).unwrap() else { panic!("Expected a form.") };
+
+        assert_eq!(form.get("title"), Some("Hello\r\nworld"));
+
+        let file = form.file("file").unwrap();
+        assert_eq!(file.filename(), "../../etc/passwd");
+        assert_eq!(file.content_type(), Some("application/octet-stream"));
+        assert_eq!(file.size(), contents.len() as u64);
+        assert!(file.path().starts_with(&limits.upload_dir));
+        assert_eq!(fs::read(file.path()).unwrap(), contents);
+
+        assert_eq!(form.file("empty").unwrap().size(), 0);
+
+        let path = file.path().to_path_buf();
+        drop(form);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn refuses_invalid_bodies() {
+        let limits = limits(16, 128);
+        let status = |content_type: &str, body: &[u8]| read_body(content_type, body, &limits).unwrap_err().status();
+
+        assert_eq!(status("application/json", b"[1, 2, 3, 4, 5, 6, 7, 8]"), 413);
+        assert_eq!(status("application/json", b"{"), 400);
+        assert_eq!(status("application/x-www-form-urlencoded", b"a=%"), 400);
+        assert_eq!(status("multipart/form-data", b"--x--"), 400); // no boundary
+        assert_eq!(status("multipart/form-data; boundary=x", b"--x\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n"), 400); // ends early
+        assert_eq!(status("multipart/form-data; boundary=x", b"--x\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n0123456789abcdefg\r\n--x--"), 413);
+
+        let request = Request::parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n");
+        assert_eq!(read(&b""[..], &request, false, &limits).unwrap_err().status(), 411);
+
+        // the client sends less than it has announced
+        let request = Request::parse("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n");
+        assert_eq!(read(&b"short"[..], &request, false, &limits).unwrap_err().status(), 400);
+    }
+}
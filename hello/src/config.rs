@@ -0,0 +1,707 @@
+//! The settings of the `hello` binary, gathered from (in the order of increasing priority) the built-in defaults, a configuration file, the environment and the command line.
+//!
+//! The configuration file uses a small subset of TOML: `key = value` pairs, `[section]` headers, `#` comments and optionally double-quoted values.
+//! ```toml
+//! bind = "127.0.0.1:8443"
+//! workers = 8
+//!
+//! [tls]
+//! certificate = "cert.pem"
+//! private_key = "key.pem"
+//!
+//! [hosts]
+//! sites = "example.com=/var/www/example, *.example.org=/var/www/org"
+//! strict = true
+//! ```
+//! Every setting `section.key` can also be set with the environment variable `HELLO_SECTION_KEY` (e.g. `HELLO_TLS_CERTIFICATE`) or with the corresponding command line option (see `USAGE`).
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::handlers::{self, Handlers};
+use crate::logging::{AccessLogFormat, Level};
+use crate::middleware::{BasicAuth, Cors};
+use crate::proxy::{Balancing, Proxies, Proxy};
+use crate::server::{Config, Mode, TlsConfig};
+use crate::virtual_hosts::Site;
+use crate::websocket::{self, Endpoints};
+
+/// The command line help.
+pub const USAGE: &str = "\
+Usage: hello [OPTIONS]
+
+Options:
+  -c, --config FILE          read the settings from FILE (also HELLO_CONFIG)
+  -b, --bind ADDRESS         the address to listen on [default: 127.0.0.1:7878]
+  -w, --workers N            the number of worker threads [default: 4]
+      --queue-capacity N     the number of connections waiting for a worker before 503s are returned [default: 16]
+  -r, --root DIR             the directory to serve the pages from [default: resources]
+      --mode MODE            threaded (a worker per connection) or event-loop (epoll, Linux only) [default: threaded]
+      --compression-threshold BYTES
+                             compress the text responses at least this long [default: 1024]
+      --read-timeout DURATION
+                             how long a client may stay silent while sending a request, e.g. 5s or 500ms [default: 5s]
+      --write-timeout DURATION
+                             how long writing a response may stall [default: 10s]
+      --header-timeout DURATION
+                             how long a client has to send the whole request head [default: 10s]
+      --max-header-size BYTES
+                             the maximum size of the request line and the headers [default: 8192]
+      --max-body-size BYTES  the maximum size of a request body kept in memory [default: 1048576]
+      --max-upload-size BYTES
+                             the maximum size of a multipart body, the uploaded files included [default: 16777216]
+      --upload-dir DIR       where the uploaded files are stored while handled [default: hello-uploads in the temporary directory]
+      --connections-per-ip N the maximum number of open connections per client address [default: unlimited]
+      --body-echo PATH       serve the handler describing the POST and PUT bodies it gets at PATH [default: none]
+      --websocket-echo PATH  serve the WebSocket endpoint sending the messages back at PATH [default: none]
+      --websocket-idle-timeout DURATION
+                             how long a WebSocket client may stay silent before it's disconnected [default: 60s]
+      --sites SITES          serve the comma-separated HOST=DIR sites by the Host header, HOST may be *.DOMAIN [default: none]
+      --strict-hosts BOOL    answer the hosts without a site with 421 rather than from --root [default: false]
+      --cors-origin ORIGINS  the origins (comma-separated, or *) allowed to call the server from a browser [default: none]
+      --basic-auth USERS     require basic authentication with one of the comma-separated NAME:PASSWORD pairs [default: none]
+      --auth-realm REALM     the realm of the basic authentication [default: hello]
+      --proxy UPSTREAMS      forward the requests to the comma-separated HOST:PORT upstreams [default: none]
+      --proxy-path PREFIX    forward only the paths under PREFIX [default: /]
+      --proxy-balancing STRATEGY
+                             round-robin or least-connections [default: round-robin]
+      --proxy-retries N      how many other upstreams to try when one fails [default: 1]
+      --proxy-timeout DURATION
+                             how long an upstream may stall [default: 30s]
+      --proxy-max-response-size BYTES
+                             the maximum size of an upstream's response [default: 16777216]
+      --proxy-health-check PATH
+                             probe the upstreams with GET PATH every 5 seconds [default: no probes]
+      --max-connections N    exit after serving N connections [default: unlimited]
+      --tls-cert FILE        PEM certificate chain; enables HTTPS together with --tls-key
+      --tls-key FILE         PEM private key
+      --log-level LEVEL      error, warn, info, debug or trace [default: info]
+      --log-file FILE        write the log to FILE (rotated) instead of the standard error
+      --access-log TARGET    off, stdout, stderr or a file [default: stdout]
+      --access-format FORMAT common or combined [default: combined]
+  -h, --help                 print this help
+
+Every option can also be set in the configuration file or with an environment variable, e.g. `workers` or HELLO_WORKERS, `tls.certificate` or HELLO_TLS_CERTIFICATE.";
+
+// how often the upstreams are probed when the health checks are on
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Everything the binary needs to know to start.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub server: Config,
+    pub max_connections: Option<usize>,
+    pub log_level: Level,
+    pub log_file: Option<PathBuf>,
+    pub access_log: AccessLogTarget,
+    pub access_format: AccessLogFormat,
+}
+
+/// Where the access log goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessLogTarget {
+    Off,
+    Stdout,
+    Stderr,
+    File(PathBuf),
+}
+
+// a setting known to the binary: its name in the configuration file and the command line options that set it
+struct Key {
+    name: &'static str,
+    long: &'static str,
+    short: Option<&'static str>,
+}
+
+const KEYS: &[Key] = &[
+    Key { name: "bind", long: "--bind", short: Some("-b") },
+    Key { name: "workers", long: "--workers", short: Some("-w") },
+    Key { name: "queue_capacity", long: "--queue-capacity", short: None },
+    Key { name: "root", long: "--root", short: Some("-r") },
+    Key { name: "mode", long: "--mode", short: None },
+    Key { name: "compression_threshold", long: "--compression-threshold", short: None },
+    Key { name: "read_timeout", long: "--read-timeout", short: None },
+    Key { name: "write_timeout", long: "--write-timeout", short: None },
+    Key { name: "header_timeout", long: "--header-timeout", short: None },
+    Key { name: "max_header_size", long: "--max-header-size", short: None },
+    Key { name: "max_body_size", long: "--max-body-size", short: None },
+    Key { name: "max_upload_size", long: "--max-upload-size", short: None },
+    Key { name: "upload_dir", long: "--upload-dir", short: None },
+    Key { name: "connections_per_ip", long: "--connections-per-ip", short: None },
+    Key { name: "handlers.echo", long: "--body-echo", short: None },
+    Key { name: "websocket.echo", long: "--websocket-echo", short: None },
+    Key { name: "websocket.idle_timeout", long: "--websocket-idle-timeout", short: None },
+    Key { name: "hosts.sites", long: "--sites", short: None },
+    Key { name: "hosts.strict", long: "--strict-hosts", short: None },
+    Key { name: "cors.allow_origin", long: "--cors-origin", short: None },
+    Key { name: "auth.users", long: "--basic-auth", short: None },
+    Key { name: "auth.realm", long: "--auth-realm", short: None },
+    Key { name: "proxy.upstreams", long: "--proxy", short: None },
+    Key { name: "proxy.path", long: "--proxy-path", short: None },
+    Key { name: "proxy.balancing", long: "--proxy-balancing", short: None },
+    Key { name: "proxy.retries", long: "--proxy-retries", short: None },
+    Key { name: "proxy.timeout", long: "--proxy-timeout", short: None },
+    Key { name: "proxy.max_response_size", long: "--proxy-max-response-size", short: None },
+    Key { name: "proxy.health_check", long: "--proxy-health-check", short: None },
+    Key { name: "max_connections", long: "--max-connections", short: None },
+    Key { name: "tls.certificate", long: "--tls-cert", short: None },
+    Key { name: "tls.private_key", long: "--tls-key", short: None },
+    Key { name: "log.level", long: "--log-level", short: None },
+    Key { name: "log.file", long: "--log-file", short: None },
+    Key { name: "log.access", long: "--access-log", short: None },
+    Key { name: "log.access_format", long: "--access-format", short: None },
+];
+
+// where a value came from, so that the errors can point at it
+#[derive(Debug, Clone)]
+enum Source {
+    File(PathBuf, usize),
+    Environment(String),
+    CommandLine(String),
+}
+
+// the raw values of the settings, later sources overriding the earlier ones
+type Values = HashMap<&'static str, (String, Source)>;
+
+impl Settings {
+    /// Gathers the settings from the configuration file, the environment and the command line arguments and validates them.
+    ///
+    /// `args` are the command line arguments without the program name. `env` looks up an environment variable, normally it is `|name| std::env::var(name).ok()`.
+    ///
+    /// # Errors
+    /// Returns a description of the first problem found: an unknown option, an unreadable or malformed configuration file, or an invalid value (along with where it came from).
+    pub fn load(args: impl Iterator<Item = String>, env: impl Fn(&str) -> Option<String>) -> Result<Settings, String> {
+        let (config_file, cli_values) = parse_args(args)?;
+
+        let mut values = Values::new();
+
+        if let Some(path) = config_file.or_else(|| env("HELLO_CONFIG").map(PathBuf::from)) {
+            let contents = fs::read_to_string(&path).map_err(|e| format!("Cannot read the configuration file {}: {}", path.display(), e))?;
+
+            values.extend(parse_file(&contents, &path)?);
+        }
+
+        for key in KEYS {
+            let variable = env_variable(key.name);
+
+            if let Some(value) = env(&variable) {
+                values.insert(key.name, (value, Source::Environment(variable)));
+            }
+        }
+
+        values.extend(cli_values);
+
+        Settings::from_values(&values)
+    }
+
+    fn from_values(values: &Values) -> Result<Settings, String> {
+        let defaults = Config::default();
+
+        let address = match values.get("bind") {
+            Some((address, source)) => {
+                // resolving the address right away catches typos early; binding can still fail later, e.g. if the port is taken
+                address
+                    .to_socket_addrs()
+                    .map_err(|e| invalid(address, "bind", source, &format!("expected HOST:PORT ({})", e)))?;
+                address.clone()
+            }
+            None => defaults.address,
+        };
+
+        let workers = parse_positive(values, "workers")?.unwrap_or(defaults.workers);
+        let queue_capacity = parse_positive(values, "queue_capacity")?.unwrap_or(defaults.queue_capacity);
+        let max_connections = parse_positive(values, "max_connections")?;
+
+        let root = match values.get("root") {
+            Some((root, source)) => {
+                let root = PathBuf::from(root);
+
+                if !root.is_dir() {
+                    return Err(invalid(&root.display(), "root", source, "not a directory"));
+                }
+
+                root
+            }
+            None => defaults.root,
+        };
+
+        let tls = match (values.get("tls.certificate"), values.get("tls.private_key")) {
+            (Some((certificate, certificate_source)), Some((private_key, private_key_source))) => {
+                let certificate = existing_file(certificate, "tls.certificate", certificate_source)?;
+                let private_key = existing_file(private_key, "tls.private_key", private_key_source)?;
+
+                Some(TlsConfig { certificate, private_key })
+            }
+            (None, None) => None,
+            _ => return Err(String::from("TLS needs both the certificate and the private key (tls.certificate and tls.private_key).")),
+        };
+
+        let mut middleware = defaults.middleware;
+
+        if let Some((origins, _)) = values.get("cors.allow_origin") {
+            let cors = origins.split(',').map(str::trim).filter(|origin| !origin.is_empty()).fold(Cors::new(), Cors::allow_origin);
+
+            middleware = middleware.with(cors);
+        }
+
+        if let Some((users, source)) = values.get("auth.users") {
+            let realm = values.get("auth.realm").map_or("hello", |(realm, _)| realm.as_str());
+            let mut auth = BasicAuth::new(realm);
+
+            for user in users.split(',').map(str::trim) {
+                // the passwords stay out of the error message
+                let (name, password) = user
+                    .split_once(':')
+                    .filter(|(name, password)| !name.is_empty() && !password.is_empty())
+                    .ok_or_else(|| invalid(&"...", "auth.users", source, "expected NAME:PASSWORD pairs separated by commas"))?;
+
+                auth = auth.user(name, password);
+            }
+
+            middleware = middleware.with(auth);
+        }
+
+        let proxies = match values.get("proxy.upstreams") {
+            Some((upstreams, source)) => {
+                let upstreams: Vec<&str> = upstreams.split(',').map(str::trim).filter(|upstream| !upstream.is_empty()).collect();
+
+                if upstreams.is_empty() {
+                    return Err(invalid(&"", "proxy.upstreams", source, "expected HOST:PORT addresses separated by commas"));
+                }
+
+                for upstream in &upstreams {
+                    upstream
+                        .to_socket_addrs()
+                        .map_err(|e| invalid(upstream, "proxy.upstreams", source, &format!("expected HOST:PORT ({})", e)))?;
+                }
+
+                let mut proxy = Proxy::new(upstreams).balancing(parse(values, "proxy.balancing")?.unwrap_or(Balancing::RoundRobin));
+
+                if let Some(retries) = parse(values, "proxy.retries")? {
+                    proxy = proxy.retries(retries);
+                }
+
+                if let Some(timeout) = parse_duration(values, "proxy.timeout")? {
+                    proxy = proxy.timeout(timeout);
+                }
+
+                if let Some(size) = parse_positive(values, "proxy.max_response_size")? {
+                    proxy = proxy.max_response_size(size);
+                }
+
+                if let Some((path, _)) = values.get("proxy.health_check") {
+                    proxy = proxy.health_check(path, HEALTH_CHECK_INTERVAL);
+                }
+
+                let prefix = values.get("proxy.path").map_or("/", |(prefix, _)| prefix.as_str());
+
+                Proxies::new().route(prefix, proxy)
+            }
+            None => defaults.proxies,
+        };
+
+        let mut virtual_hosts = defaults.virtual_hosts.strict(parse(values, "hosts.strict")?.unwrap_or(false));
+
+        if let Some((sites, source)) = values.get("hosts.sites") {
+            for site in sites.split(',').map(str::trim).filter(|site| !site.is_empty()) {
+                let (host, root) = site
+                    .split_once('=')
+                    .map(|(host, root)| (host.trim(), PathBuf::from(root.trim())))
+                    .filter(|(host, _)| !host.is_empty())
+                    .ok_or_else(|| invalid(&site, "hosts.sites", source, "expected HOST=DIR pairs separated by commas"))?;
+
+                if !root.is_dir() {
+                    return Err(invalid(&root.display(), "hosts.sites", source, "not a directory"));
+                }
+
+                virtual_hosts = virtual_hosts.site(host, Site::new(root));
+            }
+        }
+
+        let mode = parse(values, "mode")?.unwrap_or(defaults.mode);
+        let compression_threshold = parse(values, "compression_threshold")?.unwrap_or(defaults.compression_threshold);
+        let read_timeout = parse_duration(values, "read_timeout")?.unwrap_or(defaults.read_timeout);
+        let write_timeout = parse_duration(values, "write_timeout")?.unwrap_or(defaults.write_timeout);
+        let header_timeout = parse_duration(values, "header_timeout")?.unwrap_or(defaults.header_timeout);
+        let max_header_size = parse_positive(values, "max_header_size")?.unwrap_or(defaults.max_header_size);
+        let max_body_size = parse_positive(values, "max_body_size")?.unwrap_or(defaults.max_body_size);
+        let max_upload_size = parse_positive(values, "max_upload_size")?.unwrap_or(defaults.max_upload_size);
+        let upload_dir = values.get("upload_dir").map_or(defaults.upload_dir, |(dir, _)| PathBuf::from(dir)); // created on the first upload
+        let connections_per_ip = parse_positive(values, "connections_per_ip")?.or(defaults.connections_per_ip);
+        let handlers = values.get("handlers.echo").map_or(defaults.handlers, |(path, _)| Handlers::new().route("POST", path, handlers::echo).route("PUT", path, handlers::echo));
+        let websockets = values.get("websocket.echo").map_or(defaults.websockets, |(path, _)| Endpoints::new().route(path, websocket::echo));
+        let websocket_idle_timeout = parse_duration(values, "websocket.idle_timeout")?.unwrap_or(defaults.websocket_idle_timeout);
+
+        let log_level = parse(values, "log.level")?.unwrap_or(Level::Info);
+        let log_file = values.get("log.file").map(|(file, _)| PathBuf::from(file));
+        let access_log = values.get("log.access").map_or(AccessLogTarget::Stdout, |(target, _)| target.parse().unwrap()); // any other value is a file name, so parsing it never fails
+        let access_format = parse(values, "log.access_format")?.unwrap_or(AccessLogFormat::Combined);
+
+        Ok(Settings {
+            server: Config {
+                address,
+                workers,
+                queue_capacity,
+                root,
+                tls,
+                mode,
+                compression_threshold,
+                read_timeout,
+                write_timeout,
+                header_timeout,
+                max_header_size,
+                max_body_size,
+                max_upload_size,
+                upload_dir,
+                connections_per_ip,
+                middleware,
+                websockets,
+                websocket_idle_timeout,
+                proxies,
+                handlers,
+                virtual_hosts,
+            },
+            max_connections,
+            log_level,
+            log_file,
+            access_log,
+            access_format,
+        })
+    }
+}
+
+impl FromStr for AccessLogTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AccessLogTarget, String> {
+        Ok(match s {
+            "off" => AccessLogTarget::Off,
+            "stdout" => AccessLogTarget::Stdout,
+            "stderr" => AccessLogTarget::Stderr,
+            path => AccessLogTarget::File(PathBuf::from(path)),
+        })
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Mode, String> {
+        match s {
+            "threaded" => Ok(Mode::Threaded),
+            "event-loop" => Ok(Mode::EventLoop),
+            _ => Err(String::from("expected threaded or event-loop")),
+        }
+    }
+}
+
+impl FromStr for Balancing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Balancing, String> {
+        match s {
+            "round-robin" => Ok(Balancing::RoundRobin),
+            "least-connections" => Ok(Balancing::LeastConnections),
+            _ => Err(String::from("expected round-robin or least-connections")),
+        }
+    }
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AccessLogFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "common" => Ok(AccessLogFormat::Common),
+            "combined" => Ok(AccessLogFormat::Combined),
+            _ => Err(String::from("expected common or combined")),
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::File(path, line) => write!(f, "{}:{}", path.display(), line),
+            Source::Environment(variable) => write!(f, "environment variable {}", variable),
+            Source::CommandLine(option) => write!(f, "command line option {}", option),
+        }
+    }
+}
+
+// returns the path of the configuration file, if given, and the values of the other options
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(Option<PathBuf>, Values), String> {
+    let mut config_file = None;
+    let mut values = Values::new();
+
+    while let Some(arg) = args.next() {
+        // both `--workers 8` and `--workers=8` are accepted
+        let (option, inline_value) = match arg.split_once('=') {
+            Some((option, value)) if option.starts_with("--") => (option.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+
+        let mut value = || inline_value.clone().or_else(|| args.next()).ok_or_else(|| format!("Option {} needs a value.", option));
+
+        if option == "--config" || option == "-c" {
+            config_file = Some(PathBuf::from(value()?));
+            continue;
+        }
+
+        let key = KEYS
+            .iter()
+            .find(|key| key.long == option || key.short == Some(option.as_str()))
+            .ok_or_else(|| format!("Unknown option {}. See --help.", option))?;
+
+        values.insert(key.name, (value()?, Source::CommandLine(option.clone())));
+    }
+
+    Ok((config_file, values))
+}
+
+fn parse_file(contents: &str, path: &Path) -> Result<Values, String> {
+    let mut values = Values::new();
+    let mut section = String::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        let error = |message: &str| format!("{}:{}: {}", path.display(), line_number, message);
+
+        let line = strip_comment(line).trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[') {
+            section = name.strip_suffix(']').ok_or_else(|| error("unterminated section header"))?.trim().to_string();
+            continue;
+        }
+
+        let (name, value) = line.split_once('=').ok_or_else(|| error("expected `key = value`"))?;
+        let name = match section.as_str() {
+            "" => name.trim().to_string(),
+            section => format!("{}.{}", section, name.trim()),
+        };
+        let value = value.trim();
+        let value = match value.strip_prefix('"') {
+            Some(quoted) => quoted.strip_suffix('"').ok_or_else(|| error("unterminated string"))?,
+            None => value,
+        };
+
+        let key = KEYS.iter().find(|key| key.name == name).ok_or_else(|| error(&format!("unknown setting `{}`", name)))?;
+
+        values.insert(key.name, (value.to_string(), Source::File(path.to_path_buf(), line_number)));
+    }
+
+    Ok(values)
+}
+
+// `#` starts a comment unless it is inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => (),
+        }
+    }
+
+    line
+}
+
+// `tls.certificate` -> `HELLO_TLS_CERTIFICATE`
+fn env_variable(name: &str) -> String {
+    format!("HELLO_{}", name.replace('.', "_").to_ascii_uppercase())
+}
+
+fn parse<T>(values: &Values, name: &str) -> Result<Option<T>, String>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    values
+        .get(name)
+        .map(|(value, source)| value.parse().map_err(|e: T::Err| invalid(value, name, source, &e.to_string())))
+        .transpose() // `Option<Result<T, E>>` into `Result<Option<T>, E>`
+}
+
+fn parse_positive(values: &Values, name: &str) -> Result<Option<usize>, String> {
+    match values.get(name) {
+        Some((value, source)) => match value.parse() {
+            Ok(0) | Err(_) => Err(invalid(value, name, source, "expected a positive integer")),
+            Ok(number) => Ok(Some(number)),
+        },
+        None => Ok(None),
+    }
+}
+
+// a number with an optional unit: `ms`, `s` (the default) or `m`
+fn parse_duration(values: &Values, name: &str) -> Result<Option<Duration>, String> {
+    let Some((value, source)) = values.get(name) else { return Ok(None) };
+
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let duration = number.parse().ok().and_then(|number: u64| match unit.trim() {
+        "ms" => Some(Duration::from_millis(number)),
+        "" | "s" => Some(Duration::from_secs(number)),
+        "m" => number.checked_mul(60).map(Duration::from_secs),
+        _ => None,
+    });
+
+    match duration {
+        Some(duration) if !duration.is_zero() => Ok(Some(duration)),
+        _ => Err(invalid(value, name, source, "expected a positive duration like 500ms, 5s or 1m")),
+    }
+}
+
+fn existing_file(value: &str, name: &str, source: &Source) -> Result<PathBuf, String> {
+    let path = PathBuf::from(value);
+
+    if path.is_file() {
+        Ok(path)
+    } else {
+        Err(invalid(&value, name, source, "no such file"))
+    }
+}
+
+fn invalid(value: &dyn fmt::Display, name: &str, source: &Source, reason: &str) -> String {
+    format!("Invalid value '{}' for {} (from {}): {}.", value, name, source, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn defaults() {
+        let settings = Settings::load(args(&[]), no_env).unwrap();
+
+        assert_eq!(settings.server.address, "127.0.0.1:7878");
+        assert_eq!(settings.server.workers, 4);
+        assert_eq!(settings.server.root, PathBuf::from("resources"));
+        assert!(settings.server.tls.is_none());
+        assert_eq!(settings.max_connections, None);
+        assert_eq!(settings.log_level, Level::Info);
+        assert_eq!(settings.access_log, AccessLogTarget::Stdout);
+    }
+
+    #[test]
+    fn command_line_overrides_environment() {
+        let env = |name: &str| match name {
+            "HELLO_WORKERS" => Some(String::from("2")),
+            "HELLO_BIND" => Some(String::from("127.0.0.1:9000")),
+            _ => None,
+        };
+
+        let settings = Settings::load(args(&["--workers=8", "-r", "resources", "--log-level", "debug", "--mode", "event-loop"]), env).unwrap();
+
+        assert_eq!(settings.server.workers, 8);
+        assert_eq!(settings.server.address, "127.0.0.1:9000");
+        assert_eq!(settings.log_level, Level::Debug);
+        assert_eq!(settings.server.mode, Mode::EventLoop);
+
+        let settings = Settings::load(args(&["--read-timeout", "250ms", "--header-timeout=2m", "--connections-per-ip", "8"]), no_env).unwrap();
+
+        assert_eq!(settings.server.read_timeout, Duration::from_millis(250));
+        assert_eq!(settings.server.header_timeout, Duration::from_secs(120));
+        assert_eq!(settings.server.connections_per_ip, Some(8));
+
+        let settings = Settings::load(args(&["--websocket-echo", "/ws", "--websocket-idle-timeout", "30s"]), no_env).unwrap();
+
+        assert!(settings.server.websockets.get("/ws").is_some());
+        assert!(settings.server.websockets.get("/echo").is_none());
+        assert_eq!(settings.server.websocket_idle_timeout, Duration::from_secs(30));
+
+        let settings = Settings::load(args(&["--max-body-size", "4096", "--upload-dir", "uploads"]), no_env).unwrap();
+
+        assert_eq!(settings.server.max_body_size, 4096);
+        assert_eq!(settings.server.upload_dir, PathBuf::from("uploads"));
+        assert!(settings.server.handlers.get("POST", "/body").is_none());
+
+        let settings = Settings::load(args(&["--body-echo", "/body"]), no_env).unwrap();
+
+        assert!(settings.server.handlers.get("POST", "/body").is_some());
+        assert!(settings.server.handlers.get("PUT", "/body").is_some());
+
+        let settings = Settings::load(args(&["--sites", "example.com=resources, *.example.org = resources", "--strict-hosts", "true"]), no_env).unwrap();
+
+        assert!(settings.server.virtual_hosts.is_strict());
+        assert_eq!(settings.server.virtual_hosts.sites().map(|(host, _)| host).collect::<Vec<_>>(), ["example.com", "*.example.org"]);
+    }
+
+    #[test]
+    fn configuration_file() {
+        let path = env::temp_dir().join(format!("hello-config-test-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "# the test configuration\nbind = \"127.0.0.1:8080\" # loopback only\nworkers = 3\n\n[log]\naccess = off\naccess_format = \"common\"\n",
+        )
+        .unwrap();
+
+        let env = |name: &str| match name {
+            "HELLO_WORKERS" => Some(String::from("5")), // the environment wins over the file
+            _ => None,
+        };
+
+        let settings = Settings::load(args(&["--config", path.to_str().unwrap()]), env).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(settings.server.address, "127.0.0.1:8080");
+        assert_eq!(settings.server.workers, 5);
+        assert_eq!(settings.access_log, AccessLogTarget::Off);
+        assert_eq!(settings.access_format, AccessLogFormat::Common);
+    }
+
+    #[test]
+    fn errors_point_at_the_source() {
+        let error = Settings::load(args(&["--workers", "0"]), no_env).unwrap_err();
+        assert_eq!(error, "Invalid value '0' for workers (from command line option --workers): expected a positive integer.");
+
+        let env = |name: &str| (name == "HELLO_LOG_LEVEL").then(|| String::from("loud"));
+        let error = Settings::load(args(&[]), env).unwrap_err();
+        assert!(error.starts_with("Invalid value 'loud' for log.level (from environment variable HELLO_LOG_LEVEL)"), "{}", error);
+
+        let error = parse_file("[tls]\ncertificate = cert.pem\nciphers = all\n", Path::new("hello.toml")).unwrap_err();
+        assert_eq!(error, "hello.toml:3: unknown setting `tls.ciphers`");
+    }
+
+    #[test]
+    fn invalid_command_lines() {
+        assert_eq!(Settings::load(args(&["--verbose"]), no_env).unwrap_err(), "Unknown option --verbose. See --help.");
+        assert_eq!(Settings::load(args(&["--bind"]), no_env).unwrap_err(), "Option --bind needs a value.");
+        assert!(Settings::load(args(&["--bind", "nowhere"]), no_env).unwrap_err().contains("expected HOST:PORT"));
+        assert!(Settings::load(args(&["--root", "does/not/exist"]), no_env).unwrap_err().contains("not a directory"));
+        assert!(Settings::load(args(&["--tls-cert", "cert.pem"]), no_env).unwrap_err().starts_with("TLS needs both"));
+        assert!(Settings::load(args(&["--read-timeout", "5h"]), no_env).unwrap_err().contains("expected a positive duration"));
+        assert!(Settings::load(args(&["--read-timeout", "999999999999999999m"]), no_env).unwrap_err().contains("expected a positive duration"));
+        assert!(Settings::load(args(&["--proxy", "127.0.0.1:9000,nowhere"]), no_env).unwrap_err().starts_with("Invalid value 'nowhere' for proxy.upstreams"));
+        assert!(Settings::load(args(&["--proxy", "127.0.0.1:9000", "--proxy-balancing", "random"]), no_env).unwrap_err().contains("expected round-robin or least-connections"));
+        assert!(Settings::load(args(&["--proxy", "127.0.0.1:9000", "--proxy-max-response-size", "0"]), no_env).unwrap_err().contains("expected a positive integer"));
+        assert!(Settings::load(args(&["--sites", "example.com"]), no_env).unwrap_err().contains("expected HOST=DIR pairs"));
+        assert!(Settings::load(args(&["--sites", "example.com=does/not/exist"]), no_env).unwrap_err().contains("not a directory"));
+        assert!(Settings::load(args(&["--strict-hosts", "yes"]), no_env).unwrap_err().starts_with("Invalid value 'yes' for hosts.strict"));
+        assert_eq!(
+            Settings::load(args(&["--basic-auth", "alice:secret,bob"]), no_env).unwrap_err(),
+            "Invalid value '...' for auth.users (from command line option --basic-auth): expected NAME:PASSWORD pairs separated by commas."
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Handlers for the requests of the application, registered by method and path in `Config::handlers`, e.g. to receive the submitted forms.
+//!
+//! A handler gets the request with its body already read and parsed (see the `body` module); the middleware runs around it like around the built-in pages.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::body::Body;
+use crate::json::Json;
+use crate::request::Request;
+use crate::response::Response;
+
+/// A handler: answers the request, body included.
+pub type Handler = Arc<dyn Fn(&Request) -> Response + Send + Sync>;
+
+/// The handlers of the server by method and path.
+#[derive(Clone, Default)]
+pub struct Handlers {
+    handlers: Vec<(String, String, Handler)>,
+}
+
+impl Handlers {
+    pub fn new() -> Handlers {
+        Handlers::default()
+    }
+
+    /// Adds a handler for `method` requests to `path`, replacing the one already there, if any.
+    pub fn route<F>(mut self, method: &str, path: &str, handler: F) -> Handlers
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.handlers.retain(|(m, p, _)| m != method || p != path);
+        self.handlers.push((method.to_string(), path.to_string(), Arc::new(handler)));
+        self
+    }
+
+    /// Returns the handler of `method` requests to `path`, if there is one.
+    pub fn get(&self, method: &str, path: &str) -> Option<&Handler> {
+        self.handlers.iter().find(|(m, p, _)| m == method && p == path).map(|(_, _, handler)| handler)
+    }
+}
+
+impl fmt::Debug for Handlers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // the handlers are closures, so the routes are all there is to show
+        f.debug_list().entries(self.handlers.iter().map(|(method, path, _)| format!("{} {}", method, path))).finish()
+    }
+}
+
+/// A handler that describes the body it has received, as JSON: the fields and the uploaded files of a form, the value of a JSON body, or the size of any other.
+///
+/// # Example
+/// ```
+/// use hello::handlers;
+/// use hello::request::Request;
+///
+/// let response = handlers::echo(&Request::parse("POST /body HTTP/1.1\r\n\r\n"));
+///
+/// assert_eq!(response.get_header("Content-Type"), Some("application/json"));
+/// assert_eq!(response.body_bytes(), b"{}");
+/// ```
+pub fn echo(request: &Request) -> Response {
+    let description = match request.body() {
+        Body::None => Json::Object(BTreeMap::new()),
+        Body::Form(form) => {
+            let mut fields: BTreeMap<String, Json> = BTreeMap::new();
+
+            // a field may come more than once, e.g. for a multiple choice
+            for (name, value) in form.fields() {
+                match fields.entry(name.clone()).or_insert_with(|| Json::Array(Vec::new())) {
+                    Json::Array(values) => values.push(Json::from(value.as_str())),
+                    _ => unreachable!("every field is a list of values"),
+                }
+            }
+
+            let files: Vec<Json> = form
+                .files()
+                .map(|file| {
+                    [
+                        ("name", Json::from(file.name())),
+                        ("filename", Json::from(file.filename())),
+                        ("content_type", Json::from(file.content_type())),
+                        ("size", Json::from(file.size())),
+                    ]
+                    .into_iter()
+                    .collect()
+                })
+                .collect();
+
+            [("fields", Json::Object(fields)), ("files", Json::from(files))].into_iter().collect()
+        }
+        Body::Json(json) => [("json", json.clone())].into_iter().collect(),
+        Body::Raw(bytes) => [("bytes", Json::from(bytes.len() as u64))].into_iter().collect(),
+    };
+
+    Response::new(200).header("Content-Type", "application/json").body(description.to_string())
+}
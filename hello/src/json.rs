@@ -0,0 +1,529 @@
+//! A minimal JSON parser and serializer (RFC 8259), enough for the request bodies and the small responses of the handlers.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+// a deeply nested document would overflow the stack of the recursive parser otherwise
+const MAX_DEPTH: usize = 128;
+
+/// A JSON value. The numbers are kept as `f64`, like JavaScript does; the members of an object are sorted by their names.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+struct Parser<'s> {
+    text: &'s str,
+    position: usize, // in bytes
+    depth: usize,
+}
+
+impl Json {
+    /// Parses `text`, which must hold a single value, optionally surrounded by whitespace.
+    ///
+    /// # Errors
+    /// Fails on a syntax error, telling the byte offset it's at.
+    ///
+    /// # Example
+    /// ```
+    /// use hello::json::Json;
+    ///
+    /// let json = Json::parse(r#"{"name": "Ferris", "legs": 10, "tags": ["crab", "mascot"]}"#).unwrap();
+    ///
+    /// assert_eq!(json.get("name").and_then(Json::as_str), Some("Ferris"));
+    /// assert_eq!(json.get("legs").and_then(Json::as_i64), Some(10));
+    /// assert_eq!(json.get("tags").and_then(|tags| tags.at(1)), Some(&Json::from("mascot")));
+    /// assert_eq!(json.to_string(), r#"{"legs":10,"name":"Ferris","tags":["crab","mascot"]}"#);
+    /// ```
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { text, position: 0, depth: 0 };
+
+        let value = parser.value()?;
+        parser.skip_whitespace();
+
+        match parser.peek() {
+            Some(c) => Err(parser.error(&format!("unexpected '{}' after the value", c))),
+            None => Ok(value),
+        }
+    }
+
+    /// Looks `key` up if this is an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.get(key),
+            _ => None,
+        }
+    }
+
+    /// Returns the element at `index` if this is an array.
+    pub fn at(&self, index: usize) -> Option<&Json> {
+        match self {
+            Json::Array(elements) => elements.get(index),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Json::Null
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    /// Returns the number if it is an integer that an `i64` holds exactly.
+    pub fn as_i64(&self) -> Option<i64> {
+        // 2^63 itself is out of range, but it's the closest `f64` to `i64::MAX`
+        self.as_f64().filter(|number| number.fract() == 0.0 && *number >= i64::MIN as f64 && *number < i64::MAX as f64).map(|number| number as i64)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(elements) => Some(elements),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&BTreeMap<String, Json>> {
+        match self {
+            Json::Object(members) => Some(members),
+            _ => None,
+        }
+    }
+}
+
+/// Serializes the value compactly, without any whitespace. The numbers JSON has no notation for (infinities and NaN) become `null`.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(number) if number.is_finite() => write!(f, "{}", number),
+            Json::Number(_) => write!(f, "null"),
+            Json::String(string) => write_string(f, string),
+            Json::Array(elements) => {
+                write!(f, "[")?;
+
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+
+                for (i, (name, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, string: &str) -> fmt::Result {
+    write!(f, "\"")?;
+
+    for c in string.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if c < ' ' => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+
+    write!(f, "\"")
+}
+
+impl From<&str> for Json {
+    fn from(string: &str) -> Json {
+        Json::String(string.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(string: String) -> Json {
+        Json::String(string)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Json {
+        Json::Bool(value)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(number: f64) -> Json {
+        Json::Number(number)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(number: i64) -> Json {
+        Json::Number(number as f64)
+    }
+}
+
+impl From<u64> for Json {
+    fn from(number: u64) -> Json {
+        Json::Number(number as f64)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Json {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(elements: Vec<T>) -> Json {
+        Json::Array(elements.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Collects name-value pairs into an object.
+impl<K: Into<String>, V: Into<Json>> FromIterator<(K, V)> for Json {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(members: I) -> Json {
+        Json::Object(members.into_iter().map(|(name, value)| (name.into(), value.into())).collect())
+    }
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('{') => self.nested(Parser::object),
+            Some('[') => self.nested(Parser::array),
+            Some('"') => self.string().map(Json::String),
+            Some('-' | '0'..='9') => self.number(),
+            Some(_) if self.eat("null") => Ok(Json::Null),
+            Some(_) if self.eat("true") => Ok(Json::Bool(true)),
+            Some(_) if self.eat("false") => Ok(Json::Bool(false)),
+            Some(c) => Err(self.error(&format!("unexpected '{}'", c))),
+            None => Err(self.error("unexpected end of the input")),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Json, String>) -> Result<Json, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+
+        value
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut members = BTreeMap::new();
+
+        self.skip_whitespace();
+        if self.eat("}") {
+            return Ok(Json::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("expected a member name"));
+            }
+
+            let name = self.string()?;
+
+            self.skip_whitespace();
+            self.expect(':')?;
+
+            members.insert(name, self.value()?); // the last one wins if a name is repeated
+
+            self.skip_whitespace();
+            if self.eat("}") {
+                return Ok(Json::Object(members));
+            }
+            self.separator('}')?;
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut elements = Vec::new();
+
+        self.skip_whitespace();
+        if self.eat("]") {
+            return Ok(Json::Array(elements));
+        }
+
+        loop {
+            elements.push(self.value()?);
+
+            self.skip_whitespace();
+            if self.eat("]") {
+                return Ok(Json::Array(elements));
+            }
+            self.separator(']')?;
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+
+        loop {
+            let c = self.next().ok_or_else(|| self.error("unterminated string"))?;
+
+            match c {
+                '"' => return Ok(string),
+                '\\' => {
+                    let escaped = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape sequence")),
+                    };
+
+                    string.push(escaped);
+                }
+                c if c < ' ' => return Err(self.error("unescaped control character in a string")),
+                c => string.push(c),
+            }
+        }
+    }
+
+    // the part of `\uXXXX` after `\u`; the characters outside the Basic Multilingual Plane come as a surrogate pair of two escapes
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error("unpaired surrogate"));
+        }
+
+        if !self.eat("\\u") {
+            return Err(self.error("unpaired surrogate"));
+        }
+
+        let low = self.hex4()?;
+
+        if !(0xdc00..0xe000).contains(&low) {
+            return Err(self.error("unpaired surrogate"));
+        }
+
+        char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)).ok_or_else(|| self.error("invalid surrogate pair"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.position..self.position + 4).filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()));
+        let digits = digits.ok_or_else(|| self.error("expected four hexadecimal digits"))?;
+
+        self.position += 4;
+
+        Ok(u32::from_str_radix(digits, 16).expect("The digits are hexadecimal."))
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+
+        self.eat("-");
+
+        // no leading zeros, and at least one digit in each part
+        if !self.eat("0") && self.digits() == 0 {
+            return Err(self.error("expected a digit"));
+        }
+
+        if self.eat(".") && self.digits() == 0 {
+            return Err(self.error("expected a digit after the decimal point"));
+        }
+
+        if self.eat("e") || self.eat("E") {
+            if !self.eat("+") {
+                self.eat("-");
+            }
+
+            if self.digits() == 0 {
+                return Err(self.error("expected a digit in the exponent"));
+            }
+        }
+
+        // the grammar above is stricter than what `f64` accepts, so parsing can't fail (a huge number becomes an infinity)
+        Ok(Json::Number(self.text[start..self.position].parse().expect("The number is valid.")))
+    }
+
+    fn digits(&mut self) -> usize {
+        let count = self.text[self.position..].bytes().take_while(u8::is_ascii_digit).count();
+        self.position += count;
+
+        count
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+
+        Some(c)
+    }
+
+    fn eat(&mut self, expected: &str) -> bool {
+        let found = self.text[self.position..].starts_with(expected);
+
+        if found {
+            self.position += expected.len();
+        }
+
+        found
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.position += 1;
+                Ok(())
+            }
+            _ => Err(self.error(&format!("expected '{}', found {}", expected, self.found()))),
+        }
+    }
+
+    // the comma between the elements of an array or the members of an object, which `end` would close instead
+    fn separator(&mut self, end: char) -> Result<(), String> {
+        match self.eat(",") {
+            true => Ok(()),
+            false => Err(self.error(&format!("expected ',' or '{}', found {}", end, self.found()))),
+        }
+    }
+
+    fn found(&self) -> String {
+        match self.peek() {
+            Some(c) => format!("'{}'", c),
+            None => String::from("the end of the input"),
+        }
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("byte {}: {}", self.position, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_values() {
+        assert_eq!(Json::parse(" null ").unwrap(), Json::Null);
+        assert_eq!(Json::parse("true").unwrap(), Json::Bool(true));
+        assert_eq!(Json::parse("-12.5e1").unwrap(), Json::Number(-125.0));
+        assert_eq!(Json::parse("0").unwrap(), Json::Number(0.0));
+        assert_eq!(Json::parse(r#""tab\t \"quoted\" \u00e9 \ud83e\udd80""#).unwrap(), Json::from("tab\t \"quoted\" é 🦀"));
+        assert_eq!(Json::parse("[]").unwrap(), Json::Array(Vec::new()));
+        assert_eq!(Json::parse("{}").unwrap(), Json::Object(BTreeMap::new()));
+
+        let json = Json::parse("{\"a\": [1, {\"b\": null}], \"a\": [true]}").unwrap();
+        assert_eq!(json, [("a", vec![true])].into_iter().collect()); // the last one wins
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        for (text, error) in [
+            ("", "byte 0: unexpected end of the input"),
+            ("[1, 2", "byte 5: expected ',' or ']', found the end of the input"),
+            ("{\"a\": 1 \"b\": 2}", "byte 8: expected ',' or '}', found '\"'"),
+            ("[1,]", "byte 3: unexpected ']'"),
+            ("{\"a\" 1}", "byte 5: expected ':', found '1'"),
+            ("{a: 1}", "byte 1: expected a member name"),
+            ("01", "byte 1: unexpected '1' after the value"),
+            ("1.", "byte 2: expected a digit after the decimal point"),
+            ("\"\\x\"", "byte 3: invalid escape sequence"),
+            ("\"\\ud83e\"", "byte 7: unpaired surrogate"),
+            ("\"line\nbreak\"", "byte 6: unescaped control character in a string"),
+            ("nul", "byte 0: unexpected 'n'"),
+        ] {
+            assert_eq!(Json::parse(text).unwrap_err(), error, "{}", text);
+        }
+
+        assert!(Json::parse(&"[".repeat(MAX_DEPTH + 1)).unwrap_err().ends_with("nested too deeply"));
+        assert!(Json::parse(&format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH))).is_ok());
+    }
+
+    #[test]
+    fn accessors() {
+        let json = Json::parse(r#"{"count": 3, "ratio": 0.5, "big": 1e300, "on": false, "list": [null]}"#).unwrap();
+
+        assert_eq!(json.get("count").and_then(Json::as_i64), Some(3));
+        assert_eq!(json.get("ratio").and_then(Json::as_i64), None);
+        assert_eq!(json.get("ratio").and_then(Json::as_f64), Some(0.5));
+        assert_eq!(json.get("big").and_then(Json::as_i64), None);
+        assert_eq!(json.get("on").and_then(Json::as_bool), Some(false));
+        assert!(json.get("list").and_then(|list| list.at(0)).is_some_and(Json::is_null));
+        assert_eq!(json.get("count").and_then(Json::as_str), None);
+        assert_eq!(json.get("missing"), None);
+        assert_eq!(json.as_object().map(BTreeMap::len), Some(5));
+    }
+
+    #[test]
+    fn serializes() {
+        let json: Json = [
+            ("text", Json::from("\"quoted\"\n\u{1}")),
+            ("numbers", Json::from(vec![Json::from(1.5), Json::from(-2_i64), Json::from(f64::NAN)])),
+            ("none", Json::from(None::<bool>)),
+        ]
+        .into_iter()
+        .collect();
+
+        let text = json.to_string();
+        assert_eq!(text, r#"{"none":null,"numbers":[1.5,-2,null],"text":"\"quoted\"\n\u0001"}"#);
+        assert_eq!(Json::parse(&text).unwrap().get("text"), json.get("text"));
+    }
+}
@@ -1,11 +1,131 @@
-use std::sync::{Arc, mpsc, Mutex};
+use std::any::Any;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, mpsc, Mutex, RwLock, Weak};
 use std::thread;
+use std::time::Duration;
+
+use scheduler::{Entry, Scheduler, Ticket};
+
+mod base64;
+pub mod body;
+pub mod config;
+pub mod handlers;
+pub mod json;
+pub mod logging;
+pub mod metrics;
+pub mod middleware;
+pub mod proxy;
+#[cfg(target_os = "linux")]
+mod reactor;
+pub mod request;
+pub mod response;
+mod scheduler;
+pub mod server;
+mod sha1;
+pub mod template;
+pub mod virtual_hosts;
+pub mod websocket;
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+/// A callback invoked whenever a job panics. Receives the id of the worker that ran the job and the panic payload.
+pub type PanicHook = Box<dyn Fn(usize, &(dyn Any + Send)) + Send + Sync + 'static>;
+
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>,
+    workers: Mutex<Vec<Worker>>, // behind a mutex because workers are spawned on demand from `&self` methods
+    shared: Arc<Shared>,
+}
+
+/// A `ThreadPool` factory, which can be used in order to configure the properties of a new pool.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+///
+/// use hello::ThreadPool;
+///
+/// // starts with two workers, grows up to eight while there is more work than workers and shrinks back after the extra workers have been idle for a second
+/// let pool = ThreadPool::builder()
+///     .min_workers(2)
+///     .max_workers(8)
+///     .idle_timeout(Duration::from_secs(1))
+///     .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Builder {
+    min_workers: usize,
+    max_workers: usize,
+    idle_timeout: Duration,
+    queue_capacity: Option<usize>,
+}
+
+/// A snapshot of the state of a `ThreadPool`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolMetrics {
+    /// The number of workers in the pool.
+    pub workers: usize,
+    /// The number of workers running a job.
+    pub active_workers: usize,
+    /// The number of workers waiting for a job.
+    pub idle_workers: usize,
+    /// The number of jobs waiting for a free worker.
+    pub queued_jobs: usize,
+}
+
+/// How urgent a job is. A worker only takes a job once there are no jobs of a higher priority waiting; jobs of the same priority run in the order they were submitted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// For the jobs that must not wait behind the others, like health checks and monitoring.
+    High,
+    /// For everything else.
+    #[default]
+    Normal,
+    /// For the jobs nobody is waiting for eagerly, or slow anyway: they only get a worker when there's nothing else to do.
+    Background,
+}
+
+/// A handle to cancel a job submitted with `ThreadPool::execute_with` or `ThreadPool::try_execute_with` while it is still queued.
+///
+/// Dropping the token does not cancel the job.
+///
+/// # Example
+/// ```
+/// use hello::{Priority, ThreadPool};
+///
+/// let pool = ThreadPool::new(1);
+/// let (sender, receiver) = std::sync::mpsc::channel();
+///
+/// pool.execute(move || receiver.recv().unwrap()); // keeps the only worker busy, so the job below can't start
+/// let token = pool.execute_with(Priority::Background, || println!("never printed"));
+///
+/// assert!(token.cancel());
+/// assert!(token.is_cancelled());
+///
+/// sender.send(()).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    ticket: Arc<Ticket>,
+    priority: Priority,
+    shared: Weak<Shared>, // a token doesn't keep the pool alive
+}
+
+/// A handle to the metrics of a `ThreadPool`, see `ThreadPool::monitor`.
+///
+/// It does not keep the pool alive: once the pool is dropped, it reports no workers.
+#[derive(Clone)]
+pub struct Monitor {
+    shared: Weak<Shared>, // neither the pool nor its queues, like a `CancellationToken`
+}
+
+// the state every worker has access to
+struct Shared {
+    scheduler: Scheduler,
+    panic_hook: RwLock<Option<PanicHook>>,
 }
 
 struct Worker {
@@ -13,9 +133,38 @@ struct Worker {
     thread: Option<thread::JoinHandle<()>>,
 }
 
-enum Message {
-    NewJob(Job),
-    Terminate,
+/// An owned permission to join on a job submitted with `ThreadPool::spawn` (i.e. to block on its completion).
+///
+/// Dropping the handle detaches the job: it still runs, but its result is discarded.
+pub struct JobHandle<T> {
+    receiver: mpsc::Receiver<thread::Result<T>>,
+}
+
+/// A scope to spawn jobs borrowing non-`'static` data in. See `ThreadPool::scope`.
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    data: Arc<ScopeData>,
+    // both lifetimes are invariant (the same way as in `std::thread::Scope`) so that the compiler can neither shrink `'env` nor extend `'scope`
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+/// An owned permission to join on a job submitted with `Scope::spawn`.
+pub struct ScopedJobHandle<'scope, T> {
+    receiver: mpsc::Receiver<thread::Result<T>>,
+    data: &'scope ScopeData,
+}
+
+struct ScopeData {
+    running_jobs: Mutex<usize>,
+    all_finished: Condvar,
+    unhandled_panics: AtomicUsize,
+}
+
+// the part of a scoped job that reports back; being a separate type with `Drop` it notifies the scope even if the job is dropped without being run
+struct ScopedSender<T> {
+    sender: Option<mpsc::Sender<thread::Result<T>>>,
+    data: Arc<ScopeData>,
 }
 
 impl ThreadPool {
@@ -23,85 +172,569 @@ impl ThreadPool {
     ///
     /// `size` is the number of threads in the pool.
     ///
+    /// The job queue of the pool is unbounded, see `ThreadPool::with_queue_capacity` for a pool that pushes back on its clients.
+    ///
     /// # Panics
     ///
     /// `new` function will panic if `size` is zero.
     pub fn new(size: usize) -> ThreadPool {
-        assert!(size > 0);
+        ThreadPool::builder().size(size).build()
+    }
 
-        let (sender, receiver) = mpsc::channel();
+    /// Creates a new `ThreadPool` with a bounded job queue.
+    ///
+    /// `size` is the number of threads in the pool.
+    /// `capacity` is the maximum number of jobs waiting for a free worker. Once it is reached `execute` blocks and `try_execute` fails until some of the waiting jobs are picked up.
+    ///
+    /// # Panics
+    ///
+    /// `with_queue_capacity` function will panic if `size` or `capacity` is zero.
+    pub fn with_queue_capacity(size: usize, capacity: usize) -> ThreadPool {
+        ThreadPool::builder().size(size).queue_capacity(capacity).build()
+    }
 
-        let receiver = Arc::new(Mutex::new(receiver));
+    /// Creates a `Builder` to configure a new `ThreadPool` with.
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
 
-        let mut workers = Vec::with_capacity(size);
+    /// Changes the number of workers in the pool to exactly `size`.
+    ///
+    /// Missing workers are spawned right away. Surplus workers retire as soon as they finish the job at hand, if any.
+    ///
+    /// # Panics
+    ///
+    /// `resize` function will panic if `size` is zero.
+    pub fn resize(&self, size: usize) {
+        self.set_bounds(size, size);
+    }
 
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
-        }
+    /// Changes the minimum and the maximum number of workers in the pool.
+    ///
+    /// # Panics
+    ///
+    /// `set_bounds` function will panic if `min_workers` is zero or greater than `max_workers`.
+    pub fn set_bounds(&self, min_workers: usize, max_workers: usize) {
+        assert!(min_workers > 0);
+        assert!(min_workers <= max_workers);
 
-        ThreadPool {
-            workers,
-            sender,
+        self.shared.scheduler.set_bounds(min_workers, max_workers);
+
+        while self.shared.scheduler.needs_worker() && self.spawn_worker() {}
+    }
+
+    /// Returns the current number of workers, busy and idle, and of the jobs waiting for them.
+    pub fn metrics(&self) -> PoolMetrics {
+        self.monitor().metrics()
+    }
+
+    /// Returns a handle to watch the metrics of the pool with. Unlike a reference to the pool it can be moved into the jobs themselves.
+    pub fn monitor(&self) -> Monitor {
+        Monitor {
+            shared: Arc::downgrade(&self.shared),
         }
     }
 
+    /// Sets the callback to report jobs that panicked to.
+    ///
+    /// `hook` receives the id of the worker and the panic payload. A panicking job never takes its worker down, the hook is purely informational. Without a hook the panics are logged as errors.
+    pub fn set_panic_hook<F>(&self, hook: F)
+    where
+        F: Fn(usize, &(dyn Any + Send)) + Send + Sync + 'static,
+    {
+        *self.shared.panic_hook.write().expect("The panic hook lock is in a poisoned state.") = Some(Box::new(hook));
+    }
+
     /// Designates an executor for `f` from the pool.
     ///
     /// `f` is the closure to execute.
     ///
-    /// # Panics
-    ///
-    /// `execute` function will panic if the internal MPSC channel is broken.
+    /// If the job queue is bounded and full, `execute` blocks until there is room for `f`.
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static, // `'static` as a trait bound means the type does not contain any non-static references, i.e. the receiver can hold on to the type for as long as they want and it will never become invalid until they drop it
     {
         let job = Box::new(f);
 
-        self.sender.send(Message::NewJob(job)).expect("MPSC channel is broken.");
+        self.send_job(job);
+    }
+
+    /// Designates an executor for `f` from the pool unless the job queue is full.
+    ///
+    /// `f` is the closure to execute.
+    ///
+    /// # Errors
+    ///
+    /// If the job queue is bounded and full, `f` is handed back untouched as `Err(f)`, so that the caller can decide what to do with it (run it elsewhere, retry later or drop it).
+    pub fn try_execute<F>(&self, f: F) -> Result<(), F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if !self.shared.scheduler.try_reserve() {
+            return Err(f);
+        }
+
+        self.shared.scheduler.push_reserved(Entry { job: Box::new(f), ticket: None }, Priority::Normal);
+        self.grow_if_needed();
+
+        Ok(())
+    }
+
+    /// Designates an executor for `f` from the pool, ahead of the jobs of a lower `priority`, and returns a token to cancel it with as long as it hasn't started.
+    ///
+    /// `f` is the closure to execute.
+    ///
+    /// If the job queue is bounded and full, `execute_with` blocks until there is room for `f`, whatever its priority.
+    pub fn execute_with<F>(&self, priority: Priority, f: F) -> CancellationToken
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared.scheduler.reserve();
+
+        self.push_cancellable(priority, Box::new(f))
+    }
+
+    /// Designates an executor for `f` from the pool, ahead of the jobs of a lower `priority`, unless the job queue is full. Returns a token to cancel the job with as long as it hasn't started.
+    ///
+    /// `f` is the closure to execute.
+    ///
+    /// # Errors
+    ///
+    /// If the job queue is bounded and full, `f` is handed back untouched as `Err(f)`, the same way as in `try_execute`.
+    pub fn try_execute_with<F>(&self, priority: Priority, f: F) -> Result<CancellationToken, F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if !self.shared.scheduler.try_reserve() {
+            return Err(f);
+        }
+
+        Ok(self.push_cancellable(priority, Box::new(f)))
+    }
+
+    /// Returns the number of jobs waiting for a free worker.
+    pub fn queued_jobs(&self) -> usize {
+        self.shared.scheduler.queued()
+    }
+
+    /// Designates an executor for `f` from the pool and returns a handle that can be used to obtain the value returned by `f`.
+    ///
+    /// `f` is the closure to execute.
+    ///
+    /// A panic inside `f` does not take the worker down with it: it is caught and its payload is handed over to `JobHandle::join`.
+    pub fn spawn<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+
+        self.send_job(Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f)); // `AssertUnwindSafe` tells the compiler we're fine with observing whatever state `f` has left behind if it panicked - the only thing we do with it is passing the payload back to the caller
+
+            let _ = sender.send(result); // the handle may have been dropped in which case nobody is interested in the result
+        }));
+
+        JobHandle { receiver }
+    }
+
+    /// Creates a scope for spawning jobs that can borrow non-`'static` data from the caller's stack.
+    ///
+    /// `f` receives a `Scope` to spawn the jobs with. All the jobs spawned within the scope are guaranteed to finish before `scope` returns, which is what makes the borrowing sound.
+    ///
+    /// # Example
+    /// ```
+    /// use hello::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let numbers = vec![1, 2, 3, 4];
+    ///
+    /// let sum: i32 = pool.scope(|s| {
+    ///     let handles: Vec<_> = numbers.chunks(2).map(|chunk| s.spawn(move || chunk.iter().sum::<i32>())).collect();
+    ///
+    ///     handles.into_iter().map(|h| h.join().unwrap()).sum()
+    /// });
+    ///
+    /// assert_eq!(sum, 10);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// `scope` function will panic if `f` panicked or if any of the jobs spawned within the scope panicked and was not joined. Note that calling `scope` from inside a job running on the same pool may deadlock if all the workers end up waiting.
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R, // higher-ranked trait bound: `f` has to work for any `'scope` we choose, so neither the scope itself nor the handles it gives out can escape the closure
+    {
+        let scope = Scope {
+            pool: self,
+            data: Arc::new(ScopeData {
+                running_jobs: Mutex::new(0),
+                all_finished: Condvar::new(),
+                unhandled_panics: AtomicUsize::new(0),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+
+        scope.data.wait_for_all();
+
+        match result {
+            Err(payload) => panic::resume_unwind(payload),
+            Ok(_) if scope.data.unhandled_panics.load(Ordering::SeqCst) > 0 => {
+                panic!("A job spawned in the scope panicked.")
+            }
+            Ok(result) => result,
+        }
+    }
+
+    fn send_job(&self, job: Job) {
+        self.shared.scheduler.reserve();
+        self.shared.scheduler.push_reserved(Entry { job, ticket: None }, Priority::Normal);
+        self.grow_if_needed();
+    }
+
+    // the place in the queue must have been reserved already
+    fn push_cancellable(&self, priority: Priority, job: Job) -> CancellationToken {
+        let ticket = Arc::new(Ticket::new());
+
+        self.shared.scheduler.push_reserved(
+            Entry {
+                job,
+                ticket: Some(Arc::clone(&ticket)),
+            },
+            priority,
+        );
+        self.grow_if_needed();
+
+        CancellationToken {
+            ticket,
+            priority,
+            shared: Arc::downgrade(&self.shared),
+        }
+    }
+
+    fn grow_if_needed(&self) {
+        if self.shared.scheduler.needs_worker() {
+            self.spawn_worker();
+        }
+    }
+
+    // returns `false` if the pool is already at its maximum size
+    fn spawn_worker(&self) -> bool {
+        let Some(id) = self.shared.scheduler.claim_slot() else {
+            return false;
+        };
+
+        let mut workers = self.workers.lock().expect("The workers mutex is in a poisoned state.");
+
+        workers.retain(|worker| worker.thread.as_ref().is_some_and(|thread| !thread.is_finished())); // forget the workers that have retired
+        workers.push(Worker::new(id, Arc::clone(&self.shared)));
+
+        true
     }
 }
 
-impl Drop for ThreadPool {
+impl Monitor {
+    /// The same as `ThreadPool::metrics`.
+    pub fn metrics(&self) -> PoolMetrics {
+        let Some(shared) = self.shared.upgrade() else {
+            return PoolMetrics::default();
+        };
+
+        let workers = shared.scheduler.workers();
+        let active_workers = shared.scheduler.active().min(workers); // the counters are updated independently, so a snapshot can be slightly off
+
+        PoolMetrics {
+            workers,
+            active_workers,
+            idle_workers: workers - active_workers,
+            queued_jobs: shared.scheduler.queued(),
+        }
+    }
+}
+
+impl fmt::Debug for Monitor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Monitor").field("metrics", &self.metrics()).finish()
+    }
+}
+
+impl Priority {
+    // the number of lanes the scheduler keeps
+    pub(crate) const COUNT: usize = 3;
+}
+
+impl CancellationToken {
+    /// Cancels the job unless a worker has already started it. A cancelled job is taken out of the queue (making room for another one) and dropped without running.
+    ///
+    /// Returns `true` if the job has been cancelled by this call, `false` if it has started (or even finished) already, or has been cancelled before.
+    pub fn cancel(&self) -> bool {
+        if !self.ticket.cancel() {
+            return false;
+        }
+
+        // without the pool there's no queue to remove the job from, only the workers finishing the remaining jobs might still come across it
+        if let Some(shared) = self.shared.upgrade() {
+            shared.scheduler.remove(&self.ticket, self.priority);
+        }
+
+        true
+    }
+
+    /// Returns `true` if the job has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.ticket.is_cancelled()
+    }
+
+    /// Returns `true` if the job is still waiting for a worker, i.e. can still be cancelled.
+    pub fn is_queued(&self) -> bool {
+        self.ticket.is_queued()
+    }
+
+    /// The priority the job has been submitted with.
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+}
+
+impl Builder {
+    /// Creates a new `Builder` with the default configuration: between one worker and one worker per available CPU, retiring after a minute of idling, with an unbounded job queue.
+    pub fn new() -> Builder {
+        Builder {
+            min_workers: 1,
+            max_workers: thread::available_parallelism().map_or(1, |parallelism| parallelism.get()),
+            idle_timeout: Duration::from_secs(60),
+            queue_capacity: None,
+        }
+    }
+
+    /// Sets both the minimum and the maximum number of workers, i.e. makes the pool fixed-size.
+    pub fn size(self, size: usize) -> Builder {
+        self.min_workers(size).max_workers(size)
+    }
+
+    /// Sets the number of workers the pool starts with and never goes below.
+    pub fn min_workers(mut self, min_workers: usize) -> Builder {
+        self.min_workers = min_workers;
+        self
+    }
+
+    /// Sets the number of workers the pool may grow up to when there are more jobs than workers to take them.
+    pub fn max_workers(mut self, max_workers: usize) -> Builder {
+        self.max_workers = max_workers;
+        self
+    }
+
+    /// Sets for how long a worker above the minimum may stay idle before it retires.
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Builder {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Sets the maximum number of jobs waiting for a free worker. Once it is reached `ThreadPool::execute` blocks and `ThreadPool::try_execute` fails until some of the waiting jobs are picked up.
+    pub fn queue_capacity(mut self, capacity: usize) -> Builder {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// Creates the `ThreadPool` and spawns its initial workers.
+    ///
+    /// # Panics
+    ///
+    /// `build` function will panic if the minimum number of workers is zero or greater than the maximum, or if the queue capacity is zero.
+    pub fn build(self) -> ThreadPool {
+        assert!(self.min_workers > 0);
+        assert!(self.min_workers <= self.max_workers);
+        assert!(self.queue_capacity != Some(0));
+
+        let pool = ThreadPool {
+            workers: Mutex::new(Vec::with_capacity(self.max_workers)),
+            shared: Arc::new(Shared {
+                scheduler: Scheduler::new(self.min_workers, self.max_workers, self.idle_timeout, self.queue_capacity),
+                panic_hook: RwLock::new(None),
+            }),
+        };
+
+        for _ in 0..self.min_workers {
+            pool.spawn_worker();
+        }
+
+        pool
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+impl<T> JobHandle<T> {
+    /// Waits for the job to finish and returns its result.
+    ///
+    /// # Errors
+    ///
+    /// If the job panicked `Err` with the panic payload is returned (the same way as in `std::thread::JoinHandle::join`).
+    pub fn join(self) -> thread::Result<T> {
+        receive_result(&self.receiver)
+    }
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Designates an executor for `f` from the pool. Unlike `ThreadPool::spawn`, `f` may borrow anything that outlives the scope.
+    ///
+    /// `f` is the closure to execute.
+    pub fn spawn<F, T>(&'scope self, f: F) -> ScopedJobHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let (sender, receiver) = mpsc::channel();
+
+        *self.data.running_jobs.lock().expect("The scope mutex is in a poisoned state.") += 1;
+
+        let mut sender = ScopedSender {
+            sender: Some(sender),
+            data: Arc::clone(&self.data),
+        };
+
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+
+            if result.is_err() {
+                sender.data.unhandled_panics.fetch_add(1, Ordering::SeqCst); // decremented back if the handle gets joined
+            }
+
+            if let Some(sender) = sender.sender.take() {
+                let _ = sender.send(result);
+            }
+        });
+
+        // SAFETY: the pool requires `'static` jobs, but this one only borrows data for `'scope`; `ThreadPool::scope` does not return until every job spawned within it is finished (or dropped), so the borrowed data outlives the job
+        let job: Job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+
+        self.pool.send_job(job);
+
+        ScopedJobHandle {
+            receiver,
+            data: &self.data,
+        }
+    }
+}
+
+impl<'scope, T> ScopedJobHandle<'scope, T> {
+    /// Waits for the job to finish and returns its result.
+    ///
+    /// # Errors
+    ///
+    /// If the job panicked `Err` with the panic payload is returned. Such a panic is considered handled and does not make the enclosing `ThreadPool::scope` panic.
+    pub fn join(self) -> thread::Result<T> {
+        let result = receive_result(&self.receiver);
+
+        if result.is_err() {
+            self.data.unhandled_panics.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        result
+    }
+}
+
+impl ScopeData {
+    fn wait_for_all(&self) {
+        let mut running_jobs = self.running_jobs.lock().expect("The scope mutex is in a poisoned state.");
+
+        while *running_jobs > 0 {
+            running_jobs = self.all_finished.wait(running_jobs).expect("The scope mutex is in a poisoned state.");
+        }
+    }
+}
+
+impl<T> Drop for ScopedSender<T> {
     fn drop(&mut self) {
-        println!("Sending terminate message to all workers.");
+        self.sender.take(); // the channel (along with the result, that can borrow from `'scope`) has to be gone before the scope is notified
+
+        let mut running_jobs = self.data.running_jobs.lock().expect("The scope mutex is in a poisoned state.");
+
+        *running_jobs -= 1;
 
-        // we need two separate loops (one for sending messages and another for shutting down the workers) to avoid deadlocking; if we used a single loop to iterate through each worker, on the first iteration a terminate message would be sent down the channel and join called on the first worker’s thread, now if that first worker was busy processing a request at that moment, the second worker would pick up the terminate message from the channel and shut down and we would be left waiting on the first worker to shut down, but it never would because the second thread picked up the terminate message
-        for _ in &self.workers {
-            self.sender.send(Message::Terminate).expect("MPSC channel is broken.");
+        if *running_jobs == 0 {
+            self.data.all_finished.notify_all();
         }
+    }
+}
+
+fn report_panic(worker_id: usize, payload: &(dyn Any + Send), panic_hook: &RwLock<Option<PanicHook>>) {
+    let hook = panic_hook.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    // the hook is user code too, a panic inside it must not kill the worker either
+    let reported = panic::catch_unwind(AssertUnwindSafe(|| match hook.as_ref() {
+        Some(hook) => hook(worker_id, payload),
+        None => error!("Worker {} caught a panic in a job: {}", worker_id, panic_message(payload)),
+    }));
+
+    if reported.is_err() {
+        error!("Worker {}: the panic hook panicked.", worker_id);
+    }
+}
+
+/// Extracts the message from a panic payload, if there is one.
+///
+/// `panic!` with a string literal produces a `&str` payload, `panic!` with formatting arguments produces a `String`; anything else is opaque.
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+fn receive_result<T>(receiver: &mpsc::Receiver<thread::Result<T>>) -> thread::Result<T> {
+    // the sender is only dropped without sending if the job itself was dropped without being run
+    receiver.recv().unwrap_or_else(|_| Err(Box::new("The job was dropped before it could finish.")))
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        debug!("Telling all workers to terminate.");
+
+        // the workers finish the jobs that are already queued and only then quit, so joining them one by one below can't deadlock
+        self.shared.scheduler.shutdown();
 
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+        let workers = self.workers.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        for worker in workers {
+            debug!("Shutting down worker {}", worker.id);
 
             if let Some(thread) = worker.thread.take() {
-                thread.join().expect("Cannot join the thread. Possibly because it panicked.");
+                // workers catch the panics of their jobs, so this can only fail if something went really wrong; that is still no reason to panic in `drop` though (panicking while already unwinding aborts the whole process)
+                if thread.join().is_err() {
+                    error!("Worker {} terminated abnormally.", worker.id);
+                }
             }
         }
     }
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-        // loop forever asking for new jobs to execute
-        let thread = thread::spawn(move || loop {
-            let message = receiver.lock()
-                .expect(
-                    "The mutex is in a poisoned state (possibly another thread holding the mutex has panicked without releasing it.)"
-                ).recv().expect("MPSC channel is broken.");
-
-            match message {
-                Message::NewJob(job) => {
-                    println!("Worker {} got a job; executing.", id);
-
-                    job();
-                }
-                Message::Terminate => {
-                    println!("Worker {} was told to terminate.", id);
+    fn new(id: usize, shared: Arc<Shared>) -> Worker {
+        let thread = thread::spawn(move || {
+            // loop asking for new jobs to execute until the pool shuts down
+            while let Some(job) = shared.scheduler.pop(id) {
+                debug!("Worker {} got a job; executing.", id);
 
-                    break;
+                // a panicking job would otherwise unwind through this loop and kill the thread, silently shrinking the pool
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                    report_panic(id, payload.as_ref(), &shared.panic_hook);
                 }
+
+                shared.scheduler.job_done();
             }
+
+            debug!("Worker {} is retiring.", id);
         });
 
         Worker {
@@ -110,3 +743,466 @@ impl Worker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn spawn_returns_value() {
+        let pool = ThreadPool::new(2);
+
+        let handle = pool.spawn(|| 6 * 7);
+
+        assert_eq!(handle.join().unwrap(), 42);
+    }
+
+    #[test]
+    fn spawn_returns_panic_payload() {
+        let pool = ThreadPool::new(2);
+
+        let handle = pool.spawn(|| -> i32 { panic!("boom") });
+
+        let payload = handle.join().unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+
+        // the worker survived the panic so the pool is still usable
+        assert_eq!(pool.spawn(|| 1).join().unwrap(), 1);
+    }
+
+    #[test]
+    fn panicking_jobs_are_reported_to_hook() {
+        let pool = ThreadPool::new(2);
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+
+        pool.set_panic_hook(move |_, payload| {
+            sender.lock().unwrap().send(panic_message(payload).to_string()).unwrap();
+        });
+
+        pool.execute(|| panic!("job {} failed", 1));
+
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), "job 1 failed");
+    }
+
+    #[test]
+    fn panicking_jobs_preserve_capacity() {
+        let size = 4;
+        let pool = ThreadPool::new(size);
+        let panics = Arc::new(AtomicUsize::new(0));
+
+        {
+            let panics = Arc::clone(&panics);
+            pool.set_panic_hook(move |_, _| {
+                panics.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        for _ in 0..size * 5 {
+            pool.execute(|| panic!("boom"));
+        }
+
+        // the jobs below can only all finish if `size` of them run at the same time, i.e. if every worker is still alive
+        let started = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = mpsc::channel();
+
+        for _ in 0..size {
+            let started = Arc::clone(&started);
+            let sender = sender.clone();
+
+            pool.execute(move || {
+                started.fetch_add(1, Ordering::SeqCst);
+
+                let deadline = Instant::now() + Duration::from_secs(5);
+                while started.load(Ordering::SeqCst) < size && Instant::now() < deadline {
+                    thread::yield_now();
+                }
+
+                sender.send(started.load(Ordering::SeqCst)).unwrap();
+            });
+        }
+
+        for _ in 0..size {
+            assert_eq!(receiver.recv_timeout(Duration::from_secs(10)).unwrap(), size);
+        }
+
+        assert_eq!(panics.load(Ordering::SeqCst), size * 5);
+    }
+
+    #[test]
+    fn panicking_hook_does_not_kill_worker() {
+        let pool = ThreadPool::new(1);
+
+        pool.set_panic_hook(|_, _| panic!("hook failed"));
+        pool.execute(|| panic!("boom"));
+
+        assert_eq!(pool.spawn(|| 1).join().unwrap(), 1);
+    }
+
+    #[test]
+    fn try_execute_returns_job_when_queue_is_full() {
+        let pool = ThreadPool::with_queue_capacity(1, 2);
+        let (started_sender, started_receiver) = mpsc::channel();
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+
+        pool.execute(move || {
+            started_sender.send(()).unwrap();
+            release_receiver.recv().unwrap();
+        });
+        started_receiver.recv_timeout(Duration::from_secs(5)).unwrap(); // the only worker is busy from now on
+
+        assert!(pool.try_execute(|| ()).is_ok());
+        assert!(pool.try_execute(|| ()).is_ok());
+        assert_eq!(pool.queued_jobs(), 2);
+
+        let ran = Arc::new(AtomicUsize::new(0));
+        let job = {
+            let ran = Arc::clone(&ran);
+            move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+            }
+        };
+
+        let rejected = pool.try_execute(job).unwrap_err();
+        rejected(); // we got back the very same job
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+
+        drop(release_sender);
+    }
+
+    #[test]
+    fn execute_blocks_until_queue_has_room() {
+        let pool = Arc::new(ThreadPool::with_queue_capacity(1, 1));
+        let (started_sender, started_receiver) = mpsc::channel();
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+
+        pool.execute(move || {
+            started_sender.send(()).unwrap();
+            release_receiver.recv().unwrap();
+        });
+        started_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        pool.execute(|| ()); // fills the queue
+
+        let (done_sender, done_receiver) = mpsc::channel();
+        let blocked = {
+            let pool = Arc::clone(&pool);
+
+            thread::spawn(move || {
+                pool.execute(|| ());
+                done_sender.send(()).unwrap();
+            })
+        };
+
+        assert!(done_receiver.recv_timeout(Duration::from_millis(100)).is_err());
+
+        drop(release_sender);
+
+        done_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        blocked.join().unwrap();
+    }
+
+    #[test]
+    fn jobs_spawned_by_jobs_run() {
+        let pool = Arc::new(ThreadPool::new(4));
+        let counter = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = mpsc::channel();
+
+        // `spawn` rather than `execute` so that we can wait for the outer jobs to let go of their `Arc`s - the pool must not be dropped from one of its own workers
+        let handles: Vec<_> = (0..10)
+            .map(|_| {
+                let inner_pool = Arc::clone(&pool);
+                let counter = Arc::clone(&counter);
+                let sender = sender.clone();
+
+                pool.spawn(move || {
+                    for _ in 0..100 {
+                        let counter = Arc::clone(&counter);
+                        let sender = sender.clone();
+
+                        inner_pool.execute(move || {
+                            counter.fetch_add(1, Ordering::SeqCst);
+                            sender.send(()).unwrap();
+                        });
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        for _ in 0..1000 {
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+
+        assert_eq!(counter.load(Ordering::SeqCst), 1000);
+    }
+
+    #[test]
+    fn drop_finishes_queued_jobs() {
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        {
+            let pool = ThreadPool::new(2);
+
+            for _ in 0..100 {
+                let counter = Arc::clone(&counter);
+
+                pool.execute(move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+        }
+
+        assert_eq!(counter.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn jobs_run_by_priority() {
+        let pool = ThreadPool::new(1);
+        let release = occupy_workers(&pool, 1);
+        let (sender, receiver) = mpsc::channel();
+
+        for (priority, name) in [(Priority::Background, "background"), (Priority::Normal, "normal 1"), (Priority::High, "high"), (Priority::Normal, "normal 2")] {
+            let sender = sender.clone();
+            pool.execute_with(priority, move || sender.send(name).unwrap());
+        }
+
+        drop(release);
+
+        let order: Vec<_> = (0..4).map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap()).collect();
+        assert_eq!(order, ["high", "normal 1", "normal 2", "background"]);
+    }
+
+    #[test]
+    fn jobs_run_in_order_across_workers() {
+        // the jobs are spread over the deques of busy workers, the only free one has to take them from there oldest first
+        let pool = ThreadPool::new(3);
+        let release = occupy_workers(&pool, 2);
+        let (sender, receiver) = mpsc::channel();
+
+        for i in 0..10 {
+            let sender = sender.clone();
+            pool.execute(move || sender.send(i).unwrap());
+        }
+
+        let order: Vec<_> = (0..10).map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap()).collect();
+        assert_eq!(order, (0..10).collect::<Vec<_>>());
+
+        drop(release);
+
+        // the same for jobs submitted by a job that keeps its worker busy afterwards; `spawn` so that its `Arc` is let go of before the pool is dropped (see `jobs_spawned_by_jobs_run`)
+        let pool = Arc::new(ThreadPool::new(2));
+        let inner_pool = Arc::clone(&pool);
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+
+        let submitter = pool.spawn(move || {
+            for i in 0..10 {
+                let sender = sender.clone();
+                inner_pool.execute(move || sender.send(i).unwrap());
+            }
+
+            let _ = release_receiver.recv();
+        });
+
+        let order: Vec<_> = (0..10).map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap()).collect();
+        assert_eq!(order, (0..10).collect::<Vec<_>>());
+
+        drop(release_sender);
+        submitter.join().unwrap();
+    }
+
+    #[test]
+    fn cancelled_jobs_leave_the_queue() {
+        let pool = ThreadPool::with_queue_capacity(1, 1);
+        let release = occupy_workers(&pool, 1);
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        let token = {
+            let ran = Arc::clone(&ran);
+            pool.try_execute_with(Priority::Background, move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+            })
+        }
+        .unwrap_or_else(|_| panic!("The queue has room."));
+
+        assert!(pool.try_execute(|| ()).is_err());
+        assert!(token.is_queued());
+
+        assert!(token.cancel());
+        assert!(!token.cancel()); // only once
+        assert!(token.is_cancelled());
+        assert_eq!(pool.queued_jobs(), 0);
+        assert!(pool.try_execute(|| ()).is_ok()); // the place is free again
+
+        drop(release);
+
+        let started = pool.execute_with(Priority::High, || ());
+        assert!(wait_for(|| !started.is_queued()));
+        assert!(!started.cancel()); // too late
+        assert!(!started.is_cancelled());
+
+        drop(pool);
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+    }
+
+    // blocks `count` workers until the returned sender is dropped
+    fn occupy_workers(pool: &ThreadPool, count: usize) -> mpsc::Sender<()> {
+        let (started_sender, started_receiver) = mpsc::channel();
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+        let release_receiver = Arc::new(Mutex::new(release_receiver));
+
+        for _ in 0..count {
+            let started_sender = started_sender.clone();
+            let release_receiver = Arc::clone(&release_receiver);
+
+            pool.execute(move || {
+                started_sender.send(()).unwrap();
+                let _ = release_receiver.lock().unwrap().recv();
+            });
+        }
+
+        for _ in 0..count {
+            started_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+
+        release_sender
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        while !condition() {
+            if Instant::now() > deadline {
+                return false;
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        true
+    }
+
+    #[test]
+    fn pool_grows_on_demand_and_shrinks_when_idle() {
+        let pool = ThreadPool::builder()
+            .min_workers(1)
+            .max_workers(3)
+            .idle_timeout(Duration::from_millis(50))
+            .build();
+
+        assert_eq!(pool.metrics().workers, 1);
+
+        // the release sender is dropped right away, so each job only blocks until all three have started - which requires three workers
+        drop(occupy_workers(&pool, 3));
+
+        assert!(wait_for(|| pool.metrics().active_workers == 0));
+        assert!(wait_for(|| pool.metrics().workers == 1));
+    }
+
+    #[test]
+    fn monitor_outlives_pool() {
+        let pool = ThreadPool::new(2);
+        let monitor = pool.monitor();
+
+        assert_eq!(monitor.metrics().workers, 2);
+
+        drop(pool);
+
+        assert_eq!(monitor.metrics(), PoolMetrics::default());
+    }
+
+    #[test]
+    fn pool_does_not_grow_beyond_max() {
+        let pool = ThreadPool::builder().min_workers(1).max_workers(2).build();
+
+        let release = occupy_workers(&pool, 2);
+
+        for _ in 0..10 {
+            pool.execute(|| ());
+        }
+
+        let metrics = pool.metrics();
+        assert_eq!(metrics.workers, 2);
+        assert_eq!(metrics.active_workers, 2);
+        assert_eq!(metrics.idle_workers, 0);
+        assert_eq!(metrics.queued_jobs, 10);
+
+        drop(release);
+    }
+
+    #[test]
+    fn resize_spawns_and_retires_workers() {
+        let pool = ThreadPool::new(2);
+
+        pool.resize(5);
+        assert_eq!(pool.metrics().workers, 5);
+
+        let release = occupy_workers(&pool, 5);
+
+        pool.resize(1);
+        assert_eq!(pool.metrics().workers, 5); // everybody is busy, nobody can retire just yet
+
+        drop(release);
+
+        assert!(wait_for(|| pool.metrics().workers == 1));
+        assert_eq!(pool.spawn(|| 42).join().unwrap(), 42);
+    }
+
+    #[test]
+    fn scope_borrows_local_data() {
+        let pool = ThreadPool::new(3);
+        let numbers: Vec<u64> = (1..=100).collect();
+        let mut total = 0;
+
+        pool.scope(|s| {
+            let handles: Vec<_> = numbers
+                .chunks(10)
+                .map(|chunk| s.spawn(move || chunk.iter().sum::<u64>()))
+                .collect();
+
+            for handle in handles {
+                total += handle.join().unwrap();
+            }
+        });
+
+        assert_eq!(total, 5050);
+    }
+
+    #[test]
+    fn scope_waits_for_detached_jobs() {
+        let pool = ThreadPool::new(2);
+        let counter = AtomicUsize::new(0);
+
+        pool.scope(|s| {
+            for _ in 0..10 {
+                s.spawn(|| counter.fetch_add(1, Ordering::SeqCst));
+            }
+        });
+
+        assert_eq!(counter.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "A job spawned in the scope panicked")]
+    fn scope_propagates_unjoined_panic() {
+        let pool = ThreadPool::new(2);
+
+        pool.scope(|s| {
+            s.spawn(|| panic!("boom"));
+        });
+    }
+
+    #[test]
+    fn scope_ignores_joined_panic() {
+        let pool = ThreadPool::new(2);
+
+        let failed = pool.scope(|s| s.spawn(|| panic!("boom")).join().is_err());
+
+        assert!(failed);
+    }
+}
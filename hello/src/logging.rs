@@ -0,0 +1,387 @@
+//! A small logging facade.
+//!
+//! There are two independent logs: the diagnostic one, written to with the `error!`, `warn!`, `info!`, `debug!` and `trace!` macros and filtered by `Level`, and the access log with one line per served request in Common or Combined Log Format. Each of them goes to its own `Sink`: the standard error, the standard output or a size-rotated file.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The importance of a diagnostic message. Levels are ordered from the most to the least important, i.e. `Level::Error < Level::Debug`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Where the log lines go.
+#[derive(Debug)]
+pub enum Sink {
+    Stderr,
+    Stdout,
+    File(RotatingFile),
+}
+
+/// A log file that is rotated once it grows over a certain size: `hello.log` becomes `hello.log.1`, `hello.log.1` becomes `hello.log.2` and so on, the oldest one is deleted.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_backups: usize,
+    file: File,
+    size: u64,
+}
+
+/// The format of the access log lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// `host ident authuser [date] "request" status bytes`
+    Common,
+    /// The common format followed by `"referer" "user-agent"`.
+    Combined,
+}
+
+/// Everything the access log needs to know about a served request.
+#[derive(Debug, Clone)]
+pub struct AccessRecord<'a> {
+    pub remote: Option<SocketAddr>,
+    pub time: SystemTime, // when the request was received
+    pub method: &'a str,
+    pub path: &'a str,
+    pub protocol: &'a str,
+    pub status: u16,
+    pub bytes: usize, // the size of the response body
+    pub latency: Duration,
+    pub referer: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+}
+
+struct AccessLog {
+    sink: Sink,
+    format: AccessLogFormat,
+}
+
+// the level is read on every log macro invocation, so it lives in an atomic rather than behind the mutex
+static MAX_LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+static SINK: Mutex<Sink> = Mutex::new(Sink::Stderr);
+static ACCESS_LOG: Mutex<Option<AccessLog>> = Mutex::new(None);
+
+/// Logs a message at the given level. Prefer the level-specific macros.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::logging::enabled($level) {
+            $crate::logging::log($level, format_args!($($arg)+));
+        }
+    };
+}
+
+/// Logs a message at the error level.
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log!($crate::logging::Level::Error, $($arg)+) };
+}
+
+/// Logs a message at the warn level.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log!($crate::logging::Level::Warn, $($arg)+) };
+}
+
+/// Logs a message at the info level.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log!($crate::logging::Level::Info, $($arg)+) };
+}
+
+/// Logs a message at the debug level.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log!($crate::logging::Level::Debug, $($arg)+) };
+}
+
+/// Logs a message at the trace level.
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log!($crate::logging::Level::Trace, $($arg)+) };
+}
+
+/// Sets the least important level that still gets logged and where the diagnostic log goes.
+pub fn init(level: Level, sink: Sink) {
+    MAX_LEVEL.store(level as usize, Ordering::Relaxed);
+
+    *SINK.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = sink;
+}
+
+/// Enables the access log, writing it to `sink` in the given `format`. The access log is disabled until this is called.
+pub fn init_access_log(sink: Sink, format: AccessLogFormat) {
+    *ACCESS_LOG.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(AccessLog { sink, format });
+}
+
+/// Returns `true` if messages at `level` are logged.
+pub fn enabled(level: Level) -> bool {
+    level as usize <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Writes a message to the diagnostic log regardless of the level. Used by the logging macros.
+pub fn log(level: Level, args: fmt::Arguments) {
+    let line = format!("[{} {:<5}] {}", format_iso8601(SystemTime::now()), level, args);
+
+    // a logger has nowhere to report its own failures to, so they are ignored
+    let _ = SINK.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).write_line(&line);
+}
+
+/// Writes a line about a served request to the access log, if it is enabled.
+pub fn access(record: &AccessRecord) {
+    let mut access_log = ACCESS_LOG.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    if let Some(access_log) = access_log.as_mut() {
+        let line = format_access(record, access_log.format);
+
+        let _ = access_log.sink.write_line(&line);
+    }
+}
+
+/// Formats `record` as an access log line. The latency in microseconds is appended as the last field (the same way as Apache's `%D`).
+pub fn format_access(record: &AccessRecord, format: AccessLogFormat) -> String {
+    let host = record.remote.map_or_else(|| String::from("-"), |remote| remote.ip().to_string());
+
+    let mut line = format!(
+        "{} - - [{}] \"{} {} {}\" {} {}",
+        host,
+        format_clf_time(record.time),
+        record.method,
+        record.path,
+        record.protocol,
+        record.status,
+        record.bytes,
+    );
+
+    if format == AccessLogFormat::Combined {
+        line.push_str(&format!(" \"{}\" \"{}\"", record.referer.unwrap_or("-"), record.user_agent.unwrap_or("-")));
+    }
+
+    line.push_str(&format!(" {}", record.latency.as_micros()));
+
+    line
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        };
+
+        f.pad(name) // `pad` rather than `write_str` to respect the width and the alignment in the format string
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Level, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(format!("Unknown log level '{}', expected one of error, warn, info, debug, trace.", s)),
+        }
+    }
+}
+
+impl Sink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Sink::Stderr => writeln!(io::stderr().lock(), "{}", line),
+            Sink::Stdout => writeln!(io::stdout().lock(), "{}", line),
+            Sink::File(file) => file.write_line(line),
+        }
+    }
+}
+
+impl RotatingFile {
+    /// Opens (or creates) the log file at `path` for appending.
+    ///
+    /// `max_bytes` is the size after which the file is rotated.
+    /// `max_backups` is how many rotated files are kept around; with `0` the file is simply truncated when it grows too large.
+    ///
+    /// # Errors
+    /// The same as `std::fs::OpenOptions::open`.
+    pub fn new(path: impl AsRef<Path>, max_bytes: u64, max_backups: usize) -> io::Result<RotatingFile> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile {
+            path,
+            max_bytes,
+            max_backups,
+            file,
+            size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let length = line.len() as u64 + 1;
+
+        if self.size > 0 && self.size + length > self.max_bytes {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.size += length;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_backups > 0 {
+            for index in (1..self.max_backups).rev() {
+                match fs::rename(self.backup_path(index), self.backup_path(index + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => (),
+                }
+            }
+
+            fs::rename(&self.path, self.backup_path(1))?;
+        }
+
+        self.file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+
+    fn backup_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+
+        PathBuf::from(path)
+    }
+}
+
+// `10/Oct/2000:13:55:36 +0000`
+fn format_clf_time(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let (year, month, day, hours, minutes, seconds) = to_utc(time);
+
+    format!("{:02}/{}/{}:{:02}:{:02}:{:02} +0000", day, MONTHS[month as usize - 1], year, hours, minutes, seconds)
+}
+
+// `2000-10-10T13:55:36Z`
+fn format_iso8601(time: SystemTime) -> String {
+    let (year, month, day, hours, minutes, seconds) = to_utc(time);
+
+    format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hours, minutes, seconds)
+}
+
+// splits the time into the UTC year, month, day, hours, minutes and seconds
+fn to_utc(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+    let seconds = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, seconds_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // Howard Hinnant's `civil_from_days` algorithm, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153; // months counted from March
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day, seconds_of_day / 3_600, seconds_of_day % 3_600 / 60, seconds_of_day % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn record(time: SystemTime) -> AccessRecord<'static> {
+        AccessRecord {
+            remote: Some("127.0.0.1:54321".parse().unwrap()),
+            time,
+            method: "GET",
+            path: "/sleep",
+            protocol: "HTTP/1.1",
+            status: 200,
+            bytes: 2326,
+            latency: Duration::from_micros(5_000_123),
+            referer: None,
+            user_agent: Some("curl/8.0"),
+        }
+    }
+
+    #[test]
+    fn common_log_format() {
+        let time = UNIX_EPOCH + Duration::from_secs(971_186_136); // 2000-10-10 13:55:36 UTC
+
+        assert_eq!(
+            format_access(&record(time), AccessLogFormat::Common),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /sleep HTTP/1.1\" 200 2326 5000123"
+        );
+    }
+
+    #[test]
+    fn combined_log_format() {
+        let time = UNIX_EPOCH + Duration::from_secs(971_186_136);
+
+        assert_eq!(
+            format_access(&record(time), AccessLogFormat::Combined),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /sleep HTTP/1.1\" 200 2326 \"-\" \"curl/8.0\" 5000123"
+        );
+    }
+
+    #[test]
+    fn utc_conversion() {
+        assert_eq!(format_iso8601(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(format_iso8601(UNIX_EPOCH + Duration::from_secs(951_782_400)), "2000-02-29T00:00:00Z"); // a leap day
+        assert_eq!(format_iso8601(UNIX_EPOCH + Duration::from_secs(1_792_454_399)), "2026-10-19T23:59:59Z");
+    }
+
+    #[test]
+    fn level_ordering_and_parsing() {
+        assert!(Level::Error < Level::Warn);
+        assert!(Level::Debug < Level::Trace);
+        assert_eq!("DEBUG".parse(), Ok(Level::Debug));
+        assert!("verbose".parse::<Level>().is_err());
+    }
+
+    #[test]
+    fn file_rotation() {
+        let directory = env::temp_dir().join(format!("hello-logging-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("hello.log");
+
+        let mut file = RotatingFile::new(&path, 10, 2).unwrap();
+
+        for line in ["first", "second", "third", "fourth"] {
+            file.write_line(line).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(directory.join("hello.log.1")).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(directory.join("hello.log.2")).unwrap(), "second\n");
+        assert!(!directory.join("hello.log.3").exists());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,48 +1,63 @@
-use hello::ThreadPool;
+use hello::config::{AccessLogTarget, Settings, USAGE};
+use hello::logging::{self, RotatingFile, Sink};
+use hello::server::Server;
 
-use std::fs;
-use std::io::prelude::*;
-use std::net::TcpListener;
-use std::net::TcpStream;
-use std::thread;
-use std::time::Duration;
+use std::env;
+use std::io;
+use std::path::Path;
+use std::process;
+
+const LOG_FILE_SIZE: u64 = 10 * 1024 * 1024;
+const LOG_FILE_BACKUPS: usize = 5;
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = ThreadPool::new(4);
+    if env::args().skip(1).any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+
+        return;
+    }
 
-    // accept only two connections, then shutdown
-    for stream in listener.incoming().take(2) {
-        let stream = stream.unwrap(); // in fact we're iterating over connection attempts (not connections), which might be unsuccessful, hence `unwrap`
+    let settings = Settings::load(env::args().skip(1), |name| env::var(name).ok()).unwrap_or_else(|err| {
+        eprintln!("Invalid configuration: {}", err);
 
-        pool.execute(|| {
-            handle_connection(stream);
-        });
+        process::exit(2);
+    });
+
+    if let Err(err) = init_logging(&settings) {
+        eprintln!("Cannot open the log file: {}", err);
+
+        process::exit(1);
     }
-}
 
-// note that `stream` parameter is mutable; the reason is that `TcpStream` instance keeps track of what data it returns to us internally, it might read more data than we asked for and save that data for the next time we ask for data - it therefore needs to be `mut` because its internal state might change
-fn handle_connection(mut stream: TcpStream) {
-    let mut buffer = [0; 1024];
+    let server = Server::bind(settings.server).unwrap_or_else(|err| {
+        eprintln!("Cannot start the server: {}", err);
 
-    stream.read(&mut buffer).unwrap();
+        process::exit(1);
+    });
 
-    let get = b"GET / HTTP/1.1\r\n";
-    let sleep = b"GET /sleep HTTP/1.1\r\n";
+    server.serve(settings.max_connections);
+}
 
-    let (status_line, filename) = if buffer.starts_with(get) {
-        ("HTTP/1.1 200 OK\r\n\r\n", "resources/hello.html")
-    } else if buffer.starts_with(sleep) {
-        thread::sleep(Duration::from_secs(5));
-        ("HTTP/1.1 200 OK\r\n\r\n", "resources/hello.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND\r\n\r\n", "resources/404.html")
+fn init_logging(settings: &Settings) -> io::Result<()> {
+    let sink = match &settings.log_file {
+        Some(path) => file_sink(path)?,
+        None => Sink::Stderr,
     };
 
-    let contents = fs::read_to_string(filename).unwrap();
+    logging::init(settings.log_level, sink);
+
+    let access_sink = match &settings.access_log {
+        AccessLogTarget::Off => return Ok(()),
+        AccessLogTarget::Stdout => Sink::Stdout,
+        AccessLogTarget::Stderr => Sink::Stderr,
+        AccessLogTarget::File(path) => file_sink(path)?,
+    };
 
-    let response = format!("{}{}", status_line, contents);
+    logging::init_access_log(access_sink, settings.access_format);
+
+    Ok(())
+}
 
-    stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap(); // `flush` will wait and prevent the program from continuing until all the bytes are written to the connection - `TcpStream` contains an internal buffer to minimize calls to the underlying operating system
+fn file_sink(path: &Path) -> io::Result<Sink> {
+    RotatingFile::new(path, LOG_FILE_SIZE, LOG_FILE_BACKUPS).map(Sink::File)
 }
@@ -0,0 +1,141 @@
+//! The server's metrics, rendered in the Prometheus text exposition format for `/metrics`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::PoolMetrics;
+
+/// The upper bounds (in seconds) of the latency histogram buckets; the `+Inf` bucket is implicit.
+pub const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// The request counters and latency histograms, safe to update from any number of threads.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    // `BTreeMap`s rather than `HashMap`s so that the output comes out in a stable order
+    requests: Mutex<BTreeMap<(String, u16), u64>>,
+    latencies: Mutex<BTreeMap<String, Histogram>>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()], // not cumulative, the sums are computed when rendering
+    sum: f64,
+    count: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Records a request to `route` answered with `status` after `latency`.
+    ///
+    /// Keep the number of distinct routes small (e.g. don't pass the raw request paths): every one of them becomes a separate time series.
+    pub fn observe(&self, route: &str, status: u16, latency: Duration) {
+        *self
+            .requests
+            .lock()
+            .expect("The request counters are in a poisoned state.")
+            .entry((route.to_string(), status))
+            .or_insert(0) += 1;
+
+        let seconds = latency.as_secs_f64();
+        let mut latencies = self.latencies.lock().expect("The latency histograms are in a poisoned state.");
+        let histogram = latencies.entry(route.to_string()).or_default();
+
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            histogram.buckets[bucket] += 1;
+        }
+
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    /// Renders the metrics, along with the state of the pool, in the Prometheus text format.
+    pub fn render(&self, pool: &PoolMetrics) -> String {
+        let mut output = String::new();
+
+        // writing into a `String` never fails, hence the ignored results below
+        let _ = writeln!(output, "# HELP hello_requests_total The number of requests answered, by route and status.");
+        let _ = writeln!(output, "# TYPE hello_requests_total counter");
+
+        for ((route, status), count) in self.requests.lock().expect("The request counters are in a poisoned state.").iter() {
+            let _ = writeln!(output, "hello_requests_total{{route=\"{}\",status=\"{}\"}} {}", escape(route), status, count);
+        }
+
+        let _ = writeln!(output, "# HELP hello_request_duration_seconds The time from accepting a request to answering it, by route.");
+        let _ = writeln!(output, "# TYPE hello_request_duration_seconds histogram");
+
+        for (route, histogram) in self.latencies.lock().expect("The latency histograms are in a poisoned state.").iter() {
+            let route = escape(route);
+            let mut cumulative = 0;
+
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(output, "hello_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}", route, bound, cumulative);
+            }
+
+            let _ = writeln!(output, "hello_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}", route, histogram.count);
+            let _ = writeln!(output, "hello_request_duration_seconds_sum{{route=\"{}\"}} {}", route, histogram.sum);
+            let _ = writeln!(output, "hello_request_duration_seconds_count{{route=\"{}\"}} {}", route, histogram.count);
+        }
+
+        let gauges = [
+            ("hello_pool_workers", "The number of workers in the pool.", pool.workers),
+            ("hello_pool_busy_workers", "The number of workers running a job.", pool.active_workers),
+            ("hello_pool_queued_jobs", "The number of jobs waiting for a free worker.", pool.queued_jobs),
+        ];
+
+        for (name, help, value) in gauges {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} gauge", name);
+            let _ = writeln!(output, "{} {}", name, value);
+        }
+
+        output
+    }
+}
+
+// label values are quoted, so quotes, backslashes and line breaks must be escaped
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_counters_and_histograms() {
+        let metrics = Metrics::new();
+
+        metrics.observe("/", 200, Duration::from_millis(3));
+        metrics.observe("/", 200, Duration::from_millis(30));
+        metrics.observe("/", 404, Duration::from_secs(20));
+
+        let pool = PoolMetrics {
+            workers: 4,
+            active_workers: 1,
+            idle_workers: 3,
+            queued_jobs: 2,
+        };
+        let output = metrics.render(&pool);
+
+        assert!(output.contains("hello_requests_total{route=\"/\",status=\"200\"} 2\n"));
+        assert!(output.contains("hello_requests_total{route=\"/\",status=\"404\"} 1\n"));
+        assert!(output.contains("hello_request_duration_seconds_bucket{route=\"/\",le=\"0.005\"} 1\n"));
+        assert!(output.contains("hello_request_duration_seconds_bucket{route=\"/\",le=\"0.05\"} 2\n"));
+        assert!(output.contains("hello_request_duration_seconds_bucket{route=\"/\",le=\"10\"} 2\n"));
+        assert!(output.contains("hello_request_duration_seconds_bucket{route=\"/\",le=\"+Inf\"} 3\n"));
+        assert!(output.contains("hello_request_duration_seconds_count{route=\"/\"} 3\n"));
+        assert!(output.contains("# TYPE hello_pool_busy_workers gauge\nhello_pool_busy_workers 1\n"));
+        assert!(output.contains("hello_pool_queued_jobs 2\n"));
+    }
+
+    #[test]
+    fn escapes_labels() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
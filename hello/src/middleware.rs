@@ -0,0 +1,409 @@
+//! Middleware: the cross-cutting behaviour wrapped around every handler, like authentication or CORS.
+//!
+//! A `Chain` runs its middleware in the order they were added, each one wrapping all the ones after it and, eventually, the handler. The simple ones only implement `Middleware::before` (which can answer instead of the handler) and/or `Middleware::after` (which can change the response); those that need to wrap the call itself, like `CatchPanic`, override `Middleware::handle`.
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use crate::base64;
+use crate::request::Request;
+use crate::response::Response;
+use crate::{error, panic_message};
+
+/// Something to run around the handlers.
+pub trait Middleware: Send + Sync {
+    /// Called before the rest of the chain. Returning a response short-circuits: neither the rest of the chain nor the handler runs, and neither does `after` of this middleware (the middleware before it still see the response).
+    fn before(&self, _request: &mut Request) -> Option<Response> {
+        None
+    }
+
+    /// Called with the response of the rest of the chain.
+    fn after(&self, _request: &Request, response: Response) -> Response {
+        response
+    }
+
+    /// Runs this middleware around `next`, the rest of the chain. The default calls `before`, then `next` unless `before` has answered already, then `after`.
+    fn handle(&self, request: &mut Request, next: &dyn Fn(&mut Request) -> Response) -> Response {
+        if let Some(response) = self.before(request) {
+            return response;
+        }
+
+        let response = next(request);
+
+        self.after(request, response)
+    }
+}
+
+/// An ordered list of middleware.
+///
+/// # Example
+/// ```
+/// use hello::middleware::{Chain, Cors, RequestId};
+/// use hello::request::Request;
+/// use hello::response::Response;
+///
+/// let chain = Chain::new().with(RequestId::new()).with(Cors::new().allow_origin("*"));
+///
+/// let mut request = Request::parse("GET / HTTP/1.1\r\nOrigin: https://example.com\r\n\r\n");
+/// let response = chain.handle(&mut request, &|_| Response::new(200));
+///
+/// assert_eq!(response.get_header("Access-Control-Allow-Origin"), Some("*"));
+/// assert!(response.get_header("X-Request-Id").is_some());
+/// ```
+#[derive(Clone, Default)]
+pub struct Chain {
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Chain {
+    /// Creates an empty chain, which just calls the handler.
+    pub fn new() -> Chain {
+        Chain::default()
+    }
+
+    /// Appends `middleware` to the chain, i.e. wraps the handler (and only the handler) with it.
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> Chain {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Runs the chain around `handler`.
+    pub fn handle(&self, request: &mut Request, handler: &dyn Fn(&mut Request) -> Response) -> Response {
+        self.run(0, request, handler)
+    }
+
+    fn run(&self, index: usize, request: &mut Request, handler: &dyn Fn(&mut Request) -> Response) -> Response {
+        match self.middleware.get(index) {
+            Some(middleware) => middleware.handle(request, &|request| self.run(index + 1, request, handler)),
+            None => handler(request),
+        }
+    }
+}
+
+impl fmt::Debug for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Chain({} middleware)", self.middleware.len())
+    }
+}
+
+/// Answers `500 Internal Server Error` if the rest of the chain panics, instead of dropping the connection (or, in the event loop mode, bringing the whole loop down). Belongs at the very start of the chain.
+#[derive(Debug, Default)]
+pub struct CatchPanic;
+
+impl Middleware for CatchPanic {
+    fn handle(&self, request: &mut Request, next: &dyn Fn(&mut Request) -> Response) -> Response {
+        // `AssertUnwindSafe` is fine here: the request is not looked at again after a panic, and the shared state is behind mutexes, which get poisoned
+        match panic::catch_unwind(AssertUnwindSafe(|| next(request))) {
+            Ok(response) => response,
+            Err(payload) => {
+                error!("Handling {} {} has panicked: {}", request.method(), request.path(), panic_message(payload.as_ref()));
+
+                Response::new(500).header("Content-Type", "text/plain; charset=utf-8").body("INTERNAL SERVER ERROR\n")
+            }
+        }
+    }
+}
+
+/// Tags every request with an id in the `X-Request-Id` header, and echoes it in the response, so that a request can be traced through the logs.
+///
+/// The id the client (or a proxy in front of the server) sent is kept if it looks sane, otherwise a new one is made up.
+#[derive(Debug, Default)]
+pub struct RequestId {
+    counter: AtomicU64,
+}
+
+impl RequestId {
+    pub fn new() -> RequestId {
+        RequestId::default()
+    }
+
+    fn generate(&self) -> String {
+        // a random prefix per process keeps the ids from different runs (or instances) of the server apart
+        static PREFIX: OnceLock<u64> = OnceLock::new();
+        let prefix = PREFIX.get_or_init(|| RandomState::new().hash_one(std::process::id())); // `RandomState` is seeded randomly, which is all the randomness we need
+
+        format!("{:016x}-{}", prefix, self.counter.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Middleware for RequestId {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        let sane = |id: &str| !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+
+        if !request.header("X-Request-Id").is_some_and(sane) {
+            let id = self.generate();
+            request.set_header("X-Request-Id", &id);
+        }
+
+        None
+    }
+
+    fn after(&self, request: &Request, response: Response) -> Response {
+        match request.header("X-Request-Id") {
+            Some(id) => response.header("X-Request-Id", id),
+            None => response,
+        }
+    }
+}
+
+/// Cross-origin resource sharing: lets the pages of the allowed origins call the server from a browser, answering the preflight requests on its own.
+#[derive(Debug, Clone)]
+pub struct Cors {
+    origins: Vec<String>,
+    methods: String,
+    headers: String,
+    max_age: u32,
+}
+
+impl Cors {
+    /// Creates the middleware allowing no origins yet, with `GET`, `HEAD`, `POST` and `OPTIONS` and the `Content-Type` and `Authorization` headers, caching the preflight answers for ten minutes.
+    pub fn new() -> Cors {
+        Cors {
+            origins: Vec::new(),
+            methods: String::from("GET, HEAD, POST, OPTIONS"),
+            headers: String::from("Content-Type, Authorization"),
+            max_age: 600,
+        }
+    }
+
+    /// Allows `origin` (e.g. `https://example.com`) or, with `*`, any origin.
+    pub fn allow_origin(mut self, origin: &str) -> Cors {
+        self.origins.push(origin.to_string());
+        self
+    }
+
+    /// Sets the methods the cross-origin requests may use, e.g. `GET, PUT`.
+    pub fn allow_methods(mut self, methods: &str) -> Cors {
+        self.methods = methods.to_string();
+        self
+    }
+
+    /// Sets the request headers the cross-origin requests may send, e.g. `Content-Type, X-Request-Id`.
+    pub fn allow_headers(mut self, headers: &str) -> Cors {
+        self.headers = headers.to_string();
+        self
+    }
+
+    /// Sets for how many seconds the browsers may cache the answer to a preflight request.
+    pub fn max_age(mut self, seconds: u32) -> Cors {
+        self.max_age = seconds;
+        self
+    }
+
+    // the value of `Access-Control-Allow-Origin` for a request from `origin`, if it is allowed
+    fn allowed<'a>(&'a self, origin: &'a str) -> Option<&'a str> {
+        if self.origins.iter().any(|allowed| allowed == "*") {
+            Some("*")
+        } else {
+            self.origins.iter().any(|allowed| allowed == origin).then_some(origin)
+        }
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors::new()
+    }
+}
+
+impl Middleware for Cors {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        // a preflight is an `OPTIONS` request asking about the method of the real one
+        if request.method() != "OPTIONS" || request.header("Access-Control-Request-Method").is_none() {
+            return None;
+        }
+
+        let response = Response::new(204).header("Vary", "Origin");
+
+        // a disallowed origin gets the answer without the `Access-Control-*` headers, which the browser takes as a refusal
+        match request.header("Origin").and_then(|origin| self.allowed(origin)) {
+            Some(origin) => Some(
+                response
+                    .header("Access-Control-Allow-Origin", origin)
+                    .header("Access-Control-Allow-Methods", &self.methods)
+                    .header("Access-Control-Allow-Headers", &self.headers)
+                    .header("Access-Control-Max-Age", &self.max_age.to_string()),
+            ),
+            None => Some(response),
+        }
+    }
+
+    fn after(&self, request: &Request, response: Response) -> Response {
+        // the answer depends on the origin (unless any is allowed), so the caches must keep them apart
+        match request.header("Origin").and_then(|origin| self.allowed(origin)) {
+            Some("*") => response.header("Access-Control-Allow-Origin", "*"),
+            Some(origin) => response.header("Access-Control-Allow-Origin", origin).header("Vary", "Origin"),
+            None => response.header("Vary", "Origin"),
+        }
+    }
+}
+
+/// HTTP basic authentication (RFC 7617): every request must carry the user name and the password of one of the users, or gets `401 Unauthorized`.
+///
+/// Note that basic authentication sends the password in the clear (base64 is just an encoding), so it is only safe over TLS. The browsers send the CORS preflight requests without credentials, so `Cors`, if any, belongs before this one in the chain.
+#[derive(Clone)]
+pub struct BasicAuth {
+    realm: String,
+    users: Vec<(String, String)>,
+}
+
+impl BasicAuth {
+    /// Creates the middleware for the protection space `realm` (shown by the browsers when asking for the password), with no users yet.
+    pub fn new(realm: &str) -> BasicAuth {
+        BasicAuth {
+            realm: realm.to_string(),
+            users: Vec::new(),
+        }
+    }
+
+    /// Adds a user.
+    pub fn user(mut self, name: &str, password: &str) -> BasicAuth {
+        self.users.push((name.to_string(), password.to_string()));
+        self
+    }
+
+    fn authenticated(&self, authorization: &str) -> bool {
+        let Some((scheme, credentials)) = authorization.split_once(' ') else { return false };
+
+        if !scheme.eq_ignore_ascii_case("Basic") {
+            return false;
+        }
+
+        let Some(credentials) = base64::decode(credentials.trim()).and_then(|credentials| String::from_utf8(credentials).ok()) else { return false };
+        let Some((name, password)) = credentials.split_once(':') else { return false };
+
+        self.users.iter().any(|(n, p)| constant_time_eq(n, name) & constant_time_eq(p, password))
+    }
+}
+
+impl fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // the passwords stay out of the logs
+        let users: Vec<_> = self.users.iter().map(|(name, _)| name).collect();
+
+        f.debug_struct("BasicAuth").field("realm", &self.realm).field("users", &users).finish()
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        if request.header("Authorization").is_some_and(|authorization| self.authenticated(authorization)) {
+            return None;
+        }
+
+        Some(
+            Response::new(401)
+                .header("WWW-Authenticate", &format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm.replace('"', "'")))
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .body("UNAUTHORIZED\n"),
+        )
+    }
+}
+
+// compares the whole strings even if they differ early, so that the time it takes doesn't tell an attacker how much of a guess was right
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    fn ok(_: &mut Request) -> Response {
+        Response::new(200).body("hello")
+    }
+
+    fn request(head: &str) -> Request {
+        Request::parse(&format!("{}\r\n\r\n", head))
+    }
+
+    // records the order the hooks are called in
+    struct Trace(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl Middleware for Trace {
+        fn before(&self, _request: &mut Request) -> Option<Response> {
+            self.1.lock().unwrap().push(format!("{} before", self.0));
+            None
+        }
+
+        fn after(&self, _request: &Request, response: Response) -> Response {
+            self.1.lock().unwrap().push(format!("{} after", self.0));
+            response
+        }
+    }
+
+    #[test]
+    fn runs_in_order_and_short_circuits() {
+        let trace = Arc::new(Mutex::new(Vec::new()));
+        let chain = Chain::new().with(Trace("outer", Arc::clone(&trace))).with(BasicAuth::new("test")).with(Trace("inner", Arc::clone(&trace)));
+
+        let response = chain.handle(&mut request("GET / HTTP/1.1"), &ok);
+
+        assert_eq!(response.status(), 401);
+        assert_eq!(*trace.lock().unwrap(), ["outer before", "outer after"]);
+    }
+
+    #[test]
+    fn catches_panics() {
+        let chain = Chain::new().with(CatchPanic);
+
+        let response = chain.handle(&mut request("GET / HTTP/1.1"), &|_| panic!("Oops!"));
+
+        assert_eq!(response.status(), 500);
+    }
+
+    #[test]
+    fn keeps_sane_request_ids() {
+        let chain = Chain::new().with(RequestId::new());
+
+        let response = chain.handle(&mut request("GET / HTTP/1.1\r\nX-Request-Id: abc-123"), &ok);
+        assert_eq!(response.get_header("X-Request-Id"), Some("abc-123"));
+
+        let first = chain.handle(&mut request("GET / HTTP/1.1\r\nX-Request-Id: <script>"), &ok);
+        let second = chain.handle(&mut request("GET / HTTP/1.1"), &ok);
+        assert_ne!(first.get_header("X-Request-Id"), Some("<script>"));
+        assert_ne!(first.get_header("X-Request-Id"), second.get_header("X-Request-Id"));
+    }
+
+    #[test]
+    fn cors() {
+        let chain = Chain::new().with(Cors::new().allow_origin("https://example.com"));
+
+        let preflight = chain.handle(
+            &mut request("OPTIONS / HTTP/1.1\r\nOrigin: https://example.com\r\nAccess-Control-Request-Method: POST"),
+            &|_| unreachable!(),
+        );
+        assert_eq!(preflight.status(), 204);
+        assert_eq!(preflight.get_header("Access-Control-Allow-Origin"), Some("https://example.com"));
+        assert_eq!(preflight.get_header("Access-Control-Allow-Methods"), Some("GET, HEAD, POST, OPTIONS"));
+
+        let response = chain.handle(&mut request("GET / HTTP/1.1\r\nOrigin: https://example.com"), &ok);
+        assert_eq!(response.get_header("Access-Control-Allow-Origin"), Some("https://example.com"));
+        assert_eq!(response.get_header("Vary"), Some("Origin"));
+
+        let response = chain.handle(&mut request("GET / HTTP/1.1\r\nOrigin: https://evil.example"), &ok);
+        assert_eq!(response.get_header("Access-Control-Allow-Origin"), None);
+    }
+
+    #[test]
+    fn basic_auth() {
+        let chain = Chain::new().with(BasicAuth::new("hello").user("alice", "secret"));
+
+        // "alice:secret" and "alice:guess"
+        let response = chain.handle(&mut request("GET / HTTP/1.1\r\nAuthorization: Basic YWxpY2U6c2VjcmV0"), &ok);
+        assert_eq!(response.status(), 200);
+
+        let response = chain.handle(&mut request("GET / HTTP/1.1\r\nAuthorization: Basic YWxpY2U6Z3Vlc3M="), &ok);
+        assert_eq!(response.status(), 401);
+        assert_eq!(response.get_header("WWW-Authenticate"), Some("Basic realm=\"hello\", charset=\"UTF-8\""));
+
+        let response = chain.handle(&mut request("GET / HTTP/1.1\r\nAuthorization: Bearer YWxpY2U6c2VjcmV0"), &ok);
+        assert_eq!(response.status(), 401);
+    }
+}
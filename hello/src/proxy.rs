@@ -0,0 +1,609 @@
+//! A reverse proxy: forwards the requests under a path prefix to a set of upstream servers.
+//!
+//! Every request is forwarded over a connection of its own (with `Connection: close`), its body as it has been received, and the upstream's response is read in full before it is passed on, so the middleware and the compression apply to it like to any other response. That's why a response may only be so large (see `Proxy::max_response_size`): an upstream must not be able to make the server run out of memory.
+
+use std::io::{self, prelude::*, BufReader};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crate::request::Request;
+use crate::response::Response;
+use crate::server::error_response;
+use crate::{info, warn};
+
+// the headers that describe a single connection rather than the request or the response, and so are not passed on by proxies (RFC 9110, section 7.6.1)
+const HOP_BY_HOP: [&str; 8] = ["Connection", "Keep-Alive", "Proxy-Authenticate", "Proxy-Authorization", "TE", "Trailer", "Transfer-Encoding", "Upgrade"];
+
+/// How the requests are spread over the upstreams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balancing {
+    /// Each upstream in turn.
+    RoundRobin,
+    /// The upstream with the fewest requests in flight, taking turns on a tie.
+    LeastConnections,
+}
+
+/// Probes the upstreams in the background: an upstream is healthy as long as `GET path` answers with a `2xx` or `3xx` status.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheck {
+    pub path: String,
+    pub interval: Duration,
+}
+
+/// A set of upstream servers the requests are forwarded to.
+///
+/// # Example
+/// ```
+/// use hello::proxy::{Balancing, Proxy};
+///
+/// let proxy = Proxy::new(["127.0.0.1:9000", "127.0.0.1:9001"]).balancing(Balancing::LeastConnections).retries(2);
+/// ```
+#[derive(Debug)]
+pub struct Proxy {
+    upstreams: Vec<Upstream>,
+    balancing: Balancing,
+    retries: usize,
+    connect_timeout: Duration,
+    timeout: Duration,
+    max_response_size: usize,
+    health_check: Option<HealthCheck>,
+    next: AtomicUsize, // whose turn it is
+}
+
+#[derive(Debug)]
+struct Upstream {
+    address: String,
+    healthy: AtomicBool,
+    active: AtomicUsize, // the requests in flight
+}
+
+/// The proxied path prefixes of the server.
+#[derive(Debug, Clone, Default)]
+pub struct Proxies {
+    routes: Vec<(String, Arc<Proxy>)>,
+}
+
+// why forwarding to an upstream has failed, which decides whether another one may be tried
+enum Failure {
+    Connect(io::Error),        // the upstream hasn't seen the request, so trying another is always safe
+    Exchange(io::Error),       // it might have acted on it already
+    InvalidResponse(String),
+}
+
+impl Proxy {
+    /// Forwards to the upstreams at `addresses` (`host:port`), round-robin, retrying once on another upstream and without health checks.
+    ///
+    /// # Panics
+    /// `new` function will panic if `addresses` is empty.
+    pub fn new<I, S>(addresses: I) -> Proxy
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let upstreams: Vec<Upstream> = addresses
+            .into_iter()
+            .map(|address| Upstream {
+                address: address.into(),
+                healthy: AtomicBool::new(true),
+                active: AtomicUsize::new(0),
+            })
+            .collect();
+
+        assert!(!upstreams.is_empty(), "A proxy needs at least one upstream.");
+
+        Proxy {
+            upstreams,
+            balancing: Balancing::RoundRobin,
+            retries: 1,
+            connect_timeout: Duration::from_secs(1),
+            timeout: Duration::from_secs(30),
+            max_response_size: 16 * 1024 * 1024,
+            health_check: None,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn balancing(mut self, balancing: Balancing) -> Proxy {
+        self.balancing = balancing;
+        self
+    }
+
+    /// Sets how many other upstreams to try when one fails. Only the idempotent requests are retried once an upstream may have received them.
+    pub fn retries(mut self, retries: usize) -> Proxy {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets how long connecting to an upstream may take.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Proxy {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets for how long sending a request to an upstream or reading its response may stall; the request is answered with `504 Gateway Timeout` after that.
+    pub fn timeout(mut self, timeout: Duration) -> Proxy {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how large a response of an upstream may be, the head and the body together; a larger one is answered with `502 Bad Gateway`. 16 MiB by default.
+    pub fn max_response_size(mut self, size: usize) -> Proxy {
+        self.max_response_size = size;
+        self
+    }
+
+    /// Enables the health checks. The unhealthy upstreams get no requests, unless all of them are unhealthy.
+    pub fn health_check(mut self, path: &str, interval: Duration) -> Proxy {
+        self.health_check = Some(HealthCheck {
+            path: path.to_string(),
+            interval,
+        });
+        self
+    }
+
+    /// Forwards `request` and returns the upstream's response, or `502 Bad Gateway` (`504 Gateway Timeout` if the last try has timed out) if no upstream could answer it.
+    pub fn forward(&self, request: &Request) -> Response {
+        let mut tried = Vec::new();
+        let mut status = 502;
+
+        while tried.len() <= self.retries {
+            let Some(index) = self.pick(&tried) else { break };
+            tried.push(index);
+
+            let upstream = &self.upstreams[index];
+
+            upstream.active.fetch_add(1, Ordering::SeqCst);
+            let result = self.exchange(upstream, request);
+            upstream.active.fetch_sub(1, Ordering::SeqCst);
+
+            let (error, retry) = match result {
+                Ok(response) => return response,
+                Err(Failure::Connect(e)) => {
+                    // the health checks will tell when it's back; without them it has to stay in the rotation
+                    if self.health_check.is_some() {
+                        upstream.set_healthy(false);
+                    }
+
+                    status = 502;
+                    (e.to_string(), true)
+                }
+                Err(Failure::Exchange(e)) => {
+                    let timed_out = matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut); // depending on the platform a socket timeout is reported as either of the two
+
+                    status = if timed_out { 504 } else { 502 };
+                    (e.to_string(), is_idempotent(request.method()))
+                }
+                Err(Failure::InvalidResponse(e)) => {
+                    status = 502;
+                    (e, false)
+                }
+            };
+
+            warn!("Cannot forward {} {} to {}: {}", request.method(), request.path(), upstream.address, error);
+
+            if !retry {
+                break;
+            }
+        }
+
+        error_response(status)
+    }
+
+    // picks an upstream that hasn't been tried yet, preferring the healthy ones
+    fn pick(&self, tried: &[usize]) -> Option<usize> {
+        let untried: Vec<usize> = (0..self.upstreams.len()).filter(|index| !tried.contains(index)).collect();
+        let healthy: Vec<usize> = untried.iter().copied().filter(|&index| self.upstreams[index].healthy.load(Ordering::SeqCst)).collect();
+
+        // when none is healthy the health checks may just be lagging behind, trying is better than refusing outright
+        let candidates = if healthy.is_empty() { untried } else { healthy };
+
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let turn = self.next.fetch_add(1, Ordering::Relaxed);
+
+        match self.balancing {
+            Balancing::RoundRobin => Some(candidates[turn % candidates.len()]),
+            Balancing::LeastConnections => (0..candidates.len())
+                .map(|offset| candidates[(turn + offset) % candidates.len()])
+                .min_by_key(|&index| self.upstreams[index].active.load(Ordering::SeqCst)),
+        }
+    }
+
+    fn exchange(&self, upstream: &Upstream, request: &Request) -> Result<Response, Failure> {
+        let mut stream = self.connect(&upstream.address).map_err(Failure::Connect)?;
+
+        stream.write_all(forwarded_head(request).as_bytes()).map_err(Failure::Exchange)?;
+
+        // the body has been read raw for the proxy, and its `Content-Length` is passed on with the other headers
+        if let Some(body) = request.body().bytes() {
+            stream.write_all(body).map_err(Failure::Exchange)?;
+        }
+
+        read_response(BufReader::new(stream), request.method(), self.max_response_size)
+    }
+
+    fn connect(&self, address: &str) -> io::Result<TcpStream> {
+        let address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the address resolves to nothing"))?;
+
+        let stream = TcpStream::connect_timeout(&address, self.connect_timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        Ok(stream)
+    }
+
+    fn check_health(&self, upstream: &Upstream, path: &str) {
+        let probe = || -> Result<u16, String> {
+            let mut stream = self.connect(&upstream.address).map_err(|e| e.to_string())?;
+            let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, upstream.address);
+
+            stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+
+            // only the status line matters
+            let mut status_line = String::new();
+            BufReader::new(stream).read_line(&mut status_line).map_err(|e| e.to_string())?;
+
+            parse_status_line(&status_line)
+        };
+
+        match probe() {
+            Ok(status) if (200..400).contains(&status) => {
+                upstream.set_healthy(true);
+            }
+            Ok(status) => {
+                if upstream.set_healthy(false) {
+                    warn!("The upstream {} is unhealthy: GET {} answers {}.", upstream.address, path, status);
+                }
+            }
+            Err(e) => {
+                if upstream.set_healthy(false) {
+                    warn!("The upstream {} is unhealthy: {}", upstream.address, e);
+                }
+            }
+        }
+    }
+}
+
+impl Upstream {
+    // returns whether that's a change, logging the recoveries; the caller logs the failures since it knows why
+    fn set_healthy(&self, healthy: bool) -> bool {
+        let changed = self.healthy.swap(healthy, Ordering::SeqCst) != healthy;
+
+        if changed && healthy {
+            info!("The upstream {} is healthy again.", self.address);
+        }
+
+        changed
+    }
+}
+
+impl Proxies {
+    pub fn new() -> Proxies {
+        Proxies::default()
+    }
+
+    /// Forwards the requests whose path starts with `prefix` (as a whole segment: `/api` covers `/api` and `/api/users`, but not `/apis`) to `proxy`. The paths are forwarded as they are.
+    pub fn route(mut self, prefix: &str, proxy: Proxy) -> Proxies {
+        self.routes.retain(|(p, _)| p != prefix);
+        self.routes.push((prefix.to_string(), Arc::new(proxy)));
+        self
+    }
+
+    /// Returns the proxy for `path`; the longest matching prefix wins.
+    pub fn get(&self, path: &str) -> Option<&Proxy> {
+        self.routes
+            .iter()
+            .filter(|(prefix, _)| covers(prefix, path))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, proxy)| proxy.as_ref())
+    }
+
+    /// Starts a thread running the health checks for every proxy that has them. The threads end once the proxies are dropped.
+    pub(crate) fn start_health_checks(&self) {
+        for (prefix, proxy) in &self.routes {
+            let Some(check) = proxy.health_check.clone() else { continue };
+
+            // a weak reference, so that the thread doesn't keep the proxy alive after the server is gone
+            let proxy = Arc::downgrade(proxy);
+            let name = format!("health-check {}", prefix);
+
+            if let Err(e) = thread::Builder::new().name(name).spawn(move || run_health_checks(proxy, check)) {
+                warn!("Cannot start the health checks for {}: {}", prefix, e);
+            }
+        }
+    }
+}
+
+fn run_health_checks(proxy: Weak<Proxy>, check: HealthCheck) {
+    while let Some(proxy) = proxy.upgrade() {
+        for upstream in &proxy.upstreams {
+            proxy.check_health(upstream, &check.path);
+        }
+
+        drop(proxy);
+        thread::sleep(check.interval);
+    }
+}
+
+// whether `prefix` covers `path` as a whole number of segments, ignoring the query string
+fn covers(prefix: &str, path: &str) -> bool {
+    let path = path.split('?').next().unwrap_or_default();
+    let prefix = prefix.trim_end_matches('/');
+
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+fn is_idempotent(method: &str) -> bool {
+    matches!(method, "GET" | "HEAD" | "OPTIONS" | "PUT" | "DELETE" | "TRACE")
+}
+
+// the headers named in `Connection` are hop-by-hop as well
+fn is_hop_by_hop(name: &str, connection: Option<&str>) -> bool {
+    HOP_BY_HOP.iter().any(|header| header.eq_ignore_ascii_case(name)) || connection.is_some_and(|value| value.split(',').any(|header| header.trim().eq_ignore_ascii_case(name)))
+}
+
+fn forwarded_head(request: &Request) -> String {
+    let connection = request.header("Connection");
+    let mut head = format!("{} {} HTTP/1.1\r\n", request.method(), request.path());
+
+    for (name, value) in request.headers() {
+        if !is_hop_by_hop(name, connection) && !name.eq_ignore_ascii_case("X-Forwarded-For") {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+
+    // every proxy on the way appends the address it has got the request from
+    let client = request.peer().map(|peer| peer.ip().to_string());
+
+    match (request.header("X-Forwarded-For"), client) {
+        (Some(forwarded), Some(client)) => head.push_str(&format!("X-Forwarded-For: {}, {}\r\n", forwarded, client)),
+        (Some(forwarded), None) => head.push_str(&format!("X-Forwarded-For: {}\r\n", forwarded)),
+        (None, Some(client)) => head.push_str(&format!("X-Forwarded-For: {}\r\n", client)),
+        (None, None) => (),
+    }
+
+    head.push_str("Connection: close\r\n\r\n");
+
+    head
+}
+
+fn parse_status_line(line: &str) -> Result<u16, String> {
+    let mut parts = line.split_whitespace();
+
+    match (parts.next(), parts.next().map(str::parse)) {
+        (Some(protocol), Some(Ok(status))) if protocol.starts_with("HTTP/") => Ok(status),
+        _ => Err(format!("invalid status line '{}'", line.trim_end())),
+    }
+}
+
+// `method` is the one of the request the response answers: the response to a `HEAD` has no body, whatever its headers say
+fn read_response<R: BufRead>(stream: R, method: &str, max_size: usize) -> Result<Response, Failure> {
+    // one byte more than allowed, so that running out of it tells a response that is too large from one that is just large enough
+    let mut stream = stream.take(max_size as u64 + 1);
+    let too_large = || Failure::InvalidResponse(format!("the response is larger than {} bytes", max_size));
+
+    let mut line = String::new();
+    stream.read_line(&mut line).map_err(Failure::Exchange)?;
+
+    let status = parse_status_line(&line).map_err(Failure::InvalidResponse)?;
+    let mut headers = Vec::new();
+
+    loop {
+        line.clear();
+
+        if stream.read_line(&mut line).map_err(Failure::Exchange)? == 0 {
+            return Err(match stream.limit() {
+                0 => too_large(),
+                _ => Failure::InvalidResponse(String::from("the response ends within the headers")),
+            });
+        }
+
+        let line = line.trim_end();
+
+        if line.is_empty() {
+            break;
+        }
+
+        let (name, value) = line.split_once(':').ok_or_else(|| Failure::InvalidResponse(format!("invalid header '{}'", line)))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let header = |name: &str| headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str());
+
+    let mut body = Vec::new();
+
+    if method == "HEAD" || matches!(status, 100..=199 | 204 | 304) {
+        // nothing follows the headers
+    } else if header("Transfer-Encoding").is_some_and(|value| value.to_ascii_lowercase().contains("chunked")) {
+        body = read_chunked(&mut stream).map_err(|failure| if stream.limit() == 0 { too_large() } else { failure })?;
+    } else if let Some(length) = header("Content-Length") {
+        let length: u64 = length.parse().map_err(|_| Failure::InvalidResponse(format!("invalid Content-Length '{}'", length)))?;
+
+        // refused before reading any of it
+        if length >= stream.limit() {
+            return Err(too_large());
+        }
+
+        (&mut stream).take(length).read_to_end(&mut body).map_err(Failure::Exchange)?;
+
+        if (body.len() as u64) < length {
+            return Err(Failure::InvalidResponse(String::from("the body is shorter than its Content-Length")));
+        }
+    } else {
+        stream.read_to_end(&mut body).map_err(Failure::Exchange)?; // the body ends with the connection
+    }
+
+    if stream.limit() == 0 {
+        return Err(too_large());
+    }
+
+    let connection = header("Connection");
+
+    // `Content-Length` is set again when the response is written
+    let mut response = headers
+        .iter()
+        .filter(|(name, _)| !is_hop_by_hop(name, connection) && !name.eq_ignore_ascii_case("Content-Length"))
+        .fold(Response::new(status), |response, (name, value)| response.header(name, value));
+
+    // from the length of the body, except for a `HEAD`, whose length is the one of the body it goes without
+    if let (true, Some(length)) = (method == "HEAD", header("Content-Length")) {
+        let length = length.parse().map_err(|_| Failure::InvalidResponse(format!("invalid Content-Length '{}'", length)))?;
+        response = response.content_length(length);
+    }
+
+    Ok(response.body(body))
+}
+
+// the chunks are a hexadecimal size line followed by that many bytes and a line break; a zero size ends the body, possibly followed by trailers
+fn read_chunked<R: BufRead>(stream: &mut io::Take<R>) -> Result<Vec<u8>, Failure> {
+    let mut body = Vec::new();
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        stream.read_line(&mut line).map_err(Failure::Exchange)?;
+
+        let size = line.split(';').next().unwrap_or_default().trim(); // chunk extensions are ignored
+        let size = usize::from_str_radix(size, 16).map_err(|_| Failure::InvalidResponse(format!("invalid chunk size '{}'", size)))?;
+
+        if size == 0 {
+            break;
+        }
+
+        // the size is whatever the upstream says, so it's checked before anything is allocated for it
+        let start = body.len();
+        let end = start
+            .checked_add(size)
+            .filter(|_| (size as u64) < stream.limit())
+            .ok_or_else(|| Failure::InvalidResponse(format!("the chunk of {} bytes makes the response too large", size)))?;
+        body.resize(end, 0);
+        stream.read_exact(&mut body[start..]).map_err(Failure::Exchange)?;
+
+        line.clear();
+        stream.read_line(&mut line).map_err(Failure::Exchange)?; // the line break after the data
+    }
+
+    // the trailers, if any, up to the empty line
+    loop {
+        line.clear();
+
+        if stream.read_line(&mut line).map_err(Failure::Exchange)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(text: &str) -> Request {
+        Request::parse(text)
+    }
+
+    #[test]
+    fn prefixes_cover_whole_segments() {
+        assert!(covers("/api", "/api"));
+        assert!(covers("/api", "/api/users?page=2"));
+        assert!(covers("/api/", "/api/users"));
+        assert!(covers("/", "/anything"));
+        assert!(!covers("/api", "/apis"));
+
+        let proxies = Proxies::new().route("/api", Proxy::new(["a:1"])).route("/api/v2", Proxy::new(["b:1"]));
+
+        assert_eq!(proxies.get("/api/v2/users").unwrap().upstreams[0].address, "b:1");
+        assert_eq!(proxies.get("/api/v1/users").unwrap().upstreams[0].address, "a:1");
+        assert!(proxies.get("/other").is_none());
+    }
+
+    #[test]
+    fn rewrites_the_headers() {
+        let mut request = request("GET /api HTTP/1.1\r\nHost: example.com\r\nConnection: keep-alive, X-Secret\r\nX-Secret: 1\r\nX-Forwarded-For: 10.0.0.1\r\n\r\n");
+        request.set_peer(Some("192.168.1.2:5000".parse().unwrap()));
+
+        assert_eq!(
+            forwarded_head(&request),
+            "GET /api HTTP/1.1\r\nHost: example.com\r\nX-Forwarded-For: 10.0.0.1, 192.168.1.2\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn balances() {
+        let proxy = Proxy::new(["a:1", "b:1", "c:1"]);
+        let picks: Vec<usize> = (0..4).map(|_| proxy.pick(&[]).unwrap()).collect();
+        assert_eq!(picks, [0, 1, 2, 0]);
+
+        // a retry goes elsewhere
+        assert_eq!(proxy.pick(&[1, 2]), Some(0));
+        assert_eq!(proxy.pick(&[0, 1, 2]), None);
+
+        let proxy = Proxy::new(["a:1", "b:1", "c:1"]).balancing(Balancing::LeastConnections);
+        proxy.upstreams[0].active.store(2, Ordering::SeqCst);
+        proxy.upstreams[1].active.store(1, Ordering::SeqCst);
+        proxy.upstreams[2].active.store(3, Ordering::SeqCst);
+        assert_eq!(proxy.pick(&[]), Some(1));
+
+        // the unhealthy ones are skipped while there are healthy ones
+        proxy.upstreams[1].healthy.store(false, Ordering::SeqCst);
+        assert_eq!(proxy.pick(&[]), Some(0));
+        assert_eq!(proxy.pick(&[0, 2]), Some(1));
+    }
+
+    #[test]
+    fn reads_responses() {
+        let chunked = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nContent-Type: text/plain\r\n\r\n5\r\nHello\r\n7;ext=1\r\n, world\r\n0\r\nX-Trailer: 1\r\n\r\n";
+        let Ok(response) = read_response(chunked.as_bytes(), "GET", 1024) else { panic!("The response is valid.") };
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body_bytes(), b"Hello, world");
+        assert_eq!(response.get_header("Transfer-Encoding"), None);
+        assert_eq!(response.get_header("Content-Type"), Some("text/plain"));
+
+        let Ok(response) = read_response("HTTP/1.0 404 Not Found\r\nContent-Length: 3\r\n\r\nabcdef".as_bytes(), "GET", 1024) else { panic!("The response is valid.") };
+        assert_eq!(response.body_bytes(), b"abc");
+
+        assert!(read_response("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nabc".as_bytes(), "GET", 1024).is_err());
+        assert!(read_response("garbage\r\n\r\n".as_bytes(), "GET", 1024).is_err());
+
+        // the response to a HEAD has no body even though it has a Content-Length, the one the body would have had
+        let Ok(response) = read_response("HTTP/1.1 200 OK\r\nContent-Length: 10\r\nContent-Type: text/plain\r\n\r\n".as_bytes(), "HEAD", 1024) else { panic!("The response is valid.") };
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body_bytes(), b"");
+        assert_eq!(response.get_header("Content-Type"), Some("text/plain"));
+        assert_eq!(response.to_bytes(), b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 10\r\n\r\n");
+    }
+
+    #[test]
+    fn limits_the_response_size() {
+        let response = |body: &str| format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+        let head = response("").len();
+
+        // the head and the body together
+        assert!(read_response(response("abc").as_bytes(), "GET", head + 3).is_ok());
+        assert!(matches!(read_response(response("abcd").as_bytes(), "GET", head + 3), Err(Failure::InvalidResponse(_))));
+
+        // a body that ends with the connection, or that is made of chunks, is cut off all the same
+        assert!(matches!(read_response("HTTP/1.1 200 OK\r\n\r\nabcdef".as_bytes(), "GET", 20), Err(Failure::InvalidResponse(_))));
+        assert!(matches!(read_response("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n10\r\n0123456789abcdef\r\n0\r\n\r\n".as_bytes(), "GET", 50), Err(Failure::InvalidResponse(_))));
+
+        // and neither a huge size nor one that would overflow gets anything allocated
+        assert!(matches!(read_response("HTTP/1.1 200 OK\r\nContent-Length: 18446744073709551615\r\n\r\n".as_bytes(), "GET", 1024), Err(Failure::InvalidResponse(_))));
+        assert!(matches!(read_response("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\nffffffffffffffff\r\n".as_bytes(), "GET", 1024), Err(Failure::InvalidResponse(_))));
+
+        // nor does a header line that never ends
+        assert!(matches!(read_response(format!("HTTP/1.1 200 OK\r\nX-Long: {}", "a".repeat(2000)).as_bytes(), "GET", 1024), Err(Failure::InvalidResponse(_))));
+    }
+}
@@ -0,0 +1,657 @@
+//! The event loop mode of the server.
+//!
+//! Instead of dedicating a worker to a connection, every worker runs a loop around its own `epoll` instance: the sockets are non-blocking, and the loop only touches a connection when the kernel reports it ready. A delayed response (like `/sleep`) is a timer rather than a sleeping thread, so a handful of workers can juggle thousands of connections.
+//!
+//! The WebSocket sessions, the proxied requests and the requests with a body are the exception: they are made of blocking I/O (a conversation driven by the endpoint, a round trip to an upstream, a body parsed as it arrives and maybe streamed to disk), so their connections leave the loop for the workers of the pool the loops don't occupy. Those are as many as the loops, with the bounded queue of the pool in front of them; when it is full, the loop answers the connection with `503 Service Unavailable` itself rather than letting the threads pile up. The per-address limit has been applied by then, and the middleware (the authentication included) runs before any of the body is read.
+//!
+//! `epoll` is Linux-specific and has no wrapper in `std`, hence the raw syscalls through `libc`.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::{self, prelude::*};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, RawFd};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::response::Response;
+use crate::request::Request;
+use crate::server::{self, ConnectionGuard, Context, Head, Route};
+use crate::{error, warn, ThreadPool};
+
+// the tokens of the two file descriptors every loop watches besides the connections, which are numbered from zero
+const LISTENER: u64 = u64::MAX;
+const WAKER: u64 = u64::MAX - 1;
+
+const MAX_EVENTS: usize = 1024;
+
+/// Runs `threads` event loops on `pool` until `limit` connections have been accepted and answered, forever if there is no limit. The WebSocket sessions still going on by then are not waited for.
+///
+/// # Errors
+/// Fails if an `epoll` instance cannot be created or waited on, in any of the loops: the others are stopped then, dropping their connections, rather than carrying on short of a loop. The errors of the individual connections are just logged.
+pub(crate) fn run(listener: &TcpListener, pool: &ThreadPool, threads: usize, context: &Arc<Context>, limit: Option<usize>) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+
+    let waker = EventFd::new()?;
+    let accepted = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+
+    pool.scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let shared = Shared {
+                    listener,
+                    pool,
+                    waker: &waker,
+                    accepted: &accepted,
+                    failed: &failed,
+                    limit,
+                    context,
+                };
+
+                s.spawn(move || {
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| EventLoop::new(shared)?.run()));
+
+                    // the scope waits for every loop, so the others must not go on as if nothing had happened
+                    if !matches!(result, Ok(Ok(()))) {
+                        shared.fail();
+                    }
+
+                    result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+                })
+            })
+            .collect();
+
+        // all the loops are joined (and a panic in any of them propagated) before the first error is returned
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|payload| panic::resume_unwind(payload)))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .collect()
+    })
+}
+
+// what the loops share: the listening socket, the pool for the connections that leave them, the connection counter and the means to tell the others to stop accepting, or to stop altogether once one of them has failed
+#[derive(Clone, Copy)]
+struct Shared<'a> {
+    listener: &'a TcpListener,
+    pool: &'a ThreadPool,
+    waker: &'a EventFd,
+    accepted: &'a AtomicUsize,
+    failed: &'a AtomicBool,
+    limit: Option<usize>,
+    context: &'a Arc<Context>,
+}
+
+impl Shared<'_> {
+    // stops all the loops, the ones blocked in `epoll_wait` included; a loop that has stopped accepting no longer watches the waker, it notices at its next event or timer instead
+    fn fail(&self) {
+        self.failed.store(true, Ordering::SeqCst);
+
+        if let Err(e) = self.waker.wake() {
+            error!("Cannot stop the event loops: {}", e);
+        }
+    }
+}
+
+struct EventLoop<'a> {
+    shared: Shared<'a>,
+    epoll: Epoll,
+    accepting: bool,
+    connections: HashMap<u64, Connection>,
+    next_token: u64,
+    timers: BinaryHeap<Reverse<(Instant, u64)>>, // `Reverse` turns the max-heap into a min-heap, the earliest deadline on top
+}
+
+struct Connection {
+    stream: TcpStream,
+    peer: Option<SocketAddr>,
+    time: SystemTime,
+    started: Instant,
+    deadline: Instant, // when the timer of the current state runs out; the timers in the heap that don't match it are stale
+    state: State,
+    _guard: Option<ConnectionGuard>, // `None` for the connections rejected for exceeding the per-address limit
+}
+
+enum Progress {
+    Head(Head),
+    Incomplete,
+    Failed(io::Error),
+}
+
+enum State {
+    Reading(Vec<u8>),
+    Waiting(Request, Route),
+    Writing {
+        request: Request,
+        status: u16,
+        response: Vec<u8>,
+        written: usize,
+        body_size: usize,
+    },
+    Draining, // answered early, reading the rest of the request so that closing doesn't reset the connection (see `server::answers_early`)
+}
+
+impl<'a> EventLoop<'a> {
+    fn new(shared: Shared<'a>) -> io::Result<EventLoop<'a>> {
+        let epoll = Epoll::new()?;
+
+        // without `EPOLLEXCLUSIVE` every loop would be woken up for every incoming connection, only for all but one of them to find nothing to accept
+        epoll.add(shared.listener.as_raw_fd(), (libc::EPOLLIN | libc::EPOLLEXCLUSIVE) as u32, LISTENER)?;
+        epoll.add(shared.waker.fd, libc::EPOLLIN as u32, WAKER)?;
+
+        Ok(EventLoop {
+            shared,
+            epoll,
+            accepting: true,
+            connections: HashMap::new(),
+            next_token: 0,
+            timers: BinaryHeap::new(),
+        })
+    }
+
+    fn run(mut self) -> io::Result<()> {
+        let mut events = Vec::with_capacity(MAX_EVENTS);
+
+        while (self.accepting || !self.connections.is_empty()) && !self.shared.failed.load(Ordering::SeqCst) {
+            let timeout = self.timers.peek().map(|Reverse((deadline, _))| deadline.saturating_duration_since(Instant::now()));
+
+            self.epoll.wait(&mut events, timeout)?;
+
+            for event in &events {
+                let (token, flags) = (event.u64, event.events); // copied out, since `epoll_event` is a packed struct on some architectures
+
+                match token {
+                    LISTENER => self.accept()?,
+                    WAKER => self.stop_accepting()?,
+                    token => self.ready(token, flags),
+                }
+            }
+
+            self.fire_timers();
+        }
+
+        Ok(())
+    }
+
+    fn accept(&mut self) -> io::Result<()> {
+        while self.accepting {
+            // claim a connection before accepting it, so that the loops together never accept more than the limit
+            let Ok(claimed) = self.claim_connection() else { break };
+
+            match self.shared.listener.accept() {
+                Ok((stream, peer)) => self.register(stream, peer),
+                Err(e) => {
+                    self.shared.accepted.fetch_sub(1, Ordering::SeqCst); // nothing accepted after all
+
+                    // `WouldBlock` just means the backlog is drained (or another loop was quicker)
+                    if e.kind() != io::ErrorKind::WouldBlock {
+                        warn!("Cannot accept a connection: {}", e);
+                    }
+
+                    break;
+                }
+            }
+
+            // that was the last one, every loop (this one included) can stop accepting and finish what it has
+            if Some(claimed + 1) == self.shared.limit {
+                self.shared.waker.wake()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn claim_connection(&self) -> Result<usize, usize> {
+        let limit = self.shared.limit.unwrap_or(usize::MAX);
+
+        self.shared
+            .accepted
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |accepted| (accepted < limit).then_some(accepted + 1))
+    }
+
+    fn stop_accepting(&mut self) -> io::Result<()> {
+        if self.accepting {
+            self.accepting = false;
+
+            // the waker stays readable once woken (which is also how the loops that start late learn about it), so it must go as well or `epoll_wait` would keep returning it
+            self.epoll.delete(self.shared.listener.as_raw_fd())?;
+            self.epoll.delete(self.shared.waker.fd)?;
+        }
+
+        Ok(())
+    }
+
+    fn register(&mut self, stream: TcpStream, peer: SocketAddr) {
+        // a single client hogging the loops with connections it never completes is what the per-address limit is for
+        let guard = self.shared.context.limiter.acquire(peer.ip());
+
+        let token = self.next_token;
+        self.next_token += 1; // unlike file descriptors the tokens are never reused, so a stale timer can't fire for a new connection
+
+        let result = stream
+            .set_nonblocking(true)
+            .and_then(|_| self.epoll.add(stream.as_raw_fd(), (libc::EPOLLIN | libc::EPOLLRDHUP) as u32, token));
+
+        if let Err(e) = result {
+            warn!("Cannot register the connection from {}: {}", peer, e);
+            return;
+        }
+
+        let started = Instant::now();
+
+        self.connections.insert(
+            token,
+            Connection {
+                stream,
+                peer: Some(peer),
+                time: SystemTime::now(),
+                started,
+                deadline: started, // set right below
+                state: State::Reading(Vec::new()),
+                _guard: guard,
+            },
+        );
+
+        self.set_deadline(token, started + self.read_timeout(started));
+
+        if self.connections[&token]._guard.is_none() {
+            warn!("Rejecting the connection from {} with 429.", peer);
+
+            if let Err(e) = self.send(token, server::parse_request(b"", Some(peer)), server::error_response(429)) {
+                warn!("Error while serving a connection: {}", e);
+                self.close(token);
+            }
+        }
+    }
+
+    // how long a reading connection may stay silent: the read timeout, unless the header deadline comes sooner
+    fn read_timeout(&self, started: Instant) -> Duration {
+        let context = self.shared.context;
+
+        (started + context.header_timeout).saturating_duration_since(Instant::now()).min(context.read_timeout)
+    }
+
+    fn set_deadline(&mut self, token: u64, deadline: Instant) {
+        if let Some(connection) = self.connections.get_mut(&token) {
+            connection.deadline = deadline;
+            self.timers.push(Reverse((deadline, token)));
+        }
+    }
+
+    fn ready(&mut self, token: u64, flags: u32) {
+        let result = match self.connections.get(&token).map(|connection| &connection.state) {
+            Some(State::Reading(_)) => self.read(token),
+            Some(State::Writing { .. }) => self.write(token),
+            Some(State::Draining) => self.drain(token),
+            // a waiting connection has no interest registered, so only a hang-up or an error can get here
+            Some(State::Waiting(..)) if flags & (libc::EPOLLHUP | libc::EPOLLERR) as u32 != 0 => Err(io::Error::from(io::ErrorKind::ConnectionReset)),
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            warn!("Error while serving a connection: {}", e);
+            self.close(token);
+        }
+    }
+
+    fn read(&mut self, token: u64) -> io::Result<()> {
+        let max_header_size = self.shared.context.max_header_size;
+        let connection = self.connections.get_mut(&token).expect("The connection is registered.");
+        let State::Reading(buffer) = &mut connection.state else { unreachable!() };
+
+        let mut chunk = [0; 1024];
+
+        let progress = loop {
+            match connection.stream.read(&mut chunk) {
+                Ok(0) if buffer.is_empty() => break Progress::Head(Head::Closed),
+                Ok(0) => break Progress::Head(Head::Complete(std::mem::take(buffer))), // a client that half-closed its side gets whatever it managed to send answered
+                Ok(bytes_read) => {
+                    buffer.extend_from_slice(&chunk[..bytes_read]);
+
+                    if let Some(head) = server::check_head(buffer, max_header_size) {
+                        break Progress::Head(head);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Progress::Incomplete, // the rest of the request will come later
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => break Progress::Failed(e),
+            }
+        };
+
+        let started = connection.started;
+
+        match progress {
+            Progress::Head(head) => self.answer(token, head),
+            Progress::Incomplete => {
+                // every bit of progress restarts the read timeout, but not the header deadline
+                let deadline = Instant::now() + self.read_timeout(started);
+                self.set_deadline(token, deadline);
+
+                Ok(())
+            }
+            Progress::Failed(e) => Err(e),
+        }
+    }
+
+    fn answer(&mut self, token: u64, head: Head) -> io::Result<()> {
+        let (head, status) = match head {
+            Head::Complete(head) => (head, None),
+            Head::TimedOut(head) => (head, Some(408)),
+            Head::TooLarge(head) => (head, Some(431)),
+            Head::Closed => {
+                self.close(token); // the client went away without asking anything
+                return Ok(());
+            }
+        };
+
+        let peer = self.connections[&token].peer;
+        let request = server::parse_request(&head, peer);
+
+        if let Some(status) = status {
+            return self.send(token, request, server::error_response(status));
+        }
+
+        let route = server::route(&request, self.shared.context);
+
+        if route.content.blocks() || request.has_body() {
+            let body_start = server::body_start(&head).to_vec();
+            return self.hand_over(token, request, body_start);
+        }
+
+        match route.delay {
+            Some(delay) => {
+                let connection = self.connections.get_mut(&token).expect("The connection is registered.");
+
+                self.epoll.modify(connection.stream.as_raw_fd(), 0, token)?; // nothing to do until the timer fires
+                connection.state = State::Waiting(request, route);
+                self.set_deadline(token, Instant::now() + delay);
+
+                Ok(())
+            }
+            None => self.respond(token, request, route),
+        }
+    }
+
+    fn hand_over(&mut self, token: u64, request: Request, body_start: Vec<u8>) -> io::Result<()> {
+        let connection = self.connections.remove(&token).expect("The connection is registered.");
+        let fd = connection.stream.as_raw_fd();
+        self.epoll.delete(fd)?; // before the job gets to close the socket, which would free the descriptor for another connection
+
+        let context = Arc::clone(self.shared.context);
+
+        // the job can't be taken apart again when the queue is full, so it takes the connection out of a slot that the loop can take it back from
+        let slot = Arc::new(Mutex::new(Some((connection, request))));
+        let job = {
+            let slot = Arc::clone(&slot);
+
+            move || {
+                let Some((connection, request)) = slot.lock().expect("The slot mutex is in a poisoned state.").take() else { return };
+
+                if let Err(e) = serve_blocking(connection, request, &body_start, &context) {
+                    warn!("Error while serving a connection: {}", e);
+                }
+            }
+        };
+
+        if self.shared.pool.try_execute(job).is_ok() {
+            return Ok(());
+        }
+
+        // the pool is saturated; the connection goes back to the loop to be answered and drained like any other early answer
+        let (connection, request) = slot.lock().expect("The slot mutex is in a poisoned state.").take().expect("The job was dropped without running.");
+        warn!("Rejecting the connection from {:?} with 503.", connection.peer);
+
+        self.epoll.add(fd, (libc::EPOLLIN | libc::EPOLLRDHUP) as u32, token)?;
+        self.connections.insert(token, connection);
+
+        self.send(token, request, server::unavailable_response(self.shared.context))
+    }
+
+    fn respond(&mut self, token: u64, mut request: Request, route: Route) -> io::Result<()> {
+        // reading a (small, likely cached) file blocks the loop for a moment, but `epoll` can't help with regular files anyway
+        let response = server::respond(&mut request, &route, self.shared.context);
+
+        self.send(token, request, response)
+    }
+
+    fn send(&mut self, token: u64, request: Request, response: Response) -> io::Result<()> {
+        let connection = self.connections.get_mut(&token).expect("The connection is registered.");
+
+        connection.state = State::Writing {
+            request,
+            status: response.status(),
+            response: response.to_bytes(),
+            written: 0,
+            body_size: response.body_bytes().len(),
+        };
+
+        self.epoll.modify(connection.stream.as_raw_fd(), libc::EPOLLOUT as u32, token)?;
+        self.set_deadline(token, Instant::now() + self.shared.context.write_timeout);
+
+        self.write(token) // most of the time the whole response fits into the socket buffer right away
+    }
+
+    fn write(&mut self, token: u64) -> io::Result<()> {
+        let connection = self.connections.get_mut(&token).expect("The connection is registered.");
+        let State::Writing {
+            request,
+            status,
+            response,
+            written,
+            body_size,
+        } = &mut connection.state
+        else {
+            unreachable!()
+        };
+
+        while *written < response.len() {
+            match connection.stream.write(&response[*written..]) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+                Ok(bytes_written) => *written += bytes_written,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()), // `EPOLLOUT` will tell when there is room again
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        server::record(self.shared.context, request, connection.time, *status, *body_size, connection.started.elapsed());
+
+        if !server::answers_early(*status) {
+            self.close(token);
+            return Ok(());
+        }
+
+        connection.stream.shutdown(Shutdown::Write)?;
+        connection.state = State::Draining;
+
+        self.epoll.modify(connection.stream.as_raw_fd(), (libc::EPOLLIN | libc::EPOLLRDHUP) as u32, token)?;
+        self.set_deadline(token, Instant::now() + server::LINGER_TIMEOUT);
+
+        self.drain(token)
+    }
+
+    fn drain(&mut self, token: u64) -> io::Result<()> {
+        let connection = self.connections.get_mut(&token).expect("The connection is registered.");
+        let mut chunk = [0; 1024];
+
+        loop {
+            match connection.stream.read(&mut chunk) {
+                Ok(0) => break, // the client has closed its side, so can we
+                Ok(_) => (),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.close(token);
+
+        Ok(())
+    }
+
+    fn fire_timers(&mut self) {
+        let now = Instant::now();
+
+        while let Some(&Reverse((deadline, token))) = self.timers.peek() {
+            if deadline > now {
+                break;
+            }
+
+            self.timers.pop();
+
+            // the connection may have been closed in the meantime, or moved on to another deadline
+            let Some(connection) = self.connections.get_mut(&token) else { continue };
+
+            if connection.deadline != deadline {
+                continue;
+            }
+
+            let result = match std::mem::replace(&mut connection.state, State::Reading(Vec::new())) {
+                State::Reading(head) => self.answer(token, Head::TimedOut(head)),
+                State::Waiting(request, route) => self.respond(token, request, route),
+                State::Writing { .. } => Err(io::Error::new(io::ErrorKind::TimedOut, "the client stopped reading the response")),
+                State::Draining => {
+                    self.close(token); // lingered long enough
+                    Ok(())
+                }
+            };
+
+            if let Err(e) = result {
+                warn!("Error while serving a connection: {}", e);
+                self.close(token);
+            }
+        }
+    }
+
+    fn close(&mut self, token: u64) {
+        if let Some(connection) = self.connections.remove(&token) {
+            let _ = self.epoll.delete(connection.stream.as_raw_fd()); // closing the socket would deregister it anyway; it is dropped right after
+        }
+    }
+}
+
+// answers a request the way the threaded mode does, on a worker of the pool rather than in the loop
+fn serve_blocking(connection: Connection, request: Request, body_start: &[u8], context: &Context) -> io::Result<()> {
+    let Connection {
+        mut stream,
+        time,
+        started,
+        _guard, // keeps counting against the per-address limit until the connection is closed
+        ..
+    } = connection;
+
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(context.write_timeout))?;
+    let socket = stream.try_clone()?;
+
+    if server::answer(&mut stream, &socket, request, body_start, context, time, started)? {
+        server::linger(&socket, server::LINGER_TIMEOUT);
+    }
+
+    Ok(())
+}
+
+// a thin safe wrapper around an `epoll` instance
+struct Epoll {
+    fd: RawFd,
+}
+
+impl Epoll {
+    fn new() -> io::Result<Epoll> {
+        // SAFETY: `epoll_create1` has no preconditions
+        let fd = check(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+
+        Ok(Epoll { fd })
+    }
+
+    fn add(&self, fd: RawFd, events: u32, token: u64) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_ADD, fd, events, token)
+    }
+
+    fn modify(&self, fd: RawFd, events: u32, token: u64) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_MOD, fd, events, token)
+    }
+
+    fn delete(&self, fd: RawFd) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_DEL, fd, 0, 0)
+    }
+
+    fn control(&self, operation: libc::c_int, fd: RawFd, events: u32, token: u64) -> io::Result<()> {
+        let mut event = libc::epoll_event { events, u64: token };
+
+        // SAFETY: `event` is a valid `epoll_event` living across the call; an invalid `fd` is reported as an error rather than being undefined behaviour
+        check(unsafe { libc::epoll_ctl(self.fd, operation, fd, &mut event) })?;
+
+        Ok(())
+    }
+
+    // fills `events` with the ready events, waiting for at most `timeout` (forever if `None`) for at least one
+    fn wait(&self, events: &mut Vec<libc::epoll_event>, timeout: Option<Duration>) -> io::Result<()> {
+        // rounded up, otherwise a timer due in less than a millisecond would make us spin
+        let timeout = timeout.map_or(-1, |timeout| timeout.as_nanos().div_ceil(1_000_000).min(libc::c_int::MAX as u128) as libc::c_int);
+
+        events.clear();
+
+        // SAFETY: the kernel writes at most `capacity` events into the spare capacity of `events`
+        let count = unsafe { libc::epoll_wait(self.fd, events.as_mut_ptr(), events.capacity() as libc::c_int, timeout) };
+
+        match check(count) {
+            // SAFETY: the first `count` events have just been initialized by the kernel
+            Ok(count) => unsafe { events.set_len(count as usize) },
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (), // a signal; the caller checks the timers and waits again
+            Err(e) => return Err(e),
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Epoll {
+    fn drop(&mut self) {
+        // SAFETY: the descriptor is owned by `self` and closed exactly once
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+// an `eventfd` the loops use to wake each other up: once written to, it is readable in every `epoll` it is registered in
+struct EventFd {
+    fd: RawFd,
+}
+
+impl EventFd {
+    fn new() -> io::Result<EventFd> {
+        // SAFETY: `eventfd` has no preconditions
+        let fd = check(unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) })?;
+
+        Ok(EventFd { fd })
+    }
+
+    fn wake(&self) -> io::Result<()> {
+        let value: u64 = 1;
+
+        // SAFETY: `value` is a valid 8-byte buffer, which is exactly what `eventfd` expects
+        check(unsafe { libc::write(self.fd, &value as *const u64 as *const libc::c_void, 8) as libc::c_int })?;
+
+        Ok(())
+    }
+}
+
+impl Drop for EventFd {
+    fn drop(&mut self) {
+        // SAFETY: the descriptor is owned by `self` and closed exactly once
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+// turns the C convention of returning `-1` and setting `errno` into a `Result`
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
@@ -0,0 +1,135 @@
+//! HTTP requests: the request line, the headers and the body.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use crate::body::{Body, Form};
+use crate::json::Json;
+
+/// The request line, the headers and the body of a request, and where it has come from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    method: String,
+    path: String,
+    protocol: String,
+    headers: Vec<(String, String)>,
+    body: Body,
+    peer: Option<SocketAddr>,
+}
+
+impl Request {
+    /// Parses the request line and the headers; missing parts of the request line become `-`, as in the access log. The body is read separately, by the server (see the `body` module).
+    ///
+    /// # Example
+    /// ```
+    /// use hello::request::Request;
+    ///
+    /// let request = Request::parse("GET /sleep HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    ///
+    /// assert_eq!(request.method(), "GET");
+    /// assert_eq!(request.path(), "/sleep");
+    /// assert_eq!(request.header("host"), Some("localhost"));
+    /// ```
+    pub fn parse(request: &str) -> Request {
+        let mut lines = request.lines();
+
+        // the request line looks like `GET /sleep HTTP/1.1`
+        let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+        let mut next = || request_line.next().unwrap_or("-").to_string();
+        let (method, path, protocol) = (next(), next(), next());
+
+        let headers = lines
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+
+        Request {
+            method,
+            path,
+            protocol,
+            headers,
+            body: Body::None,
+            peer: None,
+        }
+    }
+
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn protocol(&self) -> &str {
+        &self.protocol
+    }
+
+    /// The address of the client, if known; a request that has only been parsed doesn't have it.
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    pub(crate) fn set_peer(&mut self, peer: Option<SocketAddr>) {
+        self.peer = peer;
+    }
+
+    /// Returns the value of the header `name` (case-insensitive), if present.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    /// Returns all the headers in the order they were received.
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// Replaces the header `name` or adds it if it is missing. Lets the middleware pass information on to the handlers.
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    /// Whether the headers announce a body, which the server then reads before answering.
+    pub fn has_body(&self) -> bool {
+        self.header("Content-Length").is_some_and(|length| length.trim() != "0") || self.header("Transfer-Encoding").is_some()
+    }
+
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    pub(crate) fn set_body(&mut self, body: Body) {
+        self.body = body;
+    }
+
+    /// Returns the submitted form, if the body is one (URL-encoded or multipart).
+    pub fn form(&self) -> Option<&Form> {
+        match &self.body {
+            Body::Form(form) => Some(form),
+            _ => None,
+        }
+    }
+
+    /// Returns the JSON value, if the body is one.
+    pub fn json(&self) -> Option<&Json> {
+        match &self.body {
+            Body::Json(json) => Some(json),
+            _ => None,
+        }
+    }
+
+    /// Parses the form field `name`, or the member `name` of a JSON body, as a `T`.
+    ///
+    /// # Errors
+    /// Fails if there is no such field, or if it isn't a valid `T`.
+    pub fn field<T>(&self, name: &str) -> Result<T, String>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.body.field(name)
+    }
+}
@@ -0,0 +1,294 @@
+//! HTTP responses: a builder for the status, the headers and the body, and the compression of the body according to the client's `Accept-Encoding`.
+
+use std::io::{self, prelude::*};
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+
+/// An HTTP/1.1 response.
+///
+/// # Example
+/// ```
+/// use hello::response::Response;
+///
+/// let response = Response::new(404).header("Content-Type", "text/plain").body("Nothing here");
+///
+/// assert_eq!(response.to_bytes(), b"HTTP/1.1 404 NOT FOUND\r\nContent-Type: text/plain\r\nContent-Length: 12\r\n\r\nNothing here");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    content_length: Option<u64>, // announced instead of the length of the body
+}
+
+/// A content coding the server can compress the bodies with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    /// Note that `deflate` in HTTP means the zlib format (RFC 1950), not a raw deflate stream.
+    Deflate,
+}
+
+impl Response {
+    /// Creates a response with the given status code, no headers and an empty body.
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+            content_length: None,
+        }
+    }
+
+    /// Adds a header. `Content-Length` is set automatically when the response is written and should not be added by hand.
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets the body.
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// Announces a `Content-Length` of `length` rather than the length of the body. Only meant for the response to a `HEAD`, which tells the size of the body a `GET` would get without sending it.
+    pub fn content_length(mut self, length: u64) -> Response {
+        self.content_length = Some(length);
+        self
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// The reason phrase of the status, e.g. `NOT FOUND` for `404`.
+    pub fn reason(&self) -> &'static str {
+        reason_phrase(self.status)
+    }
+
+    /// Returns the value of the first header called `name` (case-insensitive), if any.
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    pub fn body_bytes(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Compresses the body if it is text, at least `threshold` bytes long and the client accepts gzip or deflate according to `accept_encoding` (the value of its `Accept-Encoding` header).
+    ///
+    /// A response that could have been compressed gets `Vary: Accept-Encoding` whether it was or not, so that caches don't hand a compressed body to a client that can't decode it (or the other way around).
+    pub fn compress(self, accept_encoding: Option<&str>, threshold: usize) -> Response {
+        if self.body.len() < threshold || !self.get_header("Content-Type").is_some_and(is_compressible) || self.get_header("Content-Encoding").is_some() {
+            return self;
+        }
+
+        let response = self.header("Vary", "Accept-Encoding");
+
+        let Some(encoding) = accept_encoding.and_then(negotiate_encoding) else {
+            return response;
+        };
+
+        match encoding.encode(&response.body) {
+            Ok(body) => response.header("Content-Encoding", encoding.name()).body(body),
+            Err(_) => response, // compressing into memory can't really fail, but the uncompressed body is a fine answer anyway
+        }
+    }
+
+    /// Serializes the status line, the headers (with `Content-Length` appended unless the status forbids a body) and the body.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason_phrase(self.status));
+
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        // the informational responses (like `101 Switching Protocols`), `204 No Content` and `304 Not Modified` have no body by definition, so they must not announce one
+        if !matches!(self.status, 100..=199 | 204 | 304) {
+            head.push_str(&format!("Content-Length: {}\r\n", self.content_length.unwrap_or(self.body.len() as u64)));
+        }
+
+        head.push_str("\r\n");
+
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+
+        bytes
+    }
+
+    /// Writes the whole response to `stream` and flushes it.
+    ///
+    /// # Errors
+    /// The same as `Write::write_all` and `Write::flush`.
+    pub fn write_to<W: Write>(&self, stream: &mut W) -> io::Result<()> {
+        stream.write_all(&self.to_bytes())?;
+        stream.flush()
+    }
+}
+
+impl Encoding {
+    /// The name of the coding in `Accept-Encoding` and `Content-Encoding`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    fn encode(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Picks the coding to compress with from the value of an `Accept-Encoding` header, e.g. `gzip;q=0.8, deflate, *;q=0`.
+///
+/// The coding with the highest quality wins, gzip on a tie; `*` stands for any coding not listed explicitly and `q=0` means "not acceptable". Returns `None` if neither gzip nor deflate is acceptable.
+pub fn negotiate_encoding(accept_encoding: &str) -> Option<Encoding> {
+    let codings: Vec<(String, f32)> = accept_encoding
+        .split(',')
+        .filter_map(|coding| {
+            let mut parameters = coding.split(';');
+            let name = parameters.next()?.trim().to_ascii_lowercase();
+
+            if name.is_empty() {
+                return None;
+            }
+
+            // a malformed quality makes the coding unacceptable rather than preferred
+            let quality = parameters
+                .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+                .map(|quality| quality.trim().parse().unwrap_or(0.0))
+                .next()
+                .unwrap_or(1.0);
+
+            Some((name, quality))
+        })
+        .collect();
+
+    let quality = |names: &[&str]| {
+        codings
+            .iter()
+            .find(|(name, _)| names.contains(&name.as_str()))
+            .or_else(|| codings.iter().find(|(name, _)| name == "*"))
+            .map_or(0.0, |(_, quality)| *quality)
+    };
+
+    let gzip = quality(&["gzip", "x-gzip"]);
+    let deflate = quality(&["deflate"]);
+
+    if gzip > 0.0 && gzip >= deflate {
+        Some(Encoding::Gzip)
+    } else if deflate > 0.0 {
+        Some(Encoding::Deflate)
+    } else {
+        None
+    }
+}
+
+// images, archives and the like are compressed already, squeezing them again is just a waste of CPU
+fn is_compressible(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+
+    media_type.starts_with("text/") || ["application/json", "application/javascript", "application/xml", "image/svg+xml"].contains(&media_type.as_str())
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        101 => "SWITCHING PROTOCOLS",
+        200 => "OK",
+        201 => "CREATED",
+        204 => "NO CONTENT",
+        301 => "MOVED PERMANENTLY",
+        302 => "FOUND",
+        304 => "NOT MODIFIED",
+        400 => "BAD REQUEST",
+        401 => "UNAUTHORIZED",
+        403 => "FORBIDDEN",
+        404 => "NOT FOUND",
+        408 => "REQUEST TIMEOUT",
+        411 => "LENGTH REQUIRED",
+        413 => "PAYLOAD TOO LARGE",
+        421 => "MISDIRECTED REQUEST",
+        426 => "UPGRADE REQUIRED",
+        429 => "TOO MANY REQUESTS",
+        431 => "REQUEST HEADER FIELDS TOO LARGE",
+        500 => "INTERNAL SERVER ERROR",
+        502 => "BAD GATEWAY",
+        503 => "SERVICE UNAVAILABLE",
+        504 => "GATEWAY TIMEOUT",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::read::{GzDecoder, ZlibDecoder};
+
+    fn page(size: usize) -> Response {
+        Response::new(200).header("Content-Type", "text/html; charset=utf-8").body("a".repeat(size))
+    }
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(negotiate_encoding("gzip, deflate, br"), Some(Encoding::Gzip));
+        assert_eq!(negotiate_encoding("deflate, gzip;q=0.5"), Some(Encoding::Deflate));
+        assert_eq!(negotiate_encoding("GZIP;q=0.1, deflate;q=0.1"), Some(Encoding::Gzip));
+        assert_eq!(negotiate_encoding("br, *;q=0.3"), Some(Encoding::Gzip));
+        assert_eq!(negotiate_encoding("*, gzip;q=0"), Some(Encoding::Deflate));
+        assert_eq!(negotiate_encoding("identity"), None);
+        assert_eq!(negotiate_encoding("gzip;q=0, deflate;q=0"), None);
+        assert_eq!(negotiate_encoding("gzip;q=high"), None);
+        assert_eq!(negotiate_encoding(""), None);
+    }
+
+    #[test]
+    fn compresses_large_text_bodies() {
+        let response = page(2000).compress(Some("gzip"), 1024);
+
+        assert_eq!(response.get_header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.get_header("Vary"), Some("Accept-Encoding"));
+
+        let mut decoded = String::new();
+        GzDecoder::new(response.body_bytes()).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, "a".repeat(2000));
+
+        let response = page(2000).compress(Some("deflate"), 1024);
+
+        let mut decoded = String::new();
+        ZlibDecoder::new(response.body_bytes()).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, "a".repeat(2000));
+    }
+
+    #[test]
+    fn varies_even_when_not_compressed() {
+        let response = page(2000).compress(None, 1024);
+
+        assert_eq!(response.get_header("Content-Encoding"), None);
+        assert_eq!(response.get_header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.body_bytes().len(), 2000);
+    }
+
+    #[test]
+    fn leaves_small_and_binary_bodies_alone() {
+        assert_eq!(page(100).compress(Some("gzip"), 1024), page(100));
+
+        let image = Response::new(200).header("Content-Type", "image/png").body(vec![0; 2000]);
+        assert_eq!(image.clone().compress(Some("gzip"), 1024), image);
+    }
+}
@@ -0,0 +1,384 @@
+//! The job queue behind `ThreadPool`.
+//!
+//! Instead of having all the workers take turns on a single `Mutex<mpsc::Receiver>` every worker owns a deque of its own. The jobs are spread over the deques round-robin, wherever they are submitted from, so that submitting rarely contends for the same mutex.
+//!
+//! Every job is numbered as it comes in, and a worker always takes the oldest one at the front of any of the deques, so the jobs run in the order they came in no matter which deque they landed in or which worker happens to be free. A job parked in the deque of a busy worker therefore isn't left behind the ones that came after it.
+//!
+//! Every deque is in fact three, one per `Priority`: a worker looks for a high priority job everywhere before it settles for a normal one, and for a normal one before a background one. Within a priority the jobs still run in the order they came in.
+//!
+//! The scheduler also keeps track of how many workers there are and decides when one should retire. Every worker occupies a slot (its deque); slots are never removed, a retired worker just leaves its slot vacant for the next worker to take. Whatever is left in a vacant deque gets stolen by the others.
+
+use std::collections::VecDeque;
+use std::hint;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use std::thread;
+use std::time::Duration;
+
+use crate::{Job, Priority};
+
+// how many times a worker that has run out of jobs looks for new ones before going to sleep
+const SPIN_ROUNDS: u32 = 64;
+
+pub(crate) struct Scheduler {
+    slots: RwLock<Vec<Slot>>,
+    queued: AtomicUsize, // the number of jobs accepted but not yet picked up by any worker (including the ones that are about to be pushed)
+    capacity: Option<usize>, // `None` means the queue is unbounded
+    next: AtomicUsize, // the slot the next job goes to
+    sequence: AtomicU64, // the number of the next job, which tells the order the jobs came in
+    workers: AtomicUsize, // the number of occupied slots
+    active: AtomicUsize, // the number of workers running a job right now
+    min_workers: AtomicUsize,
+    max_workers: AtomicUsize,
+    idle_timeout: Duration,
+    sleepers: AtomicUsize, // the number of workers waiting for `work_available`
+    shutdown: AtomicBool,
+    lock: Mutex<()>, // both condition variables need a mutex, the state they guard is in the atomics above though
+    work_available: Condvar,
+    not_full: Condvar,
+}
+
+struct Slot {
+    deque: Mutex<Lanes>,
+    occupied: AtomicBool,
+}
+
+// one queue per priority, the highest first, of the jobs along with their numbers
+type Lanes = [VecDeque<(u64, Entry)>; Priority::COUNT];
+
+/// A queued job, along with the ticket to cancel it with, if it can be cancelled.
+pub(crate) struct Entry {
+    pub(crate) job: Job,
+    pub(crate) ticket: Option<Arc<Ticket>>,
+}
+
+/// The state of a cancellable job, shared by the queue and the `CancellationToken` of the job. Whoever moves it out of `QUEUED` first decides whether the job runs.
+#[derive(Debug)]
+pub(crate) struct Ticket {
+    state: AtomicU8,
+}
+
+const QUEUED: u8 = 0;
+const STARTED: u8 = 1;
+const CANCELLED: u8 = 2;
+
+impl Scheduler {
+    pub(crate) fn new(min_workers: usize, max_workers: usize, idle_timeout: Duration, capacity: Option<usize>) -> Scheduler {
+        Scheduler {
+            slots: RwLock::new(Vec::new()),
+            queued: AtomicUsize::new(0),
+            capacity,
+            next: AtomicUsize::new(0),
+            sequence: AtomicU64::new(0),
+            workers: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            min_workers: AtomicUsize::new(min_workers),
+            max_workers: AtomicUsize::new(max_workers),
+            idle_timeout,
+            sleepers: AtomicUsize::new(0),
+            shutdown: AtomicBool::new(false),
+            lock: Mutex::new(()),
+            work_available: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    }
+
+    /// Takes a place in the queue, waiting for one to free up if the queue is full. Has to be followed by `push_reserved`.
+    pub(crate) fn reserve(&self) {
+        if self.try_reserve() {
+            return;
+        }
+
+        let mut guard = self.lock();
+
+        while !self.try_reserve() {
+            guard = self.not_full.wait(guard).expect("The scheduler mutex is in a poisoned state.");
+        }
+    }
+
+    /// Takes a place in the queue unless the queue is full. Has to be followed by `push_reserved` if successful.
+    pub(crate) fn try_reserve(&self) -> bool {
+        match self.capacity {
+            None => {
+                self.queued.fetch_add(1, Ordering::SeqCst);
+
+                true
+            }
+            Some(capacity) => self
+                .queued
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| (queued < capacity).then_some(queued + 1))
+                .is_ok(),
+        }
+    }
+
+    /// Puts `entry` into the lane for `priority` of one of the deques and wakes up a sleeping worker, if there is one.
+    pub(crate) fn push_reserved(&self, entry: Entry, priority: Priority) {
+        {
+            let slots = self.slots();
+            let index = self.next.fetch_add(1, Ordering::Relaxed) % slots.len();
+            let mut lanes = lock_deque(&slots[index].deque);
+
+            // numbered with the deque locked, so that within a deque the numbers only ever grow from the front to the back
+            let sequence = self.sequence.fetch_add(1, Ordering::SeqCst);
+            lanes[priority as usize].push_back((sequence, entry));
+        }
+
+        // `queued` has been incremented before checking `sleepers`, and a worker increments `sleepers` before checking `queued`, so at least one of the two sides notices the other and no wakeup gets lost
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            let _guard = self.lock();
+
+            self.work_available.notify_one();
+        }
+    }
+
+    /// Takes the job of `ticket`, which has just been cancelled, out of the queue, so that it neither waits for a worker nor takes up room any longer. The job is dropped.
+    pub(crate) fn remove(&self, ticket: &Arc<Ticket>, priority: Priority) {
+        let removed = self.slots().iter().find_map(|slot| {
+            let mut lanes = lock_deque(&slot.deque);
+            let lane = &mut lanes[priority as usize];
+
+            lane.iter().position(|(_, entry)| entry.ticket.as_ref().is_some_and(|t| Arc::ptr_eq(t, ticket))).and_then(|position| lane.remove(position))
+        });
+
+        // not finding it means a worker has popped it in the meantime, and will drop it instead of running it
+        if removed.is_some() {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            self.notify_not_full();
+        }
+    }
+
+    /// Returns the number of jobs waiting for a free worker.
+    pub(crate) fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of workers.
+    pub(crate) fn workers(&self) -> usize {
+        self.workers.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of workers running a job.
+    pub(crate) fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the pool is below its minimum size, or if there are more jobs waiting than idle workers to take them and there is still room for another worker.
+    pub(crate) fn needs_worker(&self) -> bool {
+        let workers = self.workers();
+        let idle = workers.saturating_sub(self.active());
+
+        !self.shutdown.load(Ordering::SeqCst)
+            && (workers < self.min_workers.load(Ordering::SeqCst)
+                || (workers < self.max_workers.load(Ordering::SeqCst) && self.queued() > idle))
+    }
+
+    /// Occupies a vacant slot for a new worker and returns its index, unless the pool has reached its maximum size.
+    pub(crate) fn claim_slot(&self) -> Option<usize> {
+        let max_workers = self.max_workers.load(Ordering::SeqCst);
+
+        self.workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |workers| (workers < max_workers).then_some(workers + 1))
+            .ok()?;
+
+        let mut slots = self.slots.write().expect("The slots lock is in a poisoned state.");
+
+        match slots.iter().position(|slot| !slot.occupied.load(Ordering::SeqCst)) {
+            Some(index) => {
+                slots[index].occupied.store(true, Ordering::SeqCst);
+
+                Some(index)
+            }
+            None => {
+                slots.push(Slot {
+                    deque: Mutex::new(Default::default()),
+                    occupied: AtomicBool::new(true),
+                });
+
+                Some(slots.len() - 1)
+            }
+        }
+    }
+
+    /// Changes the bounds of the pool size. Wakes up all the sleeping workers so that the ones that are no longer needed can retire.
+    pub(crate) fn set_bounds(&self, min_workers: usize, max_workers: usize) {
+        self.min_workers.store(min_workers, Ordering::SeqCst);
+        self.max_workers.store(max_workers, Ordering::SeqCst);
+
+        self.wake_all();
+    }
+
+    /// Blocks until there is a job for the worker in the slot `index`. Returns `None` if the worker should retire: either because the scheduler is shut down and all the queued jobs are taken, or because the pool is larger than it needs to be.
+    pub(crate) fn pop(&self, index: usize) -> Option<Job> {
+        let mut idle_rounds = 0;
+
+        loop {
+            if self.try_retire(index, self.max_workers.load(Ordering::SeqCst)) {
+                // whatever is left in the deque will be stolen, but only if somebody is awake to notice
+                self.wake_all();
+
+                return None;
+            }
+
+            if let Some(entry) = self.find_job() {
+                self.job_taken();
+
+                if entry.ticket.as_ref().is_none_or(|ticket| ticket.start()) {
+                    return Some(entry.job);
+                }
+
+                // cancelled while it was on its way to this worker
+                self.job_done();
+
+                continue;
+            }
+
+            // going to sleep and being woken up costs much more than a tiny job, so before that we keep looking for a while
+            if self.queued.load(Ordering::SeqCst) > 0 || idle_rounds < SPIN_ROUNDS {
+                idle_rounds += 1;
+
+                if idle_rounds % 8 == 0 {
+                    thread::yield_now();
+                } else {
+                    hint::spin_loop();
+                }
+
+                continue;
+            }
+
+            idle_rounds = 0;
+
+            let guard = self.lock();
+
+            self.sleepers.fetch_add(1, Ordering::SeqCst);
+
+            let mut retire = false;
+
+            if self.queued.load(Ordering::SeqCst) == 0 {
+                if self.shutdown.load(Ordering::SeqCst) {
+                    retire = self.try_retire(index, 0);
+                } else {
+                    let (_guard, timeout) = self
+                        .work_available
+                        .wait_timeout(guard, self.idle_timeout)
+                        .expect("The scheduler mutex is in a poisoned state.");
+
+                    // having been idle for the whole timeout the worker is only worth keeping if the pool would be too small without it
+                    retire = timeout.timed_out()
+                        && self.queued.load(Ordering::SeqCst) == 0
+                        && self.try_retire(index, self.min_workers.load(Ordering::SeqCst));
+                }
+            }
+
+            self.sleepers.fetch_sub(1, Ordering::SeqCst);
+
+            if retire {
+                return None;
+            }
+        }
+    }
+
+    /// Marks the job the worker has taken with `pop` as finished.
+    pub(crate) fn job_done(&self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Lets the workers finish once there are no more jobs left.
+    pub(crate) fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+
+        self.wake_all();
+    }
+
+    // vacates the slot `index` if there are more than `keep` workers
+    fn try_retire(&self, index: usize, keep: usize) -> bool {
+        let retired = self
+            .workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |workers| (workers > keep).then(|| workers - 1))
+            .is_ok();
+
+        if retired {
+            self.slots()[index].occupied.store(false, Ordering::SeqCst);
+        }
+
+        retired
+    }
+
+    fn find_job(&self) -> Option<Entry> {
+        let slots = self.slots();
+
+        (0..Priority::COUNT).find_map(|lane| loop {
+            // the oldest job of the lane is at the front of one of the deques
+            let (sequence, owner) = slots
+                .iter()
+                .enumerate()
+                .filter_map(|(owner, slot)| lock_deque(&slot.deque)[lane].front().map(|(sequence, _)| (*sequence, owner)))
+                .min()?;
+
+            let mut lanes = lock_deque(&slots[owner].deque);
+
+            // another worker may have taken it in the meantime, then we look again
+            if lanes[lane].front().is_some_and(|(front, _)| *front == sequence) {
+                return lanes[lane].pop_front().map(|(_, entry)| entry);
+            }
+        })
+    }
+
+    fn job_taken(&self) {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        self.notify_not_full();
+    }
+
+    fn notify_not_full(&self) {
+        if self.capacity.is_some() {
+            let _guard = self.lock();
+
+            self.not_full.notify_one();
+        }
+    }
+
+    fn wake_all(&self) {
+        let _guard = self.lock();
+
+        self.work_available.notify_all();
+    }
+
+    fn slots(&self) -> RwLockReadGuard<'_, Vec<Slot>> {
+        self.slots.read().expect("The slots lock is in a poisoned state.")
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().expect("The scheduler mutex is in a poisoned state.")
+    }
+}
+
+impl Ticket {
+    pub(crate) fn new() -> Ticket {
+        Ticket {
+            state: AtomicU8::new(QUEUED),
+        }
+    }
+
+    /// Returns `false` if the job has already been cancelled and must not run.
+    fn start(&self) -> bool {
+        self.state.compare_exchange(QUEUED, STARTED, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+    }
+
+    /// Returns `false` if the job has already been started (or cancelled).
+    pub(crate) fn cancel(&self) -> bool {
+        self.state.compare_exchange(QUEUED, CANCELLED, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+    }
+
+    pub(crate) fn is_queued(&self) -> bool {
+        self.state.load(Ordering::SeqCst) == QUEUED
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.state.load(Ordering::SeqCst) == CANCELLED
+    }
+}
+
+fn lock_deque(deque: &Mutex<Lanes>) -> MutexGuard<'_, Lanes> {
+    // jobs never run while the deque is locked, so the mutex can't really be poisoned
+    deque.lock().expect("The deque mutex is in a poisoned state.")
+}
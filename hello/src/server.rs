@@ -0,0 +1,898 @@
+//! The web server itself: accepts the connections, optionally terminates TLS on them and hands them over to a `ThreadPool`.
+
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::collections::HashMap;
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::body::{self, Limits};
+use crate::handlers::Handlers;
+use crate::logging::{self, AccessRecord};
+use crate::metrics::Metrics;
+use crate::middleware::{CatchPanic, Chain, RequestId};
+use crate::proxy::Proxies;
+use crate::request::Request;
+use crate::response::Response;
+use crate::template::Value;
+#[cfg(target_os = "linux")]
+use crate::reactor;
+use crate::virtual_hosts::{Site, VirtualHosts};
+use crate::websocket::{self, Endpoints, Role, Transport, WebSocket};
+use crate::{debug, warn, CancellationToken, Monitor, Priority, ThreadPool};
+
+/// Represents the configuration of the server.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The address to listen on, e.g. `127.0.0.1:7878`.
+    pub address: String,
+    /// The number of threads serving the connections.
+    pub workers: usize,
+    /// The maximum number of accepted connections waiting for a free worker; the connections above that are answered with `503 Service Unavailable`. In the threaded mode a slow request (like `/sleep`) waiting in the queue is given up first, to make room for a faster one.
+    pub queue_capacity: usize,
+    /// The directory the pages (templates, see the `template` module) of the default site are served from.
+    pub root: PathBuf,
+    /// Serve HTTPS instead of plain HTTP if set.
+    pub tls: Option<TlsConfig>,
+    /// How the connections are spread over the workers.
+    pub mode: Mode,
+    /// Text bodies at least this long are compressed for the clients that accept it.
+    pub compression_threshold: usize,
+    /// For how long a client may stay silent while sending its request before it is answered with `408 Request Timeout`.
+    pub read_timeout: Duration,
+    /// For how long writing a response may stall before the connection is dropped.
+    pub write_timeout: Duration,
+    /// How long a client has to send the whole request line and headers, however busily it trickles them in. This is what stops a slowloris attack.
+    pub header_timeout: Duration,
+    /// The maximum size of the request line and the headers together; larger requests are answered with `431 Request Header Fields Too Large`.
+    pub max_header_size: usize,
+    /// The maximum size of a request body kept in memory: a form, JSON or anything else, and the fields of a multipart form together; larger bodies are answered with `413 Payload Too Large`.
+    pub max_body_size: usize,
+    /// The maximum size of a `multipart/form-data` body, the uploaded files included, which are stored in `upload_dir` rather than in memory.
+    pub max_upload_size: usize,
+    /// Where the uploaded files are kept while their request is being handled.
+    pub upload_dir: PathBuf,
+    /// The maximum number of connections a single IP address may have open at once; the ones above that are answered with `429 Too Many Requests`. Unlimited if `None`.
+    pub connections_per_ip: Option<usize>,
+    /// The middleware to run around the handlers. The errors the server detects before a request is read in full (timeouts, oversized headers, too many connections) are answered without it; the body is read once the middleware has let the request through, so e.g. an unauthorized upload is refused before it is buffered.
+    pub middleware: Chain,
+    /// The WebSocket endpoints by path, none by default. The handshake goes through the middleware like any other request; the session that follows keeps a thread busy for as long as it lasts (see the `websocket` module).
+    pub websockets: Endpoints,
+    /// For how long a WebSocket session may go without a frame from the client. Past that the endpoint's `receive` fails with a timeout, which is what keeps an idle client from holding on to a thread forever.
+    pub websocket_idle_timeout: Duration,
+    /// The path prefixes forwarded to upstream servers. They take precedence over the pages, the longest prefix wins.
+    pub proxies: Proxies,
+    /// The handlers of the application by method and path (see the `handlers` module), none by default. They take precedence over the pages, but not over the proxies.
+    pub handlers: Handlers,
+    /// The sites served by the name in the `Host` header (see the `virtual_hosts` module). `root`, `websockets`, `proxies` and `handlers` above make the default site, which serves the requests for all the other names unless the virtual hosts are strict.
+    pub virtual_hosts: VirtualHosts,
+}
+
+/// How the server multiplexes the connections onto its threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Every connection occupies a worker from the moment it is accepted until the response is written, so a slow request (like `/sleep`) keeps a worker to itself.
+    ///
+    /// While all the workers are busy the connections wait in the queue of the pool by the priority of their route: `/metrics` goes ahead of everything, `/sleep` waits until there's nothing else to do.
+    Threaded,
+    /// Every worker runs an `epoll` event loop multiplexing any number of non-blocking connections. Linux only, and without TLS.
+    ///
+    /// The connections that need blocking I/O (the WebSocket sessions, the proxied requests and the requests with a body) are passed on to as many threads again, with the queue of the pool in front of them; they are answered with `503 Service Unavailable` when it is full.
+    EventLoop,
+}
+
+/// The certificate and the private key to terminate TLS with.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// A PEM file with the certificate chain, the server's own certificate first.
+    pub certificate: PathBuf,
+    /// A PEM file with the private key (PKCS#1, PKCS#8 or SEC1).
+    pub private_key: PathBuf,
+}
+
+pub struct Server {
+    listener: TcpListener,
+    pool: ThreadPool,
+    context: Arc<Context>,
+    tls: Option<Arc<ServerConfig>>,
+    mode: Mode,
+    workers: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            address: String::from("127.0.0.1:7878"),
+            workers: 4,
+            queue_capacity: 16, // a bounded queue keeps a connection flood from piling up in memory
+            root: PathBuf::from("resources"),
+            tls: None,
+            mode: Mode::Threaded,
+            compression_threshold: 1024, // below about a kilobyte the savings are eaten up by the overhead of the compression
+            read_timeout: Duration::from_secs(5),
+            write_timeout: Duration::from_secs(10),
+            header_timeout: Duration::from_secs(10),
+            max_header_size: 8 * 1024,
+            max_body_size: 1024 * 1024,
+            max_upload_size: 16 * 1024 * 1024,
+            upload_dir: env::temp_dir().join("hello-uploads"),
+            connections_per_ip: None,
+            middleware: Chain::new().with(CatchPanic).with(RequestId::new()),
+            websockets: Endpoints::new(),
+            websocket_idle_timeout: Duration::from_secs(60),
+            proxies: Proxies::new(),
+            handlers: Handlers::new(),
+            virtual_hosts: VirtualHosts::new(),
+        }
+    }
+}
+
+impl Server {
+    /// Binds the listening socket and loads the TLS certificate, if any.
+    ///
+    /// # Errors
+    /// Fails if the address cannot be bound to, if the certificate or the key cannot be loaded, or if the event loop mode is asked for with TLS or on a platform other than Linux.
+    ///
+    /// # Panics
+    /// `bind` function will panic if the number of workers or the queue capacity is zero.
+    pub fn bind(config: Config) -> Result<Server, Box<dyn Error>> {
+        if config.mode == Mode::EventLoop {
+            if !cfg!(target_os = "linux") {
+                return Err("The event loop mode is only supported on Linux.".into());
+            }
+
+            if config.tls.is_some() {
+                return Err("TLS is not supported in the event loop mode.".into());
+            }
+        }
+
+        let tls = match &config.tls {
+            Some(tls) => Some(Arc::new(load_tls_config(tls)?)),
+            None => None,
+        };
+
+        let listener = TcpListener::bind(&config.address).map_err(|e| format!("Cannot bind to {}: {}", config.address, e))?;
+
+        // in the event loop mode the loops take one half of the workers, the connections that leave them (see the `reactor` module) the other
+        let threads = match config.mode {
+            Mode::Threaded => config.workers,
+            Mode::EventLoop => config.workers * 2,
+        };
+        let pool = ThreadPool::with_queue_capacity(threads, config.queue_capacity);
+
+        config.proxies.start_health_checks();
+
+        for (_, site) in config.virtual_hosts.sites() {
+            site.proxies.start_health_checks();
+        }
+
+        Ok(Server {
+            listener,
+            context: Arc::new(Context {
+                default_site: Site::new(config.root).websockets(config.websockets).proxies(config.proxies).handlers(config.handlers),
+                virtual_hosts: config.virtual_hosts,
+                compression_threshold: config.compression_threshold,
+                read_timeout: config.read_timeout,
+                websocket_idle_timeout: config.websocket_idle_timeout,
+                write_timeout: config.write_timeout,
+                header_timeout: config.header_timeout,
+                max_header_size: config.max_header_size,
+                body_limits: Limits {
+                    max_size: config.max_body_size,
+                    max_upload_size: config.max_upload_size,
+                    upload_dir: config.upload_dir,
+                },
+                limiter: Arc::new(ConnectionLimiter::new(config.connections_per_ip)),
+                metrics: Metrics::new(),
+                pool: pool.monitor(),
+                middleware: config.middleware,
+            }),
+            pool,
+            tls,
+            mode: config.mode,
+            workers: config.workers,
+        })
+    }
+
+    /// Returns the address the server listens on. Useful when bound to port `0`, i.e. to whatever port the OS has picked.
+    ///
+    /// # Errors
+    /// The same as `std::net::TcpListener::local_addr`.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts the connections and serves them. Returns after `limit` connections if it is set (and, in the event loop mode, answered), never otherwise.
+    pub fn serve(&self, limit: Option<usize>) {
+        match self.mode {
+            Mode::Threaded => self.serve_threaded(limit),
+            #[cfg(target_os = "linux")]
+            Mode::EventLoop => {
+                if let Err(e) = reactor::run(&self.listener, &self.pool, self.workers, &self.context, limit) {
+                    crate::error!("The event loop has failed: {}", e);
+                }
+            }
+            #[cfg(not(target_os = "linux"))]
+            Mode::EventLoop => unreachable!("`bind` refuses the event loop mode outside of Linux"),
+        }
+    }
+
+    fn serve_threaded(&self, limit: Option<usize>) {
+        // the jobs queued while the pool was saturated that may be background ones, most recent last, to make room for more urgent ones when the queue is full
+        let mut queued: Vec<Queued> = Vec::new();
+        let mut head = vec![0; self.context.max_header_size]; // for `classify`
+
+        for stream in self.listener.incoming().take(limit.unwrap_or(usize::MAX)) {
+            queued.retain(|job| job.token.is_queued()); // the ones that have started (or finished) can't be cancelled any longer
+
+            // in fact we're iterating over connection attempts (not connections), which might be unsuccessful
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Cannot accept a connection: {}", e);
+                    continue;
+                }
+            };
+
+            // a single client hogging the workers with connections it never completes is what the per-address limit is for
+            let guard = match stream.peer_addr().map(|peer| self.context.limiter.acquire(peer.ip())) {
+                Ok(Some(guard)) => guard,
+                Ok(None) => {
+                    reject_connection(stream, 429, &self.context, self.tls.is_some());
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Cannot get the address of the peer: {}", e);
+                    continue;
+                }
+            };
+
+            // the order only matters if the connection is going to wait; the request of a TLS connection can't be seen before the handshake
+            let classified = self.tls.is_none() && self.pool.metrics().idle_workers == 0;
+            let priority = if classified { classify(&stream, &mut head, &self.context) } else { Some(Priority::Normal) };
+
+            // the job takes the stream out of the slot when it starts; until then the accepting thread can still peek at the request, or answer it if the job is cancelled
+            let slot = Arc::new(Mutex::new(Some(stream)));
+            let context = Arc::clone(&self.context);
+            let tls = self.tls.clone();
+
+            let job = {
+                let slot = Arc::clone(&slot);
+
+                move || {
+                    let Some(stream) = lock_slot(&slot).take() else { return };
+
+                    if let Err(e) = serve_connection(stream, &context, tls) {
+                        warn!("Error while serving a connection: {}", e);
+                    }
+
+                    drop(guard); // the connection is closed by now
+                }
+            };
+
+            // when the pool is saturated we'd rather tell the client right away than make it wait indefinitely, unless there's a background job to give up instead
+            let submit_with = priority.unwrap_or(Priority::Normal);
+            let submitted = match self.pool.try_execute_with(submit_with, job) {
+                Err(job) if submit_with != Priority::Background && self.make_room(&mut queued, &mut head) => self.pool.try_execute_with(submit_with, job),
+                submitted => submitted,
+            };
+
+            match submitted {
+                Ok(token) if classified && priority.is_none_or(|priority| priority == Priority::Background) => queued.push(Queued { token, slot, priority }),
+                Ok(_) => (),
+                Err(job) => {
+                    drop(job);
+
+                    if let Some(stream) = lock_slot(&slot).take() {
+                        reject_connection(stream, 503, &self.context, self.tls.is_some());
+                    }
+                }
+            }
+        }
+    }
+
+    // cancels the most recently queued background job that hasn't started yet and answers its connection with `503`; returns `false` if there is none
+    fn make_room(&self, queued: &mut Vec<Queued>, head: &mut [u8]) -> bool {
+        for index in (0..queued.len()).rev() {
+            let job = &mut queued[index];
+
+            // the request wasn't all there when the connection was accepted, by now it has had the time to arrive
+            if job.priority.is_none() {
+                job.priority = lock_slot(&job.slot).as_ref().and_then(|stream| classify(stream, head, &self.context));
+            }
+
+            if job.priority == Some(Priority::Background) && job.token.cancel() {
+                let job = queued.remove(index);
+
+                if let Some(stream) = lock_slot(&job.slot).take() {
+                    reject_connection(stream, 503, &self.context, self.tls.is_some());
+                }
+
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+// a job in the queue of the pool as the accepting thread sees it; `priority` is `None` until the request has been classified
+struct Queued {
+    token: CancellationToken,
+    slot: Arc<Mutex<Option<TcpStream>>>,
+    priority: Option<Priority>,
+}
+
+fn lock_slot(slot: &Mutex<Option<TcpStream>>) -> MutexGuard<'_, Option<TcpStream>> {
+    slot.lock().expect("The connection slot is in a poisoned state.")
+}
+
+// the priority of the route of the request on `stream`, peeked at into `head` without consuming it; `None` if the request isn't all there yet
+fn classify(stream: &TcpStream, head: &mut [u8], context: &Context) -> Option<Priority> {
+    // never waiting for the request keeps a silent client from stalling the accepting thread
+    let peeked = stream.set_nonblocking(true).and_then(|_| stream.peek(head));
+    let _ = stream.set_nonblocking(false);
+
+    match peeked {
+        Ok(size) if head[..size].windows(4).any(|window| window == b"\r\n\r\n") => Some(route(&parse_request(&head[..size], stream.peer_addr().ok()), context).priority),
+        _ => None, // `WouldBlock` included
+    }
+}
+
+fn load_tls_config(config: &TlsConfig) -> Result<ServerConfig, Box<dyn Error>> {
+    let certificates = CertificateDer::pem_file_iter(&config.certificate)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Cannot load the certificate from {}: {}", config.certificate.display(), e))?;
+
+    let private_key = PrivateKeyDer::from_pem_file(&config.private_key)
+        .map_err(|e| format!("Cannot load the private key from {}: {}", config.private_key.display(), e))?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certificates, private_key)
+        .map_err(|e| format!("Invalid certificate or private key: {}", e))?;
+
+    Ok(config)
+}
+
+fn serve_connection(stream: TcpStream, context: &Context, tls: Option<Arc<ServerConfig>>) -> Result<(), Box<dyn Error>> {
+    let peer = stream.peer_addr().ok();
+
+    // the timeouts are options of the socket itself, so this handle sets them for the TLS stream wrapping `stream` as well
+    let socket = stream.try_clone()?;
+    socket.set_write_timeout(Some(context.write_timeout))?;
+
+    match tls {
+        Some(tls) => {
+            // the handshake happens lazily, on the first read from the stream
+            let mut stream = StreamOwned::new(ServerConnection::new(tls)?, stream);
+
+            let early = handle_connection(&mut stream, &socket, peer, context)?;
+
+            // tell the client we are done rather than just dropping the connection, otherwise it can't tell a complete response from a truncated one
+            stream.conn.send_close_notify();
+            stream.flush()?;
+
+            if early {
+                linger(&socket, LINGER_TIMEOUT);
+            }
+        }
+        None => {
+            let mut stream = stream;
+
+            if handle_connection(&mut stream, &socket, peer, context)? {
+                linger(&socket, LINGER_TIMEOUT);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn reject_connection(mut stream: TcpStream, status: u16, context: &Context, tls: bool) {
+    warn!("Rejecting the connection from {:?} with {}.", stream.peer_addr().ok(), status);
+
+    // answering over TLS would mean doing the handshake on the accepting thread, which is exactly what we are trying to avoid, so TLS connections are simply closed
+    if tls {
+        return;
+    }
+
+    let response = match status {
+        503 => unavailable_response(context),
+        status => error_response(status),
+    };
+
+    // the response is tiny and fits into the socket buffer, still, a client that doesn't read must not stall the accepting thread
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+    let _ = response.write_to(&mut stream); // the client may be gone already, there's nothing to do about it
+
+    // only a very short linger here, on the accepting thread; enough for a request sent right after connecting
+    linger(&stream, Duration::from_millis(50));
+}
+
+/// The answer to a connection there is no room for: the `503.html` page of the default site, with a hint to try again shortly.
+pub(crate) fn unavailable_response(context: &Context) -> Response {
+    Response::new(503)
+        .header("Retry-After", "1")
+        .header("Content-Type", "text/html; charset=utf-8")
+        .header("Connection", "close")
+        .body(fs::read_to_string(context.default_site.root.join("503.html")).unwrap_or_default())
+}
+
+/// How long to wait for a client to finish sending a request that has been answered early.
+pub(crate) const LINGER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether `status` is sent before the client has finished its request. Closing the connection right after such a response would make the kernel reset it because of the unread data, which can destroy the response before the client gets to read it, so the rest of the request is read (and thrown away) first.
+pub(crate) fn answers_early(status: u16) -> bool {
+    matches!(status, 408 | 429 | 431 | 503)
+}
+
+// a "lingering close": stop writing, then discard whatever the client still sends until it closes its side or `timeout` passes
+pub(crate) fn linger(socket: &TcpStream, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+
+    if socket.shutdown(Shutdown::Write).is_err() {
+        return; // most likely gone already
+    }
+
+    let mut socket = socket;
+    let mut buffer = [0; 1024];
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        if remaining.is_zero() || socket.set_read_timeout(Some(remaining)).is_err() {
+            return;
+        }
+
+        match socket.read(&mut buffer) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => (),
+        }
+    }
+}
+
+// returns whether the response has been sent before the whole request was read (see `answers_early`)
+// note that `stream` parameter is mutable; the reason is that a stream keeps track of what data it returns to us internally, it might read more data than we asked for and save that data for the next time we ask for data - it therefore needs to be `mut` because its internal state might change
+fn handle_connection<S: Read + Write>(stream: &mut S, socket: &TcpStream, peer: Option<SocketAddr>, context: &Context) -> io::Result<bool> {
+    let started = Instant::now();
+    let time = SystemTime::now();
+
+    let (request, response) = match read_head(stream, socket, context, started)? {
+        Head::Complete(head) => return answer(stream, socket, parse_request(&head, peer), body_start(&head), context, time, started),
+        Head::TimedOut(head) => (parse_request(&head, peer), error_response(408)),
+        Head::TooLarge(head) => (parse_request(&head, peer), error_response(431)),
+        Head::Closed => return Ok(false), // the client went away without asking anything
+    };
+
+    response.write_to(stream)?; // flushing inside will wait and prevent the program from continuing until all the bytes are written to the connection - `TcpStream` contains an internal buffer to minimize calls to the underlying operating system
+
+    record(context, &request, time, response.status(), response.body_bytes().len(), started.elapsed());
+
+    Ok(answers_early(response.status()))
+}
+
+/// Parses the request line and the headers read from `peer`.
+pub(crate) fn parse_request(head: &[u8], peer: Option<SocketAddr>) -> Request {
+    let mut request = Request::parse(&String::from_utf8_lossy(head));
+    request.set_peer(peer);
+
+    request
+}
+
+/// Returns what has been read past the end of the head: the beginning of the body, if any.
+pub(crate) fn body_start(head: &[u8]) -> &[u8] {
+    match head.windows(4).position(|window| window == b"\r\n\r\n") {
+        Some(end) => &head[end + 4..],
+        None => &[],
+    }
+}
+
+/// Answers a request whose head has been read over a blocking `stream`: waits out the delay of its route, if any, reads its body (which starts with `body_start`) once the middleware has let it through, responds and records it, or hands the connection over to a WebSocket endpoint. Returns whether the response has been sent early (see `answers_early`).
+pub(crate) fn answer<S: Read + Write>(stream: &mut S, socket: &TcpStream, mut request: Request, body_start: &[u8], context: &Context, time: SystemTime, started: Instant) -> io::Result<bool> {
+    let route = route(&request, context);
+
+    if let Content::WebSocket = route.content {
+        upgrade(stream, socket, request, context, time, started)?;
+        return Ok(false);
+    }
+
+    if request.has_body() {
+        // the header deadline is over, from now on only the read timeout applies
+        socket.set_read_timeout(Some(context.read_timeout))?;
+    }
+
+    if let Some(delay) = route.delay {
+        thread::sleep(delay);
+    }
+
+    // the body is read only once the middleware has let the request through, so that e.g. a client that isn't authorized cannot make us buffer its upload first
+    let unread = Cell::new(request.has_body());
+    let stream = RefCell::new(stream);
+
+    let response = respond_with(&mut request, context, &|request| {
+        if unread.get() {
+            // a proxy passes the body on as it is, whatever its type
+            let raw = matches!(route.content, Content::Proxy);
+            let mut stream = stream.borrow_mut();
+
+            match body::read(body_start.chain(&mut **stream), request, raw, &context.body_limits) {
+                Ok(body) => {
+                    request.set_body(body);
+                    unread.set(false);
+                }
+                Err(e) => {
+                    debug!("Cannot read the body of {} {}: {}", request.method(), request.path(), e);
+                    return error_response(e.status());
+                }
+            }
+        }
+
+        handle(request, &route, context)
+    });
+
+    response.write_to(stream.into_inner())?;
+
+    record(context, &request, time, response.status(), response.body_bytes().len(), started.elapsed());
+
+    Ok(unread.get() || answers_early(response.status())) // a body that was refused or failed to be read is still (partly) on its way
+}
+
+// answers the WebSocket handshake of a request to one of the endpoints and, if it succeeds, runs the endpoint's session over `stream`
+fn upgrade<S: Read + Write>(stream: &mut S, socket: &TcpStream, mut request: Request, context: &Context, time: SystemTime, started: Instant) -> io::Result<()> {
+    let site = context.site(&request).expect("The request is to a site.");
+    let handler = Arc::clone(site.websockets.get(request.path()).expect("The request is to an endpoint."));
+
+    // the middleware gets to refuse the upgrade, e.g. for a missing authorization
+    let response = close_connection(context.middleware.handle(&mut request, &|request| websocket::handshake(request)));
+    response.write_to(stream)?;
+
+    record(context, &request, time, response.status(), response.body_bytes().len(), started.elapsed());
+
+    if response.status() != 101 {
+        return Ok(());
+    }
+
+    // a session may well stay quiet for longer than a request, but not forever: an idle client would keep the thread to itself
+    socket.set_read_timeout(Some(context.websocket_idle_timeout))?;
+
+    let mut websocket = WebSocket::new(stream as &mut dyn Transport, Role::Server);
+    handler(&mut websocket, &request);
+
+    // close the session properly if the endpoint hasn't, but don't wait for an unresponsive client for too long
+    if !websocket.is_closed() {
+        socket.set_read_timeout(Some(context.read_timeout))?;
+
+        if let Err(e) = websocket.close(1000, "") {
+            warn!("Cannot close the WebSocket session: {}", e);
+        }
+    }
+
+    // a session failed for a protocol violation leaves the rest of the offending frame unread, which closing right away would turn into a reset
+    linger(socket, LINGER_TIMEOUT);
+
+    Ok(())
+}
+
+/// How reading the request line and the headers has ended. Everything but `Closed` carries what has been received.
+pub(crate) enum Head {
+    Complete(Vec<u8>),
+    TimedOut(Vec<u8>),
+    TooLarge(Vec<u8>),
+    Closed,
+}
+
+// reads until the empty line ending the headers, giving up on a client that is silent for longer than the read timeout or hasn't finished by the header deadline
+fn read_head<S: Read>(stream: &mut S, socket: &TcpStream, context: &Context, started: Instant) -> io::Result<Head> {
+    let deadline = started + context.header_timeout;
+
+    let mut head = Vec::new();
+    let mut chunk = [0; 1024];
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        if remaining.is_zero() {
+            return Ok(Head::TimedOut(head));
+        }
+
+        socket.set_read_timeout(Some(remaining.min(context.read_timeout)))?;
+
+        match stream.read(&mut chunk) {
+            Ok(0) if head.is_empty() => return Ok(Head::Closed),
+            Ok(0) => return Ok(Head::Complete(head)), // a client that half-closed its side gets whatever it managed to send answered
+            Ok(bytes_read) => head.extend_from_slice(&chunk[..bytes_read]),
+            // depending on the platform a socket timeout is reported as either of the two
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(Head::TimedOut(head)),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+
+        if let Some(head) = check_head(&mut head, context.max_header_size) {
+            return Ok(head);
+        }
+    }
+}
+
+/// Tells whether `head` (received so far) is complete or already too large; `None` means more is to be read.
+pub(crate) fn check_head(head: &mut Vec<u8>, max_size: usize) -> Option<Head> {
+    match head.windows(4).position(|window| window == b"\r\n\r\n") {
+        Some(end) if end + 4 <= max_size => Some(Head::Complete(std::mem::take(head))),
+        Some(_) => Some(Head::TooLarge(std::mem::take(head))),
+        None if head.len() >= max_size => Some(Head::TooLarge(std::mem::take(head))),
+        None => None,
+    }
+}
+
+/// What the server knows when answering a request, shared by all the workers.
+#[derive(Debug)]
+pub(crate) struct Context {
+    pub default_site: Site,
+    pub virtual_hosts: VirtualHosts,
+    pub compression_threshold: usize,
+    pub read_timeout: Duration,
+    pub websocket_idle_timeout: Duration,
+    pub write_timeout: Duration,
+    pub header_timeout: Duration,
+    pub max_header_size: usize,
+    pub body_limits: Limits,
+    pub limiter: Arc<ConnectionLimiter>,
+    pub metrics: Metrics,
+    pub pool: Monitor,
+    pub middleware: Chain,
+}
+
+impl Context {
+    /// The site `request` is for, by its `Host` header; `None` if the virtual hosts are strict and none of them is for that host.
+    pub fn site(&self, request: &Request) -> Option<&Site> {
+        match self.virtual_hosts.find(request.header("Host").unwrap_or_default()) {
+            Some(site) => Some(site),
+            None if self.virtual_hosts.is_strict() => None,
+            None => Some(&self.default_site),
+        }
+    }
+}
+
+/// Keeps count of the open connections per client address.
+#[derive(Debug)]
+pub(crate) struct ConnectionLimiter {
+    max: Option<usize>,
+    connections: Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// Counts as an open connection of its address until dropped.
+#[derive(Debug)]
+pub(crate) struct ConnectionGuard {
+    limiter: Arc<ConnectionLimiter>,
+    address: IpAddr,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max: Option<usize>) -> ConnectionLimiter {
+        ConnectionLimiter {
+            max,
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Registers another connection from `address`, unless it already has as many as allowed.
+    pub fn acquire(self: &Arc<Self>, address: IpAddr) -> Option<ConnectionGuard> {
+        let mut connections = self.connections.lock().expect("The connection counts are in a poisoned state.");
+        let count = connections.entry(address).or_insert(0);
+
+        if self.max.is_some_and(|max| *count >= max) {
+            return None;
+        }
+
+        *count += 1;
+
+        Some(ConnectionGuard {
+            limiter: Arc::clone(self),
+            address,
+        })
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.limiter.connections.lock().expect("The connection counts are in a poisoned state.");
+
+        if let Some(count) = connections.get_mut(&self.address) {
+            *count -= 1;
+
+            // forget the addresses without connections, otherwise the map would only ever grow
+            if *count == 0 {
+                connections.remove(&self.address);
+            }
+        }
+    }
+}
+
+/// What to answer a request with.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Route {
+    /// The name of the route in the metrics; a fixed set of them rather than the raw paths, which the clients could make up endlessly.
+    pub name: &'static str,
+    pub status: u16,
+    pub content: Content,
+    /// How long to wait before answering. The threaded mode just sleeps on the worker, the event loop sets a timer instead.
+    pub delay: Option<Duration>,
+    /// How urgently a connection waiting for a worker should be served. Only the threaded mode queues the connections, see `Mode::Threaded`.
+    pub priority: Priority,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Content {
+    /// A page rendered from a template in the root directory.
+    Page(&'static str),
+    Metrics,
+    /// A handshake to one of the WebSocket endpoints.
+    WebSocket,
+    /// A request forwarded to one of the proxies.
+    Proxy,
+    /// A request for a host none of the sites is for.
+    Misdirected,
+    /// A request to one of the handlers of the application.
+    Handler,
+}
+
+impl Content {
+    /// Whether answering takes blocking network I/O (a WebSocket session or a round trip to an upstream), which the event loop must not wait for.
+    pub fn blocks(self) -> bool {
+        matches!(self, Content::WebSocket | Content::Proxy)
+    }
+}
+
+pub(crate) fn route(request: &Request, context: &Context) -> Route {
+    let ok = Route {
+        name: "/",
+        status: 200,
+        content: Content::Page("hello.html"),
+        delay: None,
+        priority: Priority::Normal,
+    };
+
+    // the routers of the site go first, then the built-in routes, which every site has
+    let Some(site) = context.site(request) else {
+        return Route {
+            name: "misdirected",
+            status: 421,
+            content: Content::Misdirected,
+            ..ok
+        };
+    };
+
+    if site.websockets.get(request.path()).is_some() {
+        return Route {
+            name: "websocket",
+            content: Content::WebSocket,
+            ..ok
+        };
+    }
+
+    if site.proxies.get(request.path()).is_some() {
+        return Route {
+            name: "proxy",
+            content: Content::Proxy,
+            ..ok
+        };
+    }
+
+    if site.handlers.get(request.method(), request.path()).is_some() {
+        return Route {
+            name: "handler",
+            content: Content::Handler,
+            ..ok
+        };
+    }
+
+    match (request.method(), request.path()) {
+        ("GET", "/") => ok,
+        ("GET", "/sleep") => Route {
+            name: "/sleep",
+            delay: Some(Duration::from_secs(5)),
+            priority: Priority::Background,
+            ..ok
+        },
+        ("GET", "/metrics") => Route {
+            name: "/metrics",
+            content: Content::Metrics,
+            priority: Priority::High, // a scrape is how the operator finds out the server is overloaded in the first place
+            ..ok
+        },
+        _ => Route {
+            name: "other",
+            status: 404,
+            content: Content::Page("404.html"),
+            ..ok
+        },
+    }
+}
+
+/// Builds the response for a routed request by running the middleware around the handler, then compresses it if the client accepts that.
+///
+/// The delay of the route, if any, must have been waited out already.
+pub(crate) fn respond(request: &mut Request, route: &Route, context: &Context) -> Response {
+    respond_with(request, context, &|request| handle(request, route, context))
+}
+
+fn respond_with(request: &mut Request, context: &Context, handler: &dyn Fn(&mut Request) -> Response) -> Response {
+    let response = close_connection(context.middleware.handle(request, handler));
+
+    response.compress(request.header("Accept-Encoding"), context.compression_threshold)
+}
+
+fn close_connection(response: Response) -> Response {
+    match response.get_header("Connection") {
+        Some(_) => response, // e.g. `Upgrade` for a WebSocket handshake
+        None => response.header("Connection", "close"), // every connection serves a single request
+    }
+}
+
+fn handle(request: &Request, route: &Route, context: &Context) -> Response {
+    let site = || context.site(request).expect("The request is to a site.");
+
+    let (content_type, contents) = match route.content {
+        Content::Misdirected => return error_response(421),
+        Content::Proxy => return site().proxies.get(request.path()).expect("The request is to a proxy.").forward(request),
+        Content::Handler => return site().handlers.get(request.method(), request.path()).expect("The request is to a handler.")(request),
+        Content::WebSocket => unreachable!("the WebSocket handshakes are answered by `upgrade`"),
+        Content::Page(name) => match site().templates.render(name, &page_values(request, site())) {
+            Ok(contents) => ("text/html; charset=utf-8", contents),
+            Err(e) => {
+                warn!("Cannot render {}: {}", name, e);
+                return error_response(500);
+            }
+        },
+        Content::Metrics => ("text/plain; version=0.0.4; charset=utf-8", context.metrics.render(&context.pool.metrics())),
+    };
+
+    Response::new(route.status).header("Content-Type", content_type).body(contents)
+}
+
+// what every page can use; the request comes straight from the client, but the templates escape everything they insert
+fn page_values(request: &Request, site: &Site) -> Value {
+    [
+        ("method", Value::from(request.method())),
+        ("path", Value::from(request.path())),
+        ("websockets", Value::from(site.websockets.paths().collect::<Vec<_>>())),
+    ]
+    .into_iter()
+    .collect()
+}
+
+/// A plain text response for the errors the server detects itself, like timeouts.
+pub(crate) fn error_response(status: u16) -> Response {
+    let response = Response::new(status);
+    let body = format!("{}\n", response.reason());
+
+    response.header("Content-Type", "text/plain; charset=utf-8").header("Connection", "close").body(body)
+}
+
+/// Logs an answered request to the access log and counts it in the metrics.
+pub(crate) fn record(context: &Context, request: &Request, time: SystemTime, status: u16, bytes: usize, latency: Duration) {
+    context.metrics.observe(route(request, context).name, status, latency);
+
+    logging::access(&AccessRecord {
+        remote: request.peer(),
+        time,
+        method: request.method(),
+        path: request.path(),
+        protocol: request.protocol(),
+        status,
+        bytes,
+        latency,
+        referer: request.header("Referer"),
+        user_agent: request.header("User-Agent"),
+    });
+}
@@ -0,0 +1,530 @@
+//! A minimal template engine for the HTML pages.
+//!
+//! The syntax is a small subset of what Jinja and the like offer:
+//!
+//! - `{{ name }}` inserts a value, HTML-escaped; `{{ user.name }}` looks a key up in a map,
+//! - `{% if name %}...{% else %}...{% endif %}`, or `{% if not name %}`; missing values, `false` and empty texts, lists and maps are false, everything else is true,
+//! - `{% for item in list %}...{% endfor %}`,
+//! - `{% include "other.html" %}` renders another template of the same directory in place, with the same values,
+//! - `{# ... #}` is a comment.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+// a template including itself (directly or not) would recurse forever otherwise
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// What the templates are rendered with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Text(String),
+    Bool(bool),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+/// A parsed template.
+#[derive(Debug)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+/// The templates of a directory, each one read and parsed on first use and kept from then on; changes to the files are not picked up afterwards.
+#[derive(Debug)]
+pub struct Templates {
+    root: PathBuf,
+    cache: RwLock<HashMap<String, Arc<Template>>>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Variable(Vec<String>), // the path to the value, e.g. `["user", "name"]` for `{{ user.name }}`
+    If {
+        negated: bool,
+        condition: Vec<String>,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        variable: String,
+        list: Vec<String>,
+        body: Vec<Node>,
+    },
+    Include(String),
+}
+
+enum Token<'s> {
+    Text(&'s str),
+    Variable(&'s str, usize), // the contents between the braces and the line it's on
+    Tag(&'s str, usize),
+}
+
+// what rendering keeps track of; `'v` is the lifetime of the values, the loop variables included since they point into the lists
+struct Environment<'t, 'v> {
+    templates: Option<&'t Templates>,
+    values: &'v Value,
+    scopes: Vec<(String, &'v Value)>, // the loop variables, the innermost last
+    depth: usize,
+}
+
+impl Value {
+    /// Looks `key` up if this is a map.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    fn is_true(&self) -> bool {
+        match self {
+            Value::Text(text) => !text.is_empty(),
+            Value::Bool(value) => *value,
+            Value::List(list) => !list.is_empty(),
+            Value::Map(map) => !map.is_empty(),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Value {
+        Value::Text(text.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Value {
+        Value::Text(text)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Value {
+        Value::Bool(value)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Value {
+        Value::Text(value.to_string())
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(list: Vec<T>) -> Value {
+        Value::List(list.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Collects key-value pairs into a map.
+impl<K: Into<String>, V: Into<Value>> FromIterator<(K, V)> for Value {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(pairs: I) -> Value {
+        Value::Map(pairs.into_iter().map(|(key, value)| (key.into(), value.into())).collect())
+    }
+}
+
+impl Template {
+    /// Parses `source`.
+    ///
+    /// # Errors
+    /// Fails on a syntax error, telling the line it's on.
+    ///
+    /// # Example
+    /// ```
+    /// use hello::template::{Template, Value};
+    ///
+    /// let template = Template::parse("{% for fruit in fruits %}<li>{{ fruit }}</li>{% endfor %}").unwrap();
+    /// let values: Value = [("fruits", vec!["apples", "<oranges>"])].into_iter().collect();
+    ///
+    /// assert_eq!(template.render(&values).unwrap(), "<li>apples</li><li>&lt;oranges&gt;</li>");
+    /// ```
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let mut tokens = tokenize(source)?.into_iter();
+        let (nodes, _) = parse_block(&mut tokens, &[])?;
+
+        Ok(Template { nodes })
+    }
+
+    /// Renders the template with `values`, which must be a map. Use `Templates` to render the templates that include others.
+    ///
+    /// # Errors
+    /// Fails if a value to insert is missing or is not a text (or a boolean), or if the template includes another one.
+    pub fn render(&self, values: &Value) -> Result<String, String> {
+        let mut output = String::new();
+        let mut environment = Environment::new(None, values);
+
+        render(&self.nodes, &mut environment, &mut output)?;
+
+        Ok(output)
+    }
+}
+
+impl Templates {
+    /// Serves the templates from the directory `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Templates {
+        Templates {
+            root: root.into(),
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Renders the template `name` (a file in the directory) with `values`, which must be a map.
+    ///
+    /// # Errors
+    /// Fails if the template, or one it includes, cannot be read or parsed, or if a value to insert is missing or is not a text (or a boolean).
+    pub fn render(&self, name: &str, values: &Value) -> Result<String, String> {
+        let mut output = String::new();
+        let mut environment = Environment::new(Some(self), values);
+
+        render(&self.get(name)?.nodes, &mut environment, &mut output)?;
+
+        Ok(output)
+    }
+
+    fn get(&self, name: &str) -> Result<Arc<Template>, String> {
+        if let Some(template) = self.cache.read().expect("The template cache is in a poisoned state.").get(name) {
+            return Ok(Arc::clone(template));
+        }
+
+        // the names come from the server and the templates themselves, still, nothing outside of the directory is a template
+        if !Path::new(name).components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(format!("Invalid template name `{}`.", name));
+        }
+
+        let source = fs::read_to_string(self.root.join(name)).map_err(|e| format!("Cannot read the template {}: {}", name, e))?;
+        let template = Arc::new(Template::parse(&source).map_err(|e| format!("{}: {}", name, e))?);
+
+        // two threads may have parsed the same template at once, which is harmless
+        self.cache
+            .write()
+            .expect("The template cache is in a poisoned state.")
+            .insert(name.to_string(), Arc::clone(&template));
+
+        Ok(template)
+    }
+}
+
+impl<'t, 'v> Environment<'t, 'v> {
+    fn new(templates: Option<&'t Templates>, values: &'v Value) -> Environment<'t, 'v> {
+        Environment {
+            templates,
+            values,
+            scopes: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    fn lookup(&self, path: &[String]) -> Option<&'v Value> {
+        let (first, rest) = path.split_first()?;
+
+        // the loop variables shadow the values
+        let mut value = match self.scopes.iter().rev().find(|(name, _)| name == first) {
+            Some((_, value)) => *value,
+            None => self.values.get(first)?,
+        };
+
+        for key in rest {
+            value = value.get(key)?;
+        }
+
+        Some(value)
+    }
+}
+
+fn render<'v>(nodes: &[Node], environment: &mut Environment<'_, 'v>, output: &mut String) -> Result<(), String> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Variable(path) => match environment.lookup(path) {
+                Some(Value::Text(text)) => escape_into(text, output),
+                Some(Value::Bool(value)) => output.push_str(if *value { "true" } else { "false" }),
+                Some(_) => return Err(format!("`{}` is a list or a map, it cannot be inserted as it is.", path.join("."))),
+                None => return Err(format!("Unknown value `{}`.", path.join("."))),
+            },
+            Node::If {
+                negated,
+                condition,
+                then,
+                otherwise,
+            } => {
+                let value = environment.lookup(condition).is_some_and(Value::is_true);
+
+                render(if value != *negated { then } else { otherwise }, environment, output)?;
+            }
+            Node::For { variable, list, body } => {
+                let items = match environment.lookup(list) {
+                    Some(Value::List(items)) => items,
+                    Some(_) => return Err(format!("`{}` is not a list.", list.join("."))),
+                    None => return Err(format!("Unknown value `{}`.", list.join("."))),
+                };
+
+                for item in items {
+                    environment.scopes.push((variable.clone(), item));
+                    let result = render(body, environment, output);
+                    environment.scopes.pop();
+
+                    result?;
+                }
+            }
+            Node::Include(name) => {
+                let Some(templates) = environment.templates else {
+                    return Err(format!("Cannot include {}: the templates that include others must be rendered through `Templates`.", name));
+                };
+
+                if environment.depth == MAX_INCLUDE_DEPTH {
+                    return Err(format!("Cannot include {}: the includes are nested too deeply, do the templates include each other?", name));
+                }
+
+                let template = templates.get(name)?;
+
+                environment.depth += 1;
+                let result = render(&template.nodes, environment, output);
+                environment.depth -= 1;
+
+                result?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn escape_into(text: &str, output: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            c => output.push(c),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token<'_>>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+
+    while let Some(start) = ["{{", "{%", "{#"].iter().filter_map(|open| rest.find(open)).min() {
+        let open = &rest[start..start + 2];
+        let close = match open {
+            "{{" => "}}",
+            "{%" => "%}",
+            _ => "#}",
+        };
+
+        let position = source.len() - rest.len() + start;
+        let line = source[..position].matches('\n').count() + 1;
+        let inside = &rest[start + 2..];
+
+        let Some(length) = inside.find(close) else {
+            return Err(format!("line {}: `{}` is never closed.", line, open));
+        };
+
+        let mut text = &rest[..start];
+        let mut after = &inside[length + 2..];
+
+        // a tag (or a comment) alone on its line takes the whole line with it, otherwise every block would leave blank lines behind
+        let line_start = source[..position].rfind('\n').map_or(0, |newline| newline + 1);
+        let (line_end, next_line) = after.split_once('\n').unwrap_or((after, ""));
+
+        if open != "{{" && is_blank(&source[line_start..position]) && is_blank(line_end) {
+            text = text.trim_end_matches([' ', '\t']);
+            after = next_line;
+        }
+
+        if !text.is_empty() {
+            tokens.push(Token::Text(text));
+        }
+
+        match open {
+            "{{" => tokens.push(Token::Variable(inside[..length].trim(), line)),
+            "{%" => tokens.push(Token::Tag(inside[..length].trim(), line)),
+            _ => (), // a comment
+        }
+
+        rest = after;
+    }
+
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest));
+    }
+
+    Ok(tokens)
+}
+
+fn is_blank(text: &str) -> bool {
+    text.chars().all(|c| c == ' ' || c == '\t' || c == '\r')
+}
+
+// parses the nodes up to one of the `ends` tags, returning the one it has stopped at; `None` means the tokens have run out
+fn parse_block<'s>(tokens: &mut impl Iterator<Item = Token<'s>>, ends: &[&str]) -> Result<(Vec<Node>, Option<&'s str>), String> {
+    let mut nodes = Vec::new();
+
+    while let Some(token) = tokens.next() {
+        let (tag, line) = match token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text.to_string()));
+                continue;
+            }
+            Token::Variable(path, line) => {
+                nodes.push(Node::Variable(parse_path(path, line)?));
+                continue;
+            }
+            Token::Tag(tag, line) => (tag, line),
+        };
+
+        let words: Vec<&str> = tag.split_whitespace().collect();
+
+        if let [end] = words[..] {
+            if ends.contains(&end) {
+                return Ok((nodes, Some(end)));
+            }
+        }
+
+        let node = match words[..] {
+            ["if", condition] => parse_if(tokens, false, condition, line)?,
+            ["if", "not", condition] => parse_if(tokens, true, condition, line)?,
+            ["for", variable, "in", list] => {
+                let (body, end) = parse_block(tokens, &["endfor"])?;
+
+                if end.is_none() {
+                    return Err(format!("line {}: `{{% for %}}` is never closed.", line));
+                }
+
+                // the loop variable is a plain name, not a path
+                if variable.contains('.') {
+                    return Err(format!("line {}: invalid loop variable `{}`.", line, variable));
+                }
+
+                Node::For {
+                    variable: parse_path(variable, line)?.concat(),
+                    list: parse_path(list, line)?,
+                    body,
+                }
+            }
+            ["include", name] => match name.strip_prefix('"').and_then(|name| name.strip_suffix('"')) {
+                Some(name) => Node::Include(name.to_string()),
+                None => return Err(format!("line {}: the name of an included template must be quoted.", line)),
+            },
+            _ => return Err(format!("line {}: unknown or misplaced tag `{{% {} %}}`.", line, tag)),
+        };
+
+        nodes.push(node);
+    }
+
+    Ok((nodes, None))
+}
+
+fn parse_if<'s>(tokens: &mut impl Iterator<Item = Token<'s>>, negated: bool, condition: &str, line: usize) -> Result<Node, String> {
+    let unclosed = || format!("line {}: `{{% if %}}` is never closed.", line);
+
+    let (then, end) = parse_block(tokens, &["else", "endif"])?;
+
+    let otherwise = match end {
+        Some("else") => match parse_block(tokens, &["endif"])? {
+            (otherwise, Some(_)) => otherwise,
+            (_, None) => return Err(unclosed()),
+        },
+        Some(_) => Vec::new(),
+        None => return Err(unclosed()),
+    };
+
+    Ok(Node::If {
+        negated,
+        condition: parse_path(condition, line)?,
+        then,
+        otherwise,
+    })
+}
+
+fn parse_path(path: &str, line: usize) -> Result<Vec<String>, String> {
+    let segments: Vec<String> = path.split('.').map(String::from).collect();
+
+    let valid = |segment: &String| !segment.is_empty() && segment.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if !segments.iter().all(valid) {
+        return Err(format!("line {}: invalid name `{}`.", line, path));
+    }
+
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, values: &Value) -> Result<String, String> {
+        Template::parse(source)?.render(values)
+    }
+
+    #[test]
+    fn escapes_values() {
+        let values: Value = [("path", "/<script>alert('hi')</script>&\"")].into_iter().collect();
+
+        assert_eq!(
+            render("You asked for {{ path }}.", &values).unwrap(),
+            "You asked for /&lt;script&gt;alert(&#39;hi&#39;)&lt;/script&gt;&amp;&quot;."
+        );
+    }
+
+    #[test]
+    fn conditionals_and_loops() {
+        let user: Value = [("name", Value::from("Ferris")), ("admin", Value::from(true))].into_iter().collect();
+        let values: Value = [("user", user), ("items", Value::from(vec!["a", "b"])), ("empty", Value::from(Vec::<Value>::new()))]
+            .into_iter()
+            .collect();
+
+        let source = "{% if user.admin %}{{ user.name }} (admin){% else %}nobody{% endif %}\
+                      {% for item in items %}[{{ item }}{% for x in items %}{{ x }}{% endfor %}]{% endfor %}\
+                      {% if not empty %}none{% endif %}{% if missing %}?{% endif %}{# ignored #}";
+
+        assert_eq!(render(source, &values).unwrap(), "Ferris (admin)[aab][bab]none");
+    }
+
+    #[test]
+    fn standalone_tags_take_their_lines() {
+        let values: Value = [("items", vec!["a", "b"])].into_iter().collect();
+        let source = "<ul>\n  {% for item in items %}\n  <li>{{ item }}</li>\n  {% endfor %}\n</ul>\n{# the end #}\n";
+
+        assert_eq!(render(source, &values).unwrap(), "<ul>\n  <li>a</li>\n  <li>b</li>\n</ul>\n");
+    }
+
+    #[test]
+    fn reports_errors() {
+        let values = Value::Map(BTreeMap::new());
+
+        assert_eq!(render("a\n{{ b", &values).unwrap_err(), "line 2: `{{` is never closed.");
+        assert_eq!(render("{% if a %}\n{% for b in c %}", &values).unwrap_err(), "line 2: `{% for %}` is never closed.");
+        assert_eq!(render("{% endif %}", &values).unwrap_err(), "line 1: unknown or misplaced tag `{% endif %}`.");
+        assert_eq!(render("{{ a b }}", &values).unwrap_err(), "line 1: invalid name `a b`.");
+        assert_eq!(render("{{ missing }}", &values).unwrap_err(), "Unknown value `missing`.");
+        assert_eq!(render("{% include \"a.html\" %}", &values).unwrap_err(), "Cannot include a.html: the templates that include others must be rendered through `Templates`.");
+    }
+
+    #[test]
+    fn includes_from_the_directory() {
+        let root = std::env::temp_dir().join(format!("hello-templates-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("page.html"), "{% include \"title.html\" %}!").unwrap();
+        fs::write(root.join("title.html"), "<h1>{{ title }}</h1>").unwrap();
+        fs::write(root.join("loop.html"), "{% include \"loop.html\" %}").unwrap();
+
+        let templates = Templates::new(&root);
+        let values: Value = [("title", "Hello")].into_iter().collect();
+
+        assert_eq!(templates.render("page.html", &values).unwrap(), "<h1>Hello</h1>!");
+        assert!(templates.render("loop.html", &values).unwrap_err().contains("nested too deeply"));
+        assert_eq!(templates.render("../page.html", &values).unwrap_err(), "Invalid template name `../page.html`.");
+
+        // cached, so the changes go unnoticed
+        fs::write(root.join("title.html"), "changed").unwrap();
+        assert_eq!(templates.render("page.html", &values).unwrap(), "<h1>Hello</h1>!");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
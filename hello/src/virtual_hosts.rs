@@ -0,0 +1,169 @@
+//! Name-based virtual hosting: a single listener serving several sites, told apart by the `Host` header of the requests.
+//!
+//! Every site has its own document root (and so its own pages) and its own routers: WebSocket endpoints, proxies and handlers. A request for a host no site is configured for goes to the default site, made of the top-level settings of `Config`, or, in the strict mode, is answered with `421 Misdirected Request`.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::handlers::Handlers;
+use crate::proxy::Proxies;
+use crate::template::Templates;
+use crate::websocket::Endpoints;
+
+/// A site: the pages of a directory and the routers in front of them.
+#[derive(Debug, Clone)]
+pub struct Site {
+    pub(crate) root: PathBuf,
+    pub(crate) templates: Arc<Templates>, // shared by the copies of the site, so that a template is parsed once
+    pub(crate) websockets: Endpoints,
+    pub(crate) proxies: Proxies,
+    pub(crate) handlers: Handlers,
+}
+
+/// The sites by host name.
+///
+/// # Example
+/// ```
+/// use hello::virtual_hosts::{Site, VirtualHosts};
+///
+/// let hosts = VirtualHosts::new().site("example.com", Site::new("resources")).site("*.example.org", Site::new("resources"));
+///
+/// assert!(hosts.find("Example.COM:8080").is_some());
+/// assert!(hosts.find("www.example.org").is_some());
+/// assert!(hosts.find("example.org").is_none()); // a wildcard covers the subdomains only
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct VirtualHosts {
+    sites: Vec<(String, Site)>,
+    strict: bool,
+}
+
+impl Site {
+    /// A site serving the pages of `root`, without any endpoints, proxies or handlers.
+    pub fn new(root: impl Into<PathBuf>) -> Site {
+        let root = root.into();
+
+        Site {
+            templates: Arc::new(Templates::new(&root)),
+            root,
+            websockets: Endpoints::new(),
+            proxies: Proxies::new(),
+            handlers: Handlers::new(),
+        }
+    }
+
+    pub fn websockets(mut self, websockets: Endpoints) -> Site {
+        self.websockets = websockets;
+        self
+    }
+
+    pub fn proxies(mut self, proxies: Proxies) -> Site {
+        self.proxies = proxies;
+        self
+    }
+
+    pub fn handlers(mut self, handlers: Handlers) -> Site {
+        self.handlers = handlers;
+        self
+    }
+
+    /// The directory the pages of the site are served from.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl VirtualHosts {
+    pub fn new() -> VirtualHosts {
+        VirtualHosts::default()
+    }
+
+    /// Serves `site` for `host`, replacing the site already there, if any. The name is case-insensitive, and `*.example.com` stands for all the subdomains of `example.com`; an exact name wins over a wildcard.
+    pub fn site(mut self, host: &str, site: Site) -> VirtualHosts {
+        let host = host.to_ascii_lowercase();
+
+        self.sites.retain(|(h, _)| *h != host);
+        self.sites.push((host, site));
+        self
+    }
+
+    /// In the strict mode the requests for the hosts without a site of their own (or without a `Host` header) are refused rather than served by the default site.
+    pub fn strict(mut self, strict: bool) -> VirtualHosts {
+        self.strict = strict;
+        self
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Returns the host names (or patterns) with their sites, in the order they were added.
+    pub fn sites(&self) -> impl Iterator<Item = (&str, &Site)> {
+        self.sites.iter().map(|(host, site)| (host.as_str(), site))
+    }
+
+    /// Returns the site for the value of a `Host` header, which may come with a port.
+    pub fn find(&self, host: &str) -> Option<&Site> {
+        let name = host_name(host);
+
+        let exact = self.sites.iter().find(|(pattern, _)| *pattern == name);
+
+        // the most specific wildcard wins, i.e. the longest one
+        let wildcard = || {
+            self.sites
+                .iter()
+                .filter(|(pattern, _)| pattern.strip_prefix("*.").is_some_and(|domain| name.strip_suffix(domain).is_some_and(|sub| sub.len() > 1 && sub.ends_with('.'))))
+                .max_by_key(|(pattern, _)| pattern.len())
+        };
+
+        exact.or_else(wildcard).map(|(_, site)| site)
+    }
+}
+
+// the host name of a `Host` header: lowercase, without the port and without the trailing dot of a fully qualified name
+fn host_name(host: &str) -> String {
+    let host = host.trim();
+
+    let name = match host.strip_prefix('[') {
+        Some(rest) => rest.split_once(']').map_or(host, |(address, _)| &host[..address.len() + 2]), // an IPv6 address keeps its brackets
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_names() {
+        assert_eq!(host_name("Example.com"), "example.com");
+        assert_eq!(host_name("example.com:8080"), "example.com");
+        assert_eq!(host_name("example.com."), "example.com");
+        assert_eq!(host_name("[::1]:7878"), "[::1]");
+        assert_eq!(host_name("[::1]"), "[::1]");
+        assert_eq!(host_name("127.0.0.1:7878"), "127.0.0.1");
+    }
+
+    #[test]
+    fn finds_sites() {
+        let hosts = VirtualHosts::new()
+            .site("*.example.com", Site::new("wildcard"))
+            .site("*.api.example.com", Site::new("api"))
+            .site("www.example.com", Site::new("www"))
+            .site("WWW.example.com", Site::new("replaced"));
+
+        let root = |host: &str| hosts.find(host).map(|site| site.root().to_str().unwrap().to_string());
+
+        assert_eq!(root("www.example.com"), Some(String::from("replaced")));
+        assert_eq!(root("blog.example.com"), Some(String::from("wildcard")));
+        assert_eq!(root("v1.api.example.com"), Some(String::from("api")));
+        assert_eq!(root("api.example.com"), Some(String::from("wildcard")));
+        assert_eq!(root("example.com"), None);
+        assert_eq!(root("notexample.com"), None);
+        assert_eq!(root(""), None);
+
+        assert_eq!(hosts.sites().count(), 3);
+    }
+}
@@ -0,0 +1,613 @@
+//! WebSockets (RFC 6455): the opening handshake, the framing of the messages and the closing handshake.
+//!
+//! The endpoints are handlers registered by path in `Config::websockets`. Once a request to one of the paths has been upgraded, its handler is given the connection as a `WebSocket` and keeps it for as long as it runs, so every open session occupies a thread: a worker in the threaded mode, one of the workers the loops leave free in the event loop mode. That's why a session that goes quiet for longer than `Config::websocket_idle_timeout` has its `receive` fail with a timeout, upon which the endpoint is expected to return.
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::io::{self, prelude::*};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::request::Request;
+use crate::response::Response;
+use crate::server::error_response;
+use crate::{base64, sha1, warn};
+
+// the handshake proves that the server understood the request by hashing the client's key together with this (fixed) GUID
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// the opcodes; the ones from `CLOSE` on (with the highest bit set) are control frames
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// The connection a `WebSocket` runs over: a TCP stream, or a TLS stream wrapping one.
+pub trait Transport: Read + Write {}
+
+impl<T: Read + Write + ?Sized> Transport for T {}
+
+/// An endpoint: receives the upgraded connection and the request that asked for the upgrade. The connection is closed once it returns.
+pub type Handler = Arc<dyn Fn(&mut WebSocket<&mut dyn Transport>, &Request) + Send + Sync>;
+
+/// The WebSocket endpoints of the server by path.
+#[derive(Clone, Default)]
+pub struct Endpoints {
+    handlers: Vec<(String, Handler)>,
+}
+
+impl Endpoints {
+    pub fn new() -> Endpoints {
+        Endpoints::default()
+    }
+
+    /// Adds an endpoint at `path`, replacing the one already there, if any.
+    pub fn route<F>(mut self, path: &str, handler: F) -> Endpoints
+    where
+        F: Fn(&mut WebSocket<&mut dyn Transport>, &Request) + Send + Sync + 'static,
+    {
+        self.handlers.retain(|(p, _)| p != path);
+        self.handlers.push((path.to_string(), Arc::new(handler)));
+        self
+    }
+
+    /// Returns the paths of the endpoints in the order they were added.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.handlers.iter().map(|(path, _)| path.as_str())
+    }
+
+    /// Returns the handler of the endpoint at `path`, if there is one.
+    pub fn get(&self, path: &str) -> Option<&Handler> {
+        self.handlers.iter().find(|(p, _)| p == path).map(|(_, handler)| handler)
+    }
+}
+
+impl fmt::Debug for Endpoints {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // the handlers are closures, so the paths are all there is to show
+        f.debug_list().entries(self.handlers.iter().map(|(path, _)| path)).finish()
+    }
+}
+
+/// Answers the opening handshake: `101 Switching Protocols` if `request` is a valid upgrade to a WebSocket, the reason why it isn't otherwise.
+///
+/// # Example
+/// ```
+/// use hello::request::Request;
+/// use hello::websocket;
+///
+/// // the example from the RFC
+/// let request = Request::parse(
+///     "GET /chat HTTP/1.1\r\n\
+///      Host: server.example.com\r\n\
+///      Upgrade: websocket\r\n\
+///      Connection: Upgrade\r\n\
+///      Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+///      Sec-WebSocket-Version: 13\r\n\r\n",
+/// );
+/// let response = websocket::handshake(&request);
+///
+/// assert_eq!(response.status(), 101);
+/// assert_eq!(response.get_header("Sec-WebSocket-Accept"), Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+/// ```
+pub fn handshake(request: &Request) -> Response {
+    // a plain HTTP request to an endpoint
+    if !request.header("Upgrade").is_some_and(|value| has_token(value, "websocket")) {
+        return error_response(426).header("Upgrade", "websocket");
+    }
+
+    if request.method() != "GET" || !request.header("Connection").is_some_and(|value| has_token(value, "upgrade")) {
+        return error_response(400);
+    }
+
+    if request.header("Sec-WebSocket-Version") != Some("13") {
+        return error_response(426).header("Sec-WebSocket-Version", "13"); // tells the client which version we do speak
+    }
+
+    // the key is a random 16 byte nonce
+    let Some(key) = request.header("Sec-WebSocket-Key").filter(|key| base64::decode(key).is_some_and(|nonce| nonce.len() == 16)) else {
+        return error_response(400);
+    };
+
+    Response::new(101)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", &accept_key(key))
+}
+
+/// Computes the `Sec-WebSocket-Accept` header answering the client's `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    base64::encode(&sha1::digest(format!("{}{}", key, GUID).as_bytes()))
+}
+
+// whether the comma separated list of `value` has `token` in it, as in `Connection: keep-alive, Upgrade`
+fn has_token(value: &str, token: &str) -> bool {
+    value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+/// A message, as sent and received by a `WebSocket`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// `WebSocket::receive` answers these with a `Pong` by itself.
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The status code and the reason, if any.
+    Close(Option<(u16, String)>),
+}
+
+/// Which end of the connection a `WebSocket` is. It matters for the masking: the clients mask every frame they send, the servers none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Server,
+    Client,
+}
+
+/// A connection after the opening handshake.
+#[derive(Debug)]
+pub struct WebSocket<S> {
+    stream: S,
+    role: Role,
+    max_message_size: usize,
+    fragments: Option<(u8, Vec<u8>)>, // the opcode and the payload so far of a fragmented message being received
+    sent_close: bool,
+    received_close: bool,
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+impl<S: Read + Write> WebSocket<S> {
+    /// Wraps a connection whose opening handshake has been completed already.
+    pub fn new(stream: S, role: Role) -> WebSocket<S> {
+        WebSocket {
+            stream,
+            role,
+            max_message_size: 1024 * 1024,
+            fragments: None,
+            sent_close: false,
+            received_close: false,
+        }
+    }
+
+    /// Sets the size of the largest message (once its fragments are put together) to accept; a larger one fails the connection with `1009 Message Too Big`. One mebibyte by default.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Returns the underlying connection, e.g. to close it after the closing handshake.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Receives the next message, putting fragmented messages back together.
+    ///
+    /// A ping is answered, and a close confirmed, before it is returned.
+    ///
+    /// # Errors
+    /// Fails on I/O errors, with `NotConnected` once the peer has closed the connection and with `InvalidData` if the peer violates the protocol; the connection is closed with the matching status code in the latter case.
+    pub fn receive(&mut self) -> io::Result<Message> {
+        if self.received_close {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "The WebSocket has been closed by the peer."));
+        }
+
+        loop {
+            let frame = self.read_frame()?;
+
+            match frame.opcode {
+                TEXT | BINARY if self.fragments.is_some() => return Err(self.fail(1002, "A message has started before the previous one has ended.")),
+                TEXT | BINARY if frame.fin => return self.message(frame.opcode, frame.payload),
+                TEXT | BINARY => self.fragments = Some((frame.opcode, frame.payload)),
+                CONTINUATION => {
+                    let Some((opcode, mut payload)) = self.fragments.take() else {
+                        return Err(self.fail(1002, "A continuation frame without a message to continue."));
+                    };
+
+                    if payload.len() + frame.payload.len() > self.max_message_size {
+                        return Err(self.fail(1009, "The message is too big."));
+                    }
+
+                    payload.extend_from_slice(&frame.payload);
+
+                    if frame.fin {
+                        return self.message(opcode, payload);
+                    }
+
+                    self.fragments = Some((opcode, payload));
+                }
+                PING => {
+                    self.write_frame(PONG, &frame.payload)?;
+                    return Ok(Message::Ping(frame.payload));
+                }
+                PONG => return Ok(Message::Pong(frame.payload)),
+                CLOSE => return self.closed(frame.payload),
+                _ => unreachable!("`read_frame` refuses the unknown opcodes"),
+            }
+        }
+    }
+
+    /// Sends `message` as a single frame.
+    ///
+    /// # Errors
+    /// Fails on I/O errors, with `NotConnected` once a close has been sent, and with `InvalidInput` if a control message (a ping, a pong or a close) doesn't fit into 125 bytes.
+    pub fn send(&mut self, message: &Message) -> io::Result<()> {
+        if self.sent_close {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "The WebSocket has been closed."));
+        }
+
+        let (opcode, payload) = match message {
+            Message::Text(text) => (TEXT, text.as_bytes().to_vec()),
+            Message::Binary(data) => (BINARY, data.clone()),
+            Message::Ping(data) => (PING, data.clone()),
+            Message::Pong(data) => (PONG, data.clone()),
+            Message::Close(None) => (CLOSE, Vec::new()),
+            Message::Close(Some((code, reason))) => (CLOSE, close_payload(*code, reason)),
+        };
+
+        if opcode >= CLOSE && payload.len() > 125 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "A control message cannot be longer than 125 bytes."));
+        }
+
+        self.sent_close = opcode == CLOSE;
+        self.write_frame(opcode, &payload)
+    }
+
+    /// Starts the closing handshake, unless the peer has already done so, and waits for the peer to confirm it, throwing away whatever it still sends until then.
+    ///
+    /// # Errors
+    /// Fails on I/O errors, and if the peer violates the protocol in the meantime.
+    pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        if !self.sent_close {
+            self.send(&Message::Close(Some((code, reason.to_string()))))?;
+        }
+
+        while !self.received_close {
+            self.receive()?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether both ends have sent a close, so that the underlying connection can be closed.
+    pub fn is_closed(&self) -> bool {
+        self.sent_close && self.received_close
+    }
+
+    fn message(&mut self, opcode: u8, payload: Vec<u8>) -> io::Result<Message> {
+        match opcode {
+            TEXT => String::from_utf8(payload).map(Message::Text).map_err(|_| self.fail(1007, "A text message is not valid UTF-8.")),
+            _ => Ok(Message::Binary(payload)),
+        }
+    }
+
+    fn closed(&mut self, payload: Vec<u8>) -> io::Result<Message> {
+        self.received_close = true;
+
+        let status = match payload.len() {
+            0 => None,
+            1 => return Err(self.fail(1002, "A close frame with a truncated status code.")),
+            _ => {
+                let code = u16::from_be_bytes([payload[0], payload[1]]);
+
+                // the codes from 1004 to 1006 and 1015 are reserved for reporting the state of the connection locally, they never go over the wire
+                if !matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999) {
+                    return Err(self.fail(1002, "A close frame with an invalid status code."));
+                }
+
+                let Ok(reason) = String::from_utf8(payload[2..].to_vec()) else {
+                    return Err(self.fail(1007, "The reason of a close is not valid UTF-8."));
+                };
+
+                Some((code, reason))
+            }
+        };
+
+        // confirm the close by echoing the status code, unless this end has started the closing handshake
+        if !self.sent_close {
+            self.sent_close = true;
+            self.write_frame(CLOSE, &payload[..payload.len().min(2)])?;
+        }
+
+        Ok(Message::Close(status))
+    }
+
+    // fails the connection: tells the peer why (if it's still listening) and gives up on reading from it
+    fn fail(&mut self, code: u16, reason: &str) -> io::Error {
+        if !self.sent_close {
+            self.sent_close = true;
+
+            if let Err(e) = self.write_frame(CLOSE, &close_payload(code, reason)) {
+                warn!("Cannot send a close frame: {}", e);
+            }
+        }
+
+        self.received_close = true;
+
+        io::Error::new(io::ErrorKind::InvalidData, reason)
+    }
+
+    fn read_frame(&mut self) -> io::Result<Frame> {
+        let mut head = [0; 2];
+        self.stream.read_exact(&mut head)?;
+
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        let masked = head[1] & 0x80 != 0;
+
+        // the reserved bits are for the extensions, and we don't negotiate any
+        if head[0] & 0x70 != 0 {
+            return Err(self.fail(1002, "A frame with the reserved bits set."));
+        }
+
+        if !matches!(opcode, CONTINUATION | TEXT | BINARY | CLOSE | PING | PONG) {
+            return Err(self.fail(1002, "A frame with an unknown opcode."));
+        }
+
+        // the length takes 7 bits, or, if that's 126 or 127, the next 2 or 8 bytes
+        let length = match head[1] & 0x7F {
+            126 => {
+                let mut length = [0; 2];
+                self.stream.read_exact(&mut length)?;
+                u16::from_be_bytes(length) as u64
+            }
+            127 => {
+                let mut length = [0; 8];
+                self.stream.read_exact(&mut length)?;
+                u64::from_be_bytes(length)
+            }
+            length => length as u64,
+        };
+
+        if opcode >= CLOSE && (!fin || length > 125) {
+            return Err(self.fail(1002, "A fragmented or too long control frame."));
+        }
+
+        if masked != (self.role == Role::Server) {
+            return Err(self.fail(1002, "A frame masked the wrong way."));
+        }
+
+        // checked before allocating anything, a peer could otherwise make us allocate up to 2^64 bytes
+        if length > self.max_message_size as u64 {
+            return Err(self.fail(1009, "The message is too big."));
+        }
+
+        let mut mask = [0; 4];
+
+        if masked {
+            self.stream.read_exact(&mut mask)?;
+        }
+
+        let mut payload = vec![0; length as usize];
+        self.stream.read_exact(&mut payload)?;
+
+        if masked {
+            apply_mask(&mut payload, mask);
+        }
+
+        Ok(Frame { fin, opcode, payload })
+    }
+
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(0x80 | opcode); // the messages we send are never fragmented, so every frame is the final one
+
+        let mask_bit = if self.role == Role::Client { 0x80 } else { 0 };
+
+        match payload.len() {
+            length @ 0..=125 => frame.push(mask_bit | length as u8),
+            length @ 126..=0xFFFF => {
+                frame.push(mask_bit | 126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(mask_bit | 127);
+                frame.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+
+        let start = frame.len();
+
+        match self.role {
+            Role::Server => frame.extend_from_slice(payload),
+            Role::Client => {
+                let mask = masking_key();
+                frame.extend_from_slice(&mask);
+                frame.extend_from_slice(payload);
+                apply_mask(&mut frame[start + 4..], mask);
+            }
+        }
+
+        self.stream.write_all(&frame)?;
+        self.stream.flush()
+    }
+}
+
+fn close_payload(code: u16, reason: &str) -> Vec<u8> {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    payload
+}
+
+// masking and unmasking are the same operation
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (byte, key) in payload.iter_mut().zip(mask.iter().cycle()) {
+        *byte ^= key;
+    }
+}
+
+// the masking keys must be unpredictable (that's what keeps a malicious page from steering what the proxies on the way see); `RandomState` is seeded randomly, which is good enough for that
+fn masking_key() -> [u8; 4] {
+    (RandomState::new().hash_one(Instant::now()) as u32).to_be_bytes()
+}
+
+/// An endpoint that sends every text and binary message back as it is. A client that stays silent for longer than `Config::websocket_idle_timeout` is told goodbye with `1001 Going Away`.
+pub fn echo(socket: &mut WebSocket<&mut dyn Transport>, _request: &Request) {
+    loop {
+        let result = match socket.receive() {
+            Ok(Message::Close(_)) => return,
+            Ok(message @ (Message::Text(_) | Message::Binary(_))) => socket.send(&message),
+            Ok(_) => Ok(()), // the pings have been answered by `receive` already
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                if let Err(e) = socket.close(1001, "Idle for too long") {
+                    warn!("Cannot close the WebSocket session: {}", e);
+                }
+
+                return;
+            }
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            warn!("The WebSocket session has failed: {}", e);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    // what the peer has sent on one side, what we send on the other
+    struct Pipe {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buffer)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.output.write(buffer)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn server(input: &[u8]) -> WebSocket<Pipe> {
+        let pipe = Pipe {
+            input: Cursor::new(input.to_vec()),
+            output: Vec::new(),
+        };
+
+        WebSocket::new(pipe, Role::Server)
+    }
+
+    // what the client would send for `messages`
+    fn client_frames(messages: &[Message]) -> Vec<u8> {
+        let mut client = WebSocket::new(Pipe { input: Cursor::new(Vec::new()), output: Vec::new() }, Role::Client);
+
+        for message in messages {
+            client.send(message).unwrap();
+        }
+
+        client.stream.output
+    }
+
+    #[test]
+    fn examples_from_the_rfc() {
+        // a single masked text frame
+        let mut socket = server(&[0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]);
+        assert_eq!(socket.receive().unwrap(), Message::Text(String::from("Hello")));
+
+        // the unmasked version of it is what the server sends
+        socket.send(&Message::Text(String::from("Hello"))).unwrap();
+        assert_eq!(socket.stream.output, [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+    }
+
+    #[test]
+    fn round_trips_through_masking() {
+        let text = Message::Text("x".repeat(200)); // takes the 16 bit length
+        let binary = Message::Binary(vec![7; 70_000]); // takes the 64 bit length
+
+        let mut socket = server(&client_frames(&[text.clone(), binary.clone()]));
+
+        assert_eq!(socket.receive().unwrap(), text);
+        assert_eq!(socket.receive().unwrap(), binary);
+    }
+
+    #[test]
+    fn reassembles_fragments_around_control_frames() {
+        // "Hel", a ping, then "lo"; all masked with a zero key to keep it readable
+        let input = [
+            &[0x01, 0x83, 0, 0, 0, 0][..],
+            b"Hel",
+            &[0x89, 0x82, 0, 0, 0, 0],
+            b"hi",
+            &[0x80, 0x82, 0, 0, 0, 0],
+            b"lo",
+        ]
+        .concat();
+        let mut socket = server(&input);
+
+        assert_eq!(socket.receive().unwrap(), Message::Ping(b"hi".to_vec()));
+        assert_eq!(socket.stream.output, [0x8A, 0x02, b'h', b'i']); // the pong
+        assert_eq!(socket.receive().unwrap(), Message::Text(String::from("Hello")));
+    }
+
+    #[test]
+    fn confirms_the_close() {
+        let mut socket = server(&client_frames(&[Message::Close(Some((1000, String::from("bye"))))]));
+
+        assert_eq!(socket.receive().unwrap(), Message::Close(Some((1000, String::from("bye")))));
+        assert_eq!(socket.stream.output, [0x88, 0x02, 0x03, 0xE8]);
+        assert!(socket.is_closed());
+        assert_eq!(socket.receive().unwrap_err().kind(), io::ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn fails_on_protocol_violations() {
+        let cases: [(&[u8], u16); 4] = [
+            (&[0x81, 0x05, b'H', b'e', b'l', b'l', b'o'], 1002), // not masked
+            (&[0x83, 0x80, 0, 0, 0, 0], 1002),                   // unknown opcode
+            (&[0x81, 0x82, 0, 0, 0, 0, 0xC3, 0x28], 1007),       // invalid UTF-8
+            (&[0x82, 0xFF, 0, 0, 0, 1, 0, 0, 0, 0], 1009),       // 4 GiB
+        ];
+
+        for (input, code) in cases {
+            let mut socket = server(input);
+
+            assert_eq!(socket.receive().unwrap_err().kind(), io::ErrorKind::InvalidData);
+            assert_eq!(socket.stream.output[0], 0x88);
+            assert_eq!(socket.stream.output[2..4], code.to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn refuses_invalid_handshakes() {
+        let request = |headers: &str| Request::parse(&format!("GET /echo HTTP/1.1\r\nHost: localhost\r\n{}\r\n", headers));
+
+        let response = handshake(&request(""));
+        assert_eq!(response.status(), 426);
+        assert_eq!(response.get_header("Upgrade"), Some("websocket"));
+
+        let response = handshake(&request("Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n"));
+        assert_eq!(response.status(), 426);
+        assert_eq!(response.get_header("Sec-WebSocket-Version"), Some("13"));
+
+        let response = handshake(&request("Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: c2hvcnQ=\r\nSec-WebSocket-Version: 13\r\n"));
+        assert_eq!(response.status(), 400);
+
+        let response = handshake(&request("Upgrade: WebSocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n"));
+        assert_eq!(response.status(), 101);
+    }
+}
@@ -0,0 +1,31 @@
+use std::process::Command;
+
+use hello::server::Config;
+
+mod common;
+
+#[test]
+fn reports_throughput_and_latency() {
+    let address = common::start_server(Config::default());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_hello-bench")).args(["-c", "2", "-d", "500ms", &address.to_string()]).output().unwrap();
+    let report = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success(), "{}", report);
+    assert!(report.contains("2 connections, GET requests"));
+    assert!(report.contains("Statuses:     200: "), "{}", report);
+    assert!(report.contains("Errors:       0 "), "{}", report);
+    assert!(report.contains("p99.9"));
+}
+
+#[test]
+fn fails_without_responses() {
+    let output = Command::new(env!("CARGO_BIN_EXE_hello-bench")).args(["-d", "100ms", "127.0.0.1:1"]).output().unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stdout).unwrap().contains("Statuses:     none"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_hello-bench")).args(["--connections", "many", "127.0.0.1:1"]).output().unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+}
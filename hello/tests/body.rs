@@ -0,0 +1,170 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use hello::handlers::{self, Handlers};
+use hello::json::Json;
+use hello::proxy::{Proxies, Proxy};
+use hello::response::Response;
+use hello::server::{Config, Mode};
+
+mod common;
+
+// there are no handlers by default
+fn echo() -> Handlers {
+    Handlers::new().route("POST", "/body", handlers::echo)
+}
+
+fn post(content_type: &str, body: &str) -> String {
+    format!("POST /body HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}", content_type, body.len(), body)
+}
+
+// what `handlers::echo` has made of the body
+fn echoed(response: (String, Vec<u8>)) -> Json {
+    let (head, body) = response;
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+
+    Json::parse(&String::from_utf8(body).unwrap()).unwrap()
+}
+
+fn upload_dir(test: &str) -> PathBuf {
+    env::temp_dir().join(format!("hello-uploads-{}-{}", test, std::process::id()))
+}
+
+fn multipart(boundary: &str, parts: &[(&str, Option<&str>, &str)]) -> String {
+    let mut body = String::new();
+
+    for (name, filename, contents) in parts {
+        body.push_str(&format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"", boundary, name));
+
+        if let Some(filename) = filename {
+            body.push_str(&format!("; filename=\"{}\"\r\nContent-Type: text/plain", filename));
+        }
+
+        body.push_str(&format!("\r\n\r\n{}\r\n", contents));
+    }
+
+    body + &format!("--{}--\r\n", boundary)
+}
+
+#[test]
+fn parses_forms_and_json() {
+    for mode in [Mode::Threaded, Mode::EventLoop] {
+        let address = common::start_server(Config {
+            mode,
+            read_timeout: Duration::from_millis(200),
+            handlers: echo(),
+            ..Config::default()
+        });
+
+        let json = echoed(common::send(address, &post("application/x-www-form-urlencoded", "name=Ferris+the+crab&tag=rust&tag=%F0%9F%A6%80")));
+        assert_eq!(json.to_string(), r#"{"fields":{"name":["Ferris the crab"],"tag":["rust","🦀"]},"files":[]}"#);
+
+        let json = echoed(common::send(address, &post("application/json; charset=utf-8", r#"{"legs": 10, "nested": {"ok": true}}"#)));
+        assert_eq!(json.to_string(), r#"{"json":{"legs":10,"nested":{"ok":true}}}"#);
+
+        let json = echoed(common::send(address, &post("text/plain", "just text")));
+        assert_eq!(json.to_string(), r#"{"bytes":9}"#);
+
+        let (head, _) = common::send(address, &post("application/json", "{\"legs\": }"));
+        assert!(head.starts_with("HTTP/1.1 400 BAD REQUEST\r\n"), "{}", head);
+
+        // a body that isn't all there before the client stops sending
+        let (head, _) = common::send(address, "POST /body HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 100\r\n\r\n{}");
+        assert!(head.starts_with("HTTP/1.1 408 REQUEST TIMEOUT\r\n"), "{}", head);
+    }
+}
+
+#[test]
+fn streams_uploads_to_disk() {
+    let upload_dir = upload_dir("streams");
+    let kept = env::temp_dir().join(format!("hello-kept-upload-{}", std::process::id()));
+    let contents = "a line of the file, with -- and --boundary-like bits\r\n".repeat(2_000);
+
+    for mode in [Mode::Threaded, Mode::EventLoop] {
+        let destination = kept.clone();
+
+        let address = common::start_server(Config {
+            mode,
+            upload_dir: upload_dir.clone(),
+            handlers: Handlers::new().route("POST", "/upload", move |request| {
+                let form = request.form().expect("The body is a form.");
+                let file = form.file("document").expect("The document is uploaded.");
+
+                file.persist(&destination).unwrap();
+
+                Response::new(201).body(format!("{} {} {}", form.get("title").unwrap_or("?"), file.filename(), file.size()))
+            }),
+            ..Config::default()
+        });
+
+        let body = multipart("hello-boundary", &[("title", None, "Notes"), ("document", Some("notes.txt"), &contents), ("other", Some("other.txt"), "dropped")]);
+        let request = format!("POST /upload HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=hello-boundary\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+
+        let (head, body) = common::send(address, &request);
+        assert!(head.starts_with("HTTP/1.1 201 CREATED\r\n"), "{}", head);
+        assert_eq!(String::from_utf8(body).unwrap(), format!("Notes notes.txt {}", contents.len()));
+
+        assert_eq!(fs::read_to_string(&kept).unwrap(), contents);
+        fs::remove_file(&kept).unwrap();
+
+        // the file that hasn't been kept is gone together with the request
+        assert_eq!(fs::read_dir(&upload_dir).unwrap().count(), 0);
+    }
+
+    fs::remove_dir(&upload_dir).unwrap();
+}
+
+#[test]
+fn enforces_the_limits() {
+    for mode in [Mode::Threaded, Mode::EventLoop] {
+        let address = common::start_server(Config {
+            mode,
+            max_body_size: 64,
+            max_upload_size: 256,
+            upload_dir: upload_dir("limits"),
+            handlers: echo(),
+            ..Config::default()
+        });
+
+        let status = |request: &str| common::send(address, request).0.lines().next().unwrap().to_string();
+
+        assert_eq!(status(&post("application/json", &format!("[{}0]", "0, ".repeat(30)))), "HTTP/1.1 413 PAYLOAD TOO LARGE");
+        assert_eq!(status(&post("application/json", &format!("[{}0]", "0, ".repeat(20)))), "HTTP/1.1 200 OK");
+
+        // a file may be larger than the in-memory limit, but not than the upload limit
+        let file = "x".repeat(128);
+        let upload = multipart("b", &[("file", Some("x.txt"), &file)]);
+        assert_eq!(status(&post("multipart/form-data; boundary=b", &upload)), "HTTP/1.1 200 OK");
+
+        let upload = multipart("b", &[("file", Some("x.txt"), &file), ("more", Some("x.txt"), &file)]);
+        assert_eq!(status(&post("multipart/form-data; boundary=b", &upload)), "HTTP/1.1 413 PAYLOAD TOO LARGE");
+
+        // the fields of a multipart form are kept in memory
+        let upload = multipart("b", &[("field", None, &file)]);
+        assert_eq!(status(&post("multipart/form-data; boundary=b", &upload)), "HTTP/1.1 413 PAYLOAD TOO LARGE");
+
+        assert_eq!(status("POST /body HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\n{}\r\n0\r\n\r\n"), "HTTP/1.1 411 LENGTH REQUIRED");
+    }
+}
+
+#[test]
+fn proxies_forward_bodies() {
+    let upstream = common::start_server(Config {
+        handlers: echo(),
+        ..Config::default()
+    });
+
+    let address = common::start_server(Config {
+        proxies: Proxies::new().route("/", Proxy::new([upstream.to_string()])),
+        max_body_size: 16, // the upstream is the one to parse it, only the size counts here
+        ..Config::default()
+    });
+
+    let json = echoed(common::send(address, &post("application/json", "[1, 2, 3]")));
+    assert_eq!(json.to_string(), r#"{"json":[1,2,3]}"#);
+
+    let (head, _) = common::send(address, &post("application/json", "[1, 2, 3, 4, 5, 6, 7, 8, 9]"));
+    assert!(head.starts_with("HTTP/1.1 413 PAYLOAD TOO LARGE\r\n"), "{}", head);
+}
@@ -0,0 +1,55 @@
+// helpers shared by the integration tests; living in `common/mod.rs` rather than `common.rs` keeps Cargo from treating them as a test crate of their own
+
+#![allow(dead_code)] // every test crate compiles its own copy of this module and uses only some of the helpers
+
+use std::io::prelude::*;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+
+use hello::server::{Config, Server};
+
+/// Starts a server on a free loopback port in the background and returns its address.
+pub fn start_server(config: Config) -> SocketAddr {
+    let server = Server::bind(Config {
+        address: String::from("127.0.0.1:0"), // port `0` lets the OS pick a free one, so the tests can run in parallel
+        ..config
+    })
+    .unwrap();
+
+    let address = server.local_addr().unwrap();
+
+    thread::spawn(move || server.serve(None));
+
+    address
+}
+
+/// Sends `request` over `stream` and reads the response until the server closes the connection.
+pub fn exchange<S: Read + Write>(stream: &mut S, request: &str) -> String {
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    response
+}
+
+/// Sends a `GET` request for `path` over plain HTTP and returns the raw response.
+pub fn get(address: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+
+    exchange(&mut stream, &format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path))
+}
+
+/// Sends a raw request over plain HTTP and returns the status line with the headers and the (possibly binary) body separately.
+pub fn send(address: SocketAddr, request: &str) -> (String, Vec<u8>) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+
+    let end = response.windows(4).position(|window| window == b"\r\n\r\n").expect("The response has a head.") + 4;
+    let body = response.split_off(end);
+
+    (String::from_utf8(response).unwrap(), body)
+}
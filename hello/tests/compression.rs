@@ -0,0 +1,72 @@
+use std::io::prelude::*;
+use std::net::SocketAddr;
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+use hello::server::{Config, Mode};
+
+mod common;
+
+fn compressing(mode: Mode) -> Config {
+    Config {
+        mode,
+        compression_threshold: 0, // the pages in `resources` are too small for the default
+        ..Config::default()
+    }
+}
+
+// the page as it is sent to the clients that don't accept any compression
+fn hello_page(address: SocketAddr) -> String {
+    let (_, body) = common::send(address, "GET / HTTP/1.1\r\n\r\n");
+
+    String::from_utf8(body).unwrap()
+}
+
+#[test]
+fn gzip() {
+    for mode in [Mode::Threaded, Mode::EventLoop] {
+        let address = common::start_server(compressing(mode));
+
+        let (head, body) = common::send(address, "GET / HTTP/1.1\r\nAccept-Encoding: br, gzip\r\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("Content-Encoding: gzip\r\n"));
+        assert!(head.contains("Vary: Accept-Encoding\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+
+        let mut page = String::new();
+        GzDecoder::new(&body[..]).read_to_string(&mut page).unwrap();
+        assert_eq!(page, hello_page(address));
+    }
+}
+
+#[test]
+fn deflate() {
+    let address = common::start_server(compressing(Mode::Threaded));
+
+    let (head, body) = common::send(address, "GET / HTTP/1.1\r\nAccept-Encoding: gzip;q=0.5, deflate\r\n\r\n");
+    assert!(head.contains("Content-Encoding: deflate\r\n"));
+
+    let mut page = String::new();
+    ZlibDecoder::new(&body[..]).read_to_string(&mut page).unwrap();
+    assert_eq!(page, hello_page(address));
+}
+
+#[test]
+fn identity_when_not_accepted() {
+    let address = common::start_server(compressing(Mode::Threaded));
+
+    let (head, body) = common::send(address, "GET / HTTP/1.1\r\n\r\n");
+    assert!(!head.contains("Content-Encoding"));
+    assert!(head.contains("Vary: Accept-Encoding\r\n"));
+    assert!(String::from_utf8(body).unwrap().contains("<p>Hi from Rust</p>"));
+}
+
+#[test]
+fn small_pages_are_not_compressed() {
+    let address = common::start_server(Config::default());
+
+    let (head, body) = common::send(address, "GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+    assert!(!head.contains("Content-Encoding"));
+    assert!(!head.contains("Vary"));
+    assert_eq!(String::from_utf8(body).unwrap(), hello_page(address));
+}
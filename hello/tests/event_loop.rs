@@ -0,0 +1,141 @@
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hello::handlers::{self, Handlers};
+use hello::server::{Config, Mode, Server, TlsConfig};
+
+mod common;
+
+fn event_loop(workers: usize) -> Config {
+    Config {
+        workers,
+        mode: Mode::EventLoop,
+        ..Config::default()
+    }
+}
+
+#[test]
+fn serves_pages() {
+    let address = common::start_server(event_loop(2));
+
+    let response = common::get(address, "/");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("<h1>Hello!</h1>"));
+
+    let response = common::get(address, "/missing");
+    assert!(response.starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+}
+
+#[test]
+fn sleeping_request_does_not_stall_the_loop() {
+    let address = common::start_server(event_loop(1));
+
+    let sleeper = thread::spawn(move || {
+        let started = Instant::now();
+        let response = common::get(address, "/sleep");
+
+        (response, started.elapsed())
+    });
+
+    thread::sleep(Duration::from_millis(100)); // let the `/sleep` request arrive first
+
+    // with a single thread-per-connection worker this would take the whole 5 seconds
+    let started = Instant::now();
+    for _ in 0..10 {
+        assert!(common::get(address, "/").starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    let (response, elapsed) = sleeper.join().unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(elapsed >= Duration::from_secs(5));
+}
+
+#[test]
+fn multiplexes_many_connections() {
+    let address = common::start_server(event_loop(2));
+
+    // all the connections are open at the same time, each sending its request in two halves
+    let mut streams: Vec<_> = (0..500).map(|_| TcpStream::connect(address).unwrap()).collect();
+
+    for stream in &mut streams {
+        stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    }
+
+    for stream in &mut streams {
+        let response = common::exchange(stream, "Host: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
+
+#[test]
+fn uploads_beyond_the_pool_are_refused() {
+    let address = common::start_server(Config {
+        queue_capacity: 1,
+        handlers: Handlers::new().route("POST", "/body", handlers::echo),
+        ..event_loop(1)
+    });
+
+    // the bodies are held back, so the first upload keeps the only thread for uploads busy and the second one waits in the queue
+    let upload = "POST /body HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\n";
+    let mut uploads: Vec<_> = (0..2)
+        .map(|_| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(upload.as_bytes()).unwrap();
+            thread::sleep(Duration::from_millis(200)); // keep the order of arrival
+
+            stream
+        })
+        .collect();
+
+    // there is no room for a third one, rather than getting a thread of its own it's refused right away
+    let started = Instant::now();
+    let response = common::exchange(&mut TcpStream::connect(address).unwrap(), upload);
+    assert!(response.starts_with("HTTP/1.1 503 SERVICE UNAVAILABLE\r\n"), "{}", response);
+    assert!(response.contains("Retry-After: 1\r\n"));
+    assert!(started.elapsed() < Duration::from_secs(2), "{:?}", started.elapsed());
+
+    // the loop itself keeps serving what it doesn't have to pass on
+    assert!(common::get(address, "/").starts_with("HTTP/1.1 200 OK\r\n"));
+
+    for stream in &mut uploads {
+        let response = common::exchange(stream, "hello");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("{\"bytes\":5}"), "{}", response);
+    }
+}
+
+#[test]
+fn returns_after_limit() {
+    let server = Server::bind(Config {
+        address: String::from("127.0.0.1:0"),
+        ..event_loop(3)
+    })
+    .unwrap();
+    let address = server.local_addr().unwrap();
+
+    let serving = thread::spawn(move || server.serve(Some(4)));
+
+    for _ in 0..4 {
+        assert!(common::get(address, "/").starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    serving.join().unwrap();
+}
+
+#[test]
+fn tls_is_refused() {
+    let result = Server::bind(Config {
+        address: String::from("127.0.0.1:0"),
+        tls: Some(TlsConfig {
+            certificate: "cert.pem".into(),
+            private_key: "key.pem".into(),
+        }),
+        ..event_loop(1)
+    });
+
+    let error = result.err().unwrap().to_string();
+    assert_eq!(error, "TLS is not supported in the event loop mode.");
+}
@@ -0,0 +1,47 @@
+use std::thread;
+use std::time::Duration;
+
+use hello::server::Config;
+
+mod common;
+
+fn value(metrics: &str, series: &str) -> Option<f64> {
+    metrics.lines().find_map(|line| line.strip_prefix(series)?.strip_prefix(' ')?.parse().ok())
+}
+
+#[test]
+fn counts_requests_by_route_and_status() {
+    let address = common::start_server(Config::default());
+
+    common::get(address, "/");
+    common::get(address, "/");
+    common::get(address, "/missing");
+    common::get(address, "/also/missing");
+
+    let response = common::get(address, "/metrics");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n"));
+
+    assert_eq!(value(&response, "hello_requests_total{route=\"/\",status=\"200\"}"), Some(2.0));
+    assert_eq!(value(&response, "hello_requests_total{route=\"other\",status=\"404\"}"), Some(2.0));
+    assert_eq!(value(&response, "hello_request_duration_seconds_count{route=\"/\"}"), Some(2.0));
+    assert_eq!(value(&response, "hello_request_duration_seconds_bucket{route=\"/\",le=\"+Inf\"}"), Some(2.0));
+    assert_eq!(value(&response, "hello_pool_workers"), Some(4.0));
+
+    // the scrape itself is counted once it is answered
+    let response = common::get(address, "/metrics");
+    assert_eq!(value(&response, "hello_requests_total{route=\"/metrics\",status=\"200\"}"), Some(1.0));
+}
+
+#[test]
+fn reports_busy_workers() {
+    let address = common::start_server(Config::default());
+
+    thread::spawn(move || common::get(address, "/sleep"));
+    thread::sleep(Duration::from_millis(200)); // let the `/sleep` request occupy a worker
+
+    // the sleeping request and the scrape itself
+    let response = common::get(address, "/metrics");
+    assert_eq!(value(&response, "hello_pool_busy_workers"), Some(2.0));
+    assert_eq!(value(&response, "hello_pool_queued_jobs"), Some(0.0));
+}
@@ -0,0 +1,89 @@
+use std::time::{Duration, Instant};
+
+use hello::handlers::{self, Handlers};
+use hello::middleware::{BasicAuth, CatchPanic, Chain, Middleware, RequestId};
+use hello::request::Request;
+use hello::response::Response;
+use hello::server::{Config, Mode};
+
+mod common;
+
+// a buggy piece of middleware
+struct PanicOn(&'static str);
+
+impl Middleware for PanicOn {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        if request.path() == self.0 {
+            panic!("Cannot handle {}", self.0);
+        }
+
+        None
+    }
+}
+
+#[test]
+fn panics_become_internal_server_errors() {
+    for mode in [Mode::Threaded, Mode::EventLoop] {
+        let address = common::start_server(Config {
+            workers: 1,
+            mode,
+            middleware: Chain::new().with(CatchPanic).with(PanicOn("/boom")),
+            ..Config::default()
+        });
+
+        let response = common::get(address, "/boom");
+        assert!(response.starts_with("HTTP/1.1 500 INTERNAL SERVER ERROR\r\n"), "{}", response);
+
+        // and the only worker (or event loop) is still alive
+        let response = common::get(address, "/");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
+
+#[test]
+fn default_chain_tags_requests() {
+    let address = common::start_server(Config::default());
+
+    let response = common::exchange(&mut std::net::TcpStream::connect(address).unwrap(), "GET / HTTP/1.1\r\nX-Request-Id: trace-me\r\n\r\n");
+    assert!(response.contains("X-Request-Id: trace-me\r\n"));
+}
+
+#[test]
+fn basic_auth_protects_every_route() {
+    let address = common::start_server(Config {
+        middleware: Chain::new().with(RequestId::new()).with(BasicAuth::new("hello").user("alice", "secret")),
+        ..Config::default()
+    });
+
+    for path in ["/", "/metrics", "/missing"] {
+        let response = common::get(address, path);
+        assert!(response.starts_with("HTTP/1.1 401 UNAUTHORIZED\r\n"), "{}", response);
+        assert!(response.contains("WWW-Authenticate: Basic realm=\"hello\""));
+        assert!(response.contains("X-Request-Id: ")); // the middleware before it still sees the short-circuited response
+    }
+
+    let mut stream = std::net::TcpStream::connect(address).unwrap();
+    let response = common::exchange(&mut stream, "GET / HTTP/1.1\r\nAuthorization: Basic YWxpY2U6c2VjcmV0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+}
+
+#[test]
+fn basic_auth_refuses_uploads_before_reading_them() {
+    for mode in [Mode::Threaded, Mode::EventLoop] {
+        let address = common::start_server(Config {
+            workers: 1,
+            mode,
+            middleware: Chain::new().with(BasicAuth::new("hello").user("alice", "secret")),
+            handlers: Handlers::new().route("POST", "/body", handlers::echo),
+            ..Config::default()
+        });
+
+        // none of the promised body is ever sent, so waiting for it would end in a `408` only after the read timeout
+        let started = Instant::now();
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        let response = common::exchange(&mut stream, "POST /body HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 1000000\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 401 UNAUTHORIZED\r\n"), "{}", response);
+        assert!(started.elapsed() < Duration::from_secs(3), "{:?}", started.elapsed());
+    }
+}
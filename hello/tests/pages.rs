@@ -0,0 +1,51 @@
+use std::env;
+use std::fs;
+
+use hello::server::{Config, Mode};
+use hello::websocket::{self, Endpoints};
+
+mod common;
+
+#[test]
+fn not_found_page_shows_the_path_escaped() {
+    for mode in [Mode::Threaded, Mode::EventLoop] {
+        let address = common::start_server(Config {
+            mode,
+            ..Config::default()
+        });
+
+        let response = common::get(address, "/<script>alert(1)</script>");
+        assert!(response.starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+        assert!(response.contains("<code>GET /&lt;script&gt;alert(1)&lt;/script&gt;</code>"));
+        assert!(!response.contains("<script>"));
+    }
+}
+
+#[test]
+fn hello_page_lists_the_websocket_endpoints() {
+    let address = common::start_server(Config {
+        websockets: Endpoints::new().route("/echo", websocket::echo),
+        ..Config::default()
+    });
+
+    let response = common::get(address, "/");
+    assert!(response.contains("<h1>Hello!</h1>")); // from the page itself
+    assert!(response.contains("<title>Hello!</title>")); // from the included header
+    assert!(response.contains("<li><code>/echo</code></li>"));
+}
+
+#[test]
+fn broken_templates_are_internal_server_errors() {
+    let root = env::temp_dir().join(format!("hello-pages-test-{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("hello.html"), "{% if path %}never closed").unwrap();
+
+    let address = common::start_server(Config {
+        root: root.clone(),
+        ..Config::default()
+    });
+
+    assert!(common::get(address, "/").starts_with("HTTP/1.1 500 INTERNAL SERVER ERROR\r\n"));
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,70 @@
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hello::server::Config;
+
+mod common;
+
+#[test]
+fn slow_requests_make_room_for_fast_ones() {
+    let address = common::start_server(Config {
+        workers: 1,
+        queue_capacity: 2,
+        ..Config::default()
+    });
+
+    let get = move |path: &'static str| {
+        let handle = thread::spawn(move || {
+            let started = Instant::now();
+            let response = common::get(address, path);
+
+            (response, started.elapsed())
+        });
+
+        thread::sleep(Duration::from_millis(200)); // keep the order of arrival
+
+        handle
+    };
+
+    let running = get("/sleep"); // takes the only worker
+    let queued = [get("/sleep"), get("/sleep")]; // fill the queue
+
+    // the queue is full, so the most recent `/sleep` waiting in it gives way to each of these
+    let metrics = get("/metrics");
+    let page = get("/");
+
+    for sleeper in queued {
+        let (response, elapsed) = sleeper.join().unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 503 SERVICE UNAVAILABLE\r\n"), "{}", response);
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed); // right away, not after waiting for the worker
+    }
+
+    for fast in [metrics, page] {
+        let (response, _) = fast.join().unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    }
+
+    assert!(running.join().unwrap().0.starts_with("HTTP/1.1 200 OK\r\n"));
+}
+
+#[test]
+fn queued_connections_are_closed_once_answered() {
+    let address = common::start_server(Config {
+        workers: 1,
+        ..Config::default()
+    });
+
+    let running = thread::spawn(move || common::get(address, "/sleep"));
+    thread::sleep(Duration::from_millis(200)); // takes the only worker
+
+    // the second one waits in the queue; nothing is accepted after it, so nothing but its own job can let go of the connection
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(15))).unwrap(); // fails rather than hangs
+    let response = common::exchange(&mut stream, "GET /sleep HTTP/1.1\r\nHost: localhost\r\n\r\n");
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(running.join().unwrap().starts_with("HTTP/1.1 200 OK\r\n"));
+}
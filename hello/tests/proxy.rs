@@ -0,0 +1,139 @@
+use std::io::prelude::*;
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::time::Duration;
+
+use hello::proxy::{Proxies, Proxy};
+use hello::server::{Config, Mode};
+
+mod common;
+
+// a stand-in upstream answering every request with whatever `respond` makes of its head
+fn upstream<F>(respond: F) -> SocketAddr
+where
+    F: Fn(&str) -> String + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut head = Vec::new();
+            let mut byte = [0];
+
+            while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                head.push(byte[0]);
+            }
+
+            let _ = stream.write_all(respond(&String::from_utf8_lossy(&head)).as_bytes());
+        }
+    });
+
+    address
+}
+
+fn text(status: &str, body: &str) -> String {
+    format!("HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}", status, body.len(), body)
+}
+
+// one that answers with its name
+fn named(name: &'static str) -> SocketAddr {
+    upstream(move |_| text("200 OK", name))
+}
+
+// an address nothing listens on
+fn dead() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+fn proxying(prefix: &str, proxy: Proxy) -> Config {
+    Config {
+        proxies: Proxies::new().route(prefix, proxy),
+        ..Config::default()
+    }
+}
+
+fn body(response: &str) -> &str {
+    response.split_once("\r\n\r\n").unwrap().1
+}
+
+#[test]
+fn forwards_with_rewritten_headers() {
+    // the upstream sends the request it got back
+    let echo = upstream(|head| text("201 Created", head));
+
+    for mode in [Mode::Threaded, Mode::EventLoop] {
+        let address = common::start_server(Config {
+            mode,
+            ..proxying("/api", Proxy::new([echo.to_string()]))
+        });
+
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        let response = common::exchange(&mut stream, "GET /api/users?page=2 HTTP/1.1\r\nHost: example.com\r\nX-Forwarded-For: 10.0.0.1\r\nConnection: keep-alive\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 201 CREATED\r\n"), "{}", response);
+        assert!(response.contains("X-Request-Id: ")); // the middleware applies as well
+
+        let forwarded = body(&response);
+        assert!(forwarded.starts_with("GET /api/users?page=2 HTTP/1.1\r\n"));
+        assert!(forwarded.contains("Host: example.com\r\n"));
+        assert!(forwarded.contains("X-Forwarded-For: 10.0.0.1, 127.0.0.1\r\n"));
+        assert!(forwarded.contains("Connection: close\r\n"));
+        assert!(!forwarded.contains("keep-alive"));
+
+        // the other paths are still served by the server itself
+        assert!(common::get(address, "/").contains("<h1>Hello!</h1>"));
+    }
+}
+
+#[test]
+fn round_robin() {
+    let address = common::start_server(proxying("/", Proxy::new([named("a").to_string(), named("b").to_string()])));
+
+    let names: Vec<String> = (0..4).map(|_| body(&common::get(address, "/")).to_string()).collect();
+    assert_eq!(names, ["a", "b", "a", "b"]);
+}
+
+#[test]
+fn retries_on_another_upstream() {
+    let address = common::start_server(proxying("/", Proxy::new([dead().to_string(), named("alive").to_string()])));
+
+    for _ in 0..4 {
+        let response = common::get(address, "/");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(body(&response), "alive");
+    }
+}
+
+#[test]
+fn bad_gateway_and_gateway_timeout() {
+    let address = common::start_server(proxying("/", Proxy::new([dead().to_string(), dead().to_string()])));
+    assert!(common::get(address, "/").starts_with("HTTP/1.1 502 BAD GATEWAY\r\n"));
+
+    // accepts, then never answers
+    let silent = upstream(|_| {
+        thread::sleep(Duration::from_secs(2));
+        String::new()
+    });
+
+    let address = common::start_server(proxying("/", Proxy::new([silent.to_string()]).timeout(Duration::from_millis(200)).retries(0)));
+    assert!(common::get(address, "/").starts_with("HTTP/1.1 504 GATEWAY TIMEOUT\r\n"));
+}
+
+#[test]
+fn skips_unhealthy_upstreams() {
+    let sick = upstream(|head| match head.starts_with("GET /health ") {
+        true => text("503 Service Unavailable", "not now"),
+        false => text("200 OK", "sick"),
+    });
+    let healthy = named("healthy");
+
+    let proxy = Proxy::new([sick.to_string(), healthy.to_string()]).health_check("/health", Duration::from_millis(50));
+    let address = common::start_server(proxying("/", proxy));
+
+    thread::sleep(Duration::from_millis(200)); // let the first round of the health checks run
+
+    for _ in 0..4 {
+        assert_eq!(body(&common::get(address, "/")), "healthy");
+    }
+}
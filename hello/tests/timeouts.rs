@@ -0,0 +1,118 @@
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hello::server::{Config, Mode};
+
+mod common;
+
+const MODES: [Mode; 2] = [Mode::Threaded, Mode::EventLoop];
+
+fn impatient(mode: Mode) -> Config {
+    Config {
+        workers: 1,
+        mode,
+        read_timeout: Duration::from_millis(300),
+        header_timeout: Duration::from_millis(800),
+        ..Config::default()
+    }
+}
+
+fn read_response(stream: &mut TcpStream) -> String {
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    response
+}
+
+#[test]
+fn silent_client_times_out() {
+    for mode in MODES {
+        let address = common::start_server(impatient(mode));
+
+        let started = Instant::now();
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n").unwrap(); // and then nothing
+
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 408 REQUEST TIMEOUT\r\n"), "{}", response);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // the only worker is free again
+        assert!(common::get(address, "/").starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
+
+#[test]
+fn trickling_client_hits_header_deadline() {
+    for mode in MODES {
+        let address = common::start_server(impatient(mode));
+
+        let started = Instant::now();
+        let mut stream = TcpStream::connect(address).unwrap();
+
+        // a slowloris: every byte comes well within the read timeout, but the request never ends
+        let request = b"GET / HTTP/1.1\r\nX-Padding: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        for byte in request {
+            if stream.write_all(&[*byte]).is_err() {
+                break; // the server has given up on us
+            }
+
+            thread::sleep(Duration::from_millis(50));
+
+            if started.elapsed() > Duration::from_secs(1) {
+                break;
+            }
+        }
+
+        let response = read_response(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 408 REQUEST TIMEOUT\r\n"), "{}", response);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
+
+#[test]
+fn oversized_header_is_refused() {
+    for mode in MODES {
+        let address = common::start_server(Config {
+            max_header_size: 256,
+            ..impatient(mode)
+        });
+
+        let request = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(1000));
+        let response = common::exchange(&mut TcpStream::connect(address).unwrap(), &request);
+        assert!(response.starts_with("HTTP/1.1 431 REQUEST HEADER FIELDS TOO LARGE\r\n"), "{}", response);
+
+        let response = common::get(address, "/");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
+
+#[test]
+fn connections_per_ip_are_limited() {
+    for mode in MODES {
+        let address = common::start_server(Config {
+            workers: 4,
+            connections_per_ip: Some(2),
+            read_timeout: Duration::from_secs(5),
+            header_timeout: Duration::from_secs(5),
+            ..impatient(mode)
+        });
+
+        // two idle connections take up the whole allowance of 127.0.0.1
+        let first = TcpStream::connect(address).unwrap();
+        let second = TcpStream::connect(address).unwrap();
+        thread::sleep(Duration::from_millis(200)); // let the server accept them
+
+        let response = common::get(address, "/");
+        assert!(response.starts_with("HTTP/1.1 429 TOO MANY REQUESTS\r\n"), "{}", response);
+
+        drop(first);
+        drop(second);
+        thread::sleep(Duration::from_millis(200)); // let the server notice
+
+        let response = common::get(address, "/");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    }
+}
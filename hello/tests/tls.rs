@@ -0,0 +1,116 @@
+use std::env;
+use std::fs;
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use hello::server::{Config, Server, TlsConfig};
+
+mod common;
+
+// a freshly generated self-signed certificate for `localhost` written to a temporary directory
+struct Certificate {
+    directory: PathBuf,
+    der: CertificateDer<'static>,
+}
+
+impl Certificate {
+    fn generate(name: &str) -> Certificate {
+        let rcgen::CertifiedKey { cert, key_pair } = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+
+        let directory = env::temp_dir().join(format!("hello-tls-test-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("cert.pem"), cert.pem()).unwrap();
+        fs::write(directory.join("key.pem"), key_pair.serialize_pem()).unwrap();
+
+        Certificate {
+            directory,
+            der: cert.der().clone(),
+        }
+    }
+
+    fn tls_config(&self) -> TlsConfig {
+        TlsConfig {
+            certificate: self.directory.join("cert.pem"),
+            private_key: self.directory.join("key.pem"),
+        }
+    }
+}
+
+impl Drop for Certificate {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.directory);
+    }
+}
+
+fn https_get(address: SocketAddr, certificate: &Certificate, path: &str) -> String {
+    let mut roots = RootCertStore::empty();
+    roots.add(certificate.der.clone()).unwrap(); // trust our self-signed certificate and nothing else
+
+    let config = ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+    let connection = ClientConnection::new(Arc::new(config), ServerName::try_from("localhost").unwrap()).unwrap();
+    let mut stream = StreamOwned::new(connection, TcpStream::connect(address).unwrap());
+
+    common::exchange(&mut stream, &format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path))
+}
+
+#[test]
+fn serves_pages_over_https() {
+    let certificate = Certificate::generate("pages");
+    let address = common::start_server(Config {
+        tls: Some(certificate.tls_config()),
+        ..Config::default()
+    });
+
+    let response = https_get(address, &certificate, "/");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("<h1>Hello!</h1>"));
+
+    let response = https_get(address, &certificate, "/missing");
+    assert!(response.starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+}
+
+#[test]
+fn plain_http_still_works_without_tls() {
+    let address = common::start_server(Config::default());
+
+    let response = common::get(address, "/");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+}
+
+#[test]
+fn untrusted_certificate_is_refused_by_client() {
+    let served = Certificate::generate("served");
+    let other = Certificate::generate("other");
+    let address = common::start_server(Config {
+        tls: Some(served.tls_config()),
+        ..Config::default()
+    });
+
+    let mut roots = RootCertStore::empty();
+    roots.add(other.der.clone()).unwrap();
+
+    let config = ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+    let connection = ClientConnection::new(Arc::new(config), ServerName::try_from("localhost").unwrap()).unwrap();
+    let mut stream = StreamOwned::new(connection, TcpStream::connect(address).unwrap());
+
+    assert!(std::io::Write::write_all(&mut stream, b"GET / HTTP/1.1\r\n\r\n").and_then(|_| std::io::Write::flush(&mut stream)).is_err());
+}
+
+#[test]
+fn missing_certificate_is_reported() {
+    let result = Server::bind(Config {
+        address: String::from("127.0.0.1:0"),
+        tls: Some(TlsConfig {
+            certificate: PathBuf::from("does/not/exist.pem"),
+            private_key: PathBuf::from("does/not/exist.key"),
+        }),
+        ..Config::default()
+    });
+
+    let error = result.err().unwrap().to_string();
+    assert!(error.contains("Cannot load the certificate"), "{}", error);
+}
@@ -0,0 +1,90 @@
+use std::env;
+use std::fs;
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+
+use hello::handlers::Handlers;
+use hello::response::Response;
+use hello::server::{Config, Mode};
+use hello::virtual_hosts::{Site, VirtualHosts};
+use hello::websocket::{self, Endpoints};
+
+mod common;
+
+// a document root with pages of its own
+fn site_root(name: &str) -> PathBuf {
+    let root = env::temp_dir().join(format!("hello-site-{}-{}", name, std::process::id()));
+
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("hello.html"), format!("<h1>{}</h1>\n", name)).unwrap();
+    fs::write(root.join("404.html"), format!("<h1>{}: nothing at {{{{ path }}}}</h1>\n", name)).unwrap();
+
+    root
+}
+
+fn get(address: SocketAddr, host: &str, path: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+
+    common::exchange(&mut stream, &format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, host))
+}
+
+#[test]
+fn serves_sites_by_host() {
+    let (alpha, beta) = (site_root("alpha"), site_root("beta"));
+
+    for mode in [Mode::Threaded, Mode::EventLoop] {
+        let address = common::start_server(Config {
+            mode,
+            virtual_hosts: VirtualHosts::new()
+                .site("alpha.test", Site::new(&alpha))
+                .site("*.beta.test", Site::new(&beta).handlers(Handlers::new().route("GET", "/only-beta", |_| Response::new(200).body("beta's own"))))
+                .strict(false),
+            websockets: Endpoints::new().route("/echo", websocket::echo),
+            ..Config::default()
+        });
+
+        assert!(get(address, "alpha.test", "/").contains("<h1>alpha</h1>"));
+        assert!(get(address, "ALPHA.test:7878", "/").contains("<h1>alpha</h1>"));
+        assert!(get(address, "www.beta.test", "/").contains("<h1>beta</h1>"));
+        assert!(get(address, "alpha.test", "/missing").contains("<h1>alpha: nothing at /missing</h1>"));
+
+        // the routers are per site
+        assert!(get(address, "www.beta.test", "/only-beta").ends_with("\r\n\r\nbeta's own"));
+        assert!(get(address, "alpha.test", "/only-beta").starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+
+        // the built-in routes are everywhere
+        assert!(get(address, "alpha.test", "/metrics").contains("# TYPE"));
+
+        // everything else goes to the default site
+        assert!(get(address, "unknown.test", "/").contains("<h1>Hello!</h1>"));
+        assert!(get(address, "beta.test", "/").contains("<h1>Hello!</h1>"));
+        assert!(common::get(address, "/echo").starts_with("HTTP/1.1 426 UPGRADE REQUIRED\r\n")); // the default site's endpoint
+    }
+
+    fs::remove_dir_all(alpha).unwrap();
+    fs::remove_dir_all(beta).unwrap();
+}
+
+#[test]
+fn strict_mode_refuses_unknown_hosts() {
+    let gamma = site_root("gamma");
+
+    for mode in [Mode::Threaded, Mode::EventLoop] {
+        let address = common::start_server(Config {
+            mode,
+            virtual_hosts: VirtualHosts::new().site("gamma.test", Site::new(&gamma)).strict(true),
+            ..Config::default()
+        });
+
+        assert!(get(address, "gamma.test", "/").contains("<h1>gamma</h1>"));
+
+        let response = get(address, "unknown.test", "/");
+        assert!(response.starts_with("HTTP/1.1 421 MISDIRECTED REQUEST\r\n"), "{}", response);
+        assert!(response.contains("X-Request-Id: ")); // answered through the middleware
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        assert!(common::exchange(&mut stream, "GET / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 421 MISDIRECTED REQUEST\r\n"));
+    }
+
+    fs::remove_dir_all(gamma).unwrap();
+}
@@ -0,0 +1,168 @@
+use std::io::{self, prelude::*};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use hello::middleware::{BasicAuth, Chain};
+use hello::server::{Config, Mode};
+use hello::websocket::{self, Endpoints, Message, Role, WebSocket};
+
+mod common;
+
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+// there are no endpoints by default
+fn echo() -> Endpoints {
+    Endpoints::new().route("/echo", websocket::echo)
+}
+
+// sends the opening handshake and returns the head of the response, read byte by byte so that nothing after it is consumed
+fn handshake(stream: &mut TcpStream, path: &str, headers: &str) -> String {
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n{}\r\n",
+        path, KEY, headers
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut head = Vec::new();
+    let mut byte = [0];
+
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+
+    String::from_utf8(head).unwrap()
+}
+
+fn connect(address: SocketAddr, path: &str) -> WebSocket<TcpStream> {
+    let mut stream = TcpStream::connect(address).unwrap();
+
+    let head = handshake(&mut stream, path, "");
+    assert!(head.starts_with("HTTP/1.1 101 SWITCHING PROTOCOLS\r\n"), "{}", head);
+    assert!(head.contains(&format!("Sec-WebSocket-Accept: {}\r\n", websocket::accept_key(KEY))));
+
+    WebSocket::new(stream, Role::Client)
+}
+
+#[test]
+fn echoes_messages() {
+    for mode in [Mode::Threaded, Mode::EventLoop] {
+        let address = common::start_server(Config {
+            mode,
+            websockets: echo(),
+            ..Config::default()
+        });
+        let mut socket = connect(address, "/echo");
+
+        let messages = [
+            Message::Text(String::from("Hello")),
+            Message::Binary(vec![0, 1, 2, 255]),
+            Message::Text("a long one ".repeat(10_000)),
+        ];
+
+        for message in messages {
+            socket.send(&message).unwrap();
+            assert_eq!(socket.receive().unwrap(), message);
+        }
+
+        socket.send(&Message::Ping(b"anyone?".to_vec())).unwrap();
+        assert_eq!(socket.receive().unwrap(), Message::Pong(b"anyone?".to_vec()));
+
+        socket.close(1000, "done").unwrap();
+        assert!(socket.is_closed());
+
+        assert_eq!(socket.receive().unwrap_err().kind(), io::ErrorKind::NotConnected);
+
+        // the server closes the connection once the closing handshake is over
+        assert_eq!(socket.into_inner().read(&mut [0]).unwrap(), 0);
+    }
+}
+
+#[test]
+fn runs_custom_endpoints() {
+    let address = common::start_server(Config {
+        websockets: Endpoints::new().route("/greet", |socket, request| {
+            let greeting = format!("Hello from {}", request.path());
+            socket.send(&Message::Text(greeting)).unwrap();
+            // returning without closing, the server does that
+        }),
+        ..Config::default()
+    });
+
+    let mut socket = connect(address, "/greet");
+
+    assert_eq!(socket.receive().unwrap(), Message::Text(String::from("Hello from /greet")));
+    assert_eq!(socket.receive().unwrap(), Message::Close(Some((1000, String::new()))));
+
+    // there are no other endpoints
+    assert!(common::get(address, "/echo").starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+}
+
+#[test]
+fn refuses_plain_requests_to_endpoints() {
+    let address = common::start_server(Config {
+        websockets: echo(),
+        ..Config::default()
+    });
+
+    let response = common::get(address, "/echo");
+    assert!(response.starts_with("HTTP/1.1 426 UPGRADE REQUIRED\r\n"));
+    assert!(response.contains("Upgrade: websocket\r\n"));
+}
+
+#[test]
+fn middleware_guards_the_handshake() {
+    let address = common::start_server(Config {
+        middleware: Chain::new().with(BasicAuth::new("hello").user("alice", "secret")),
+        websockets: echo(),
+        ..Config::default()
+    });
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    assert!(handshake(&mut stream, "/echo", "").starts_with("HTTP/1.1 401 UNAUTHORIZED\r\n"));
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    let head = handshake(&mut stream, "/echo", "Authorization: Basic YWxpY2U6c2VjcmV0\r\n");
+    assert!(head.starts_with("HTTP/1.1 101 SWITCHING PROTOCOLS\r\n"));
+}
+
+#[test]
+fn fails_unmasked_client_frames() {
+    let address = common::start_server(Config {
+        websockets: echo(),
+        ..Config::default()
+    });
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    handshake(&mut stream, "/echo", "");
+
+    // written by hand, a client `WebSocket` would mask it
+    stream.write_all(&[0x81, 0x02, b'h', b'i']).unwrap();
+    let mut socket = WebSocket::new(stream, Role::Client);
+
+    match socket.receive().unwrap() {
+        Message::Close(Some((1002, _))) => (),
+        message => panic!("Expected a protocol error, got {:?}", message),
+    }
+}
+
+#[test]
+fn idle_sessions_are_closed() {
+    for mode in [Mode::Threaded, Mode::EventLoop] {
+        let address = common::start_server(Config {
+            mode,
+            workers: 1,
+            websockets: echo(),
+            websocket_idle_timeout: Duration::from_millis(200),
+            ..Config::default()
+        });
+
+        let mut socket = connect(address, "/echo");
+        socket.send(&Message::Text(String::from("Hello"))).unwrap();
+        assert_eq!(socket.receive().unwrap(), Message::Text(String::from("Hello")));
+
+        // then nothing, which mustn't keep the only worker busy for good
+        assert_eq!(socket.receive().unwrap(), Message::Close(Some((1001, String::from("Idle for too long")))));
+        assert!(common::get(address, "/").starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}